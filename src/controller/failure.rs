@@ -0,0 +1,117 @@
+use super::{Command, preset_context};
+use super::super::{SpotifyError, PlayerState, PlayerAction, ConnectDeviceList};
+use super::super::devices::DeviceSettings;
+
+// How long to show a failure that nothing has fixed.
+pub const FAILURE_TIMEOUT: i64 = 120;
+
+/// A command that Spotify didn't carry out, kept to tell the user why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub command: Command,
+    pub error: SpotifyError,
+    /// When it failed, in seconds since the epoch
+    pub time: i64,
+}
+
+impl Failure {
+    pub fn new(command: Command, error: SpotifyError, time: i64) -> Failure {
+        Failure { command: command, error: error, time: time }
+    }
+
+    /// Short explanation of what went wrong, and how to fix it.
+    pub fn message(&self) -> String {
+        match self.error {
+            SpotifyError::Connection(_) => "Can't reach Spotify \u{2014} check your connection".to_string(),
+            SpotifyError::Rejected { status, ref reason, .. } => {
+                match (status, reason.as_ref().map(|r| r.as_str())) {
+                    (_, Some("NO_ACTIVE_DEVICE")) => "No active device \u{2014} pick one below".to_string(),
+                    (_, Some("PREMIUM_REQUIRED")) => "Spotify Premium is required to control playback".to_string(),
+                    (404, _) if self.is_player_command() => "No active device \u{2014} pick one below".to_string(),
+                    (404, _) => format!("Spotify couldn't {} \u{2014} it may have been deleted", self.action()),
+                    (401, _) => "Spotify login expired \u{2014} reconfigure Connectr".to_string(),
+                    (403, _) => "Spotify doesn't allow that right now".to_string(),
+                    (429, _) => "Spotify is busy \u{2014} try again in a moment".to_string(),
+                    _ => format!("Spotify couldn't {}", self.action()),
+                }
+            },
+            SpotifyError::Disallowed(PlayerAction::Volume) => {
+                "This device doesn't allow remote control".to_string()
+            },
+            SpotifyError::Disallowed(_) => format!("Spotify can't {} right now", self.action()),
+            SpotifyError::UnknownDevice(ref name) => format!("Can't find device {} \u{2014} is it on?", name),
+            SpotifyError::NotYourPlaylist(_) => "Tracks can only be removed from your own playlists".to_string(),
+            SpotifyError::NothingToSave => "Nothing to save as a preset \u{2014} play a playlist or album".to_string(),
+            SpotifyError::NoPresets(None) => "No presets to choose from".to_string(),
+            SpotifyError::NoPresets(Some(ref group)) => format!("No presets in {} to choose from", group),
+        }
+    }
+
+    /// Whether the command controls the player, so a 404 means there's no
+    /// device to play on rather than that something is gone.
+    fn is_player_command(&self) -> bool {
+        match self.command {
+            Command::PlayPause | Command::SkipNext | Command::SkipPrev |
+            Command::Volume(_) | Command::Shuffle(_) | Command::SelectDevice(_) |
+            Command::Preset(_) | Command::Surprise(_) | Command::Resume(..) => true,
+            _ => false,
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self.command {
+            Command::SelectDevice(_) => "switch devices",
+            Command::PlayPause => "play or pause",
+            Command::SkipNext => "skip to the next track",
+            Command::SkipPrev => "skip to the previous track",
+            Command::Volume(_) => "change the volume",
+            Command::Shuffle(_) => "change shuffle",
+            Command::Preset(_) | Command::Surprise(_) => "play the preset",
+            Command::Resume(..) => "resume playback",
+            Command::SaveTrack => "save the track",
+            Command::UndoQuickSave => "undo the quick-save",
+            Command::RemoveTrack => "remove the track",
+            Command::SavePreset => "look up the preset's name",
+            _ => "do that",
+        }
+    }
+
+    /// Whether fresh state from Spotify shows that the problem went away.
+    ///
+    /// `devices` is the list as Spotify reported it, before `settings`
+    /// arranged it, and `polled` is false if Spotify didn't answer the poll
+    /// at all.
+    pub fn resolved(&self, player: Option<&PlayerState>, devices: Option<&ConnectDeviceList>,
+                    settings: &DeviceSettings, polled: bool, now: i64) -> bool {
+        if now > self.time + FAILURE_TIMEOUT {
+            return true;
+        }
+        let active = devices.map(|list| list.devices.iter().any(|d| d.is_active)).unwrap_or(false) ||
+            player.is_some();
+        match self.error {
+            SpotifyError::Connection(_) => polled,
+            SpotifyError::Disallowed(action) => match player {
+                Some(player) => player.allows(action),
+                None => false,
+            },
+            SpotifyError::Rejected { status, ref reason, .. } => {
+                match (status, reason.as_ref().map(|r| r.as_str())) {
+                    (_, Some("NO_ACTIVE_DEVICE")) => active,
+                    (404, _) if self.is_player_command() => active,
+                    _ => false,
+                }
+            },
+            SpotifyError::UnknownDevice(ref name) => match devices {
+                Some(list) => settings.find(list, name).is_some(),
+                None => false,
+            },
+            // Nothing to fix, so it goes away with the context
+            SpotifyError::NotYourPlaylist(ref context) => {
+                player.and_then(|p| p.context.as_ref()).map(|c| &c.uri) != Some(context)
+            },
+            SpotifyError::NothingToSave => player.and_then(preset_context).is_some(),
+            // Only changing connectr.ini fixes it, so it stays until it times out
+            SpotifyError::NoPresets(_) => false,
+        }
+    }
+}
@@ -0,0 +1,893 @@
+// Application logic shared by every Connectr frontend.  Frontends draw the
+// state published by the Spotify thread, and send it `CommandMessage`s.
+
+#[cfg(test)]
+mod test;
+
+mod optimistic;
+pub use self::optimistic::{Expectation, OptimisticState, OPTIMISTIC_GRACE};
+mod failure;
+pub use self::failure::{Failure, FAILURE_TIMEOUT};
+mod resume;
+pub use self::resume::{ResumeHistory, ResumePoint, ContextPosition};
+pub use self::resume::{RESUME_MIN_DURATION_MS, RESUME_FINISHED_MS, RESUME_MAX_ENTRIES};
+
+extern crate time;
+
+use std::thread;
+use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::channel;
+use std::sync::mpsc::{Sender, Receiver};
+
+use super::reconfigure;
+use super::import;
+use super::devices::{DeviceEvent, DeviceEventKind, device_events};
+use super::preset::Preset;
+use super::quicksave::{QuickSave, SavedTrack};
+use super::uri::{SpotifyUri, SpotifyUriKind};
+use super::{SpotifyConnectr, SpotifyResponse, SpotifyError, PlayerState, PlayContext, ConnectDeviceList};
+use super::{DeviceId, PlayerAction, ConnectPlaybackItem, check_response};
+
+// How often to refresh Spotify state (if nothing triggers a refresh earlier).
+pub const REFRESH_PERIOD: i64 = 30;
+
+pub enum SpotifyThreadCommand {
+    Update,
+    InvalidSettings,
+    ConfigActive,
+    ConfigInactive,
+    /// The thread saved its state and stopped, so the frontend should exit
+    Quit,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum RefreshTime {
+    Now,   // immediately
+    Soon,  // after ~1 sec
+    Later, // don't change whatever the current schedule is
+    Redraw, // instantly, with stale data
+}
+
+/// Something for the Spotify thread to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    SelectDevice(DeviceId),
+    PlayPause,
+    SkipNext,
+    SkipPrev,
+    Volume(u32),
+    Shuffle(bool),
+    Preset(Preset),
+    /// Play a random preset, from one group or from all of them
+    Surprise(Option<String>),
+    /// Play the item with this URI from a position in milliseconds
+    Resume(String, u64),
+    Redraw,
+    Reconfigure,
+    SaveTrack,
+    /// Take the track the last quick-save added back out
+    UndoQuickSave,
+    /// Remove the playing track from the user's playlist it plays from
+    RemoveTrack,
+    /// Add what's playing to the presets
+    SavePreset,
+    /// Pick presets from the user's library in a web browser
+    ImportPresets,
+    EditAlarms,
+    /// Save state and stop the Spotify thread
+    Quit,
+}
+
+pub type CommandResult = Result<(), SpotifyError>;
+
+/// A command sent to the Spotify thread, and where to report how it went.
+pub struct CommandMessage {
+    pub command: Command,
+    pub reply: Option<Sender<CommandResult>>,
+}
+
+impl CommandMessage {
+    /// A command nobody waits on.
+    pub fn new(command: Command) -> CommandMessage {
+        CommandMessage { command: command, reply: None }
+    }
+    /// A command, and the channel that receives its result once it has run.
+    pub fn with_reply(command: Command) -> (CommandMessage, Receiver<CommandResult>) {
+        let (tx, rx) = channel::<CommandResult>();
+        (CommandMessage { command: command, reply: Some(tx) }, rx)
+    }
+    fn reply(&self, result: CommandResult) {
+        if let Some(ref tx) = self.reply {
+            let _ = tx.send(result);
+        }
+    }
+}
+
+/// The player action a command needs Spotify to allow, if any.
+pub fn player_action(cmd: &Command, player_state: &PlayerState) -> Option<PlayerAction> {
+    match *cmd {
+        Command::SelectDevice(_) => Some(PlayerAction::TransferPlayback),
+        Command::PlayPause => match player_state.is_playing {
+            true => Some(PlayerAction::Pause),
+            false => Some(PlayerAction::Resume),
+        },
+        Command::SkipNext => Some(PlayerAction::SkipNext),
+        Command::SkipPrev => Some(PlayerAction::SkipPrev),
+        Command::Volume(_) => Some(PlayerAction::Volume),
+        Command::Shuffle(_) => Some(PlayerAction::ToggleShuffle),
+        Command::Preset(_) | Command::Surprise(_) | Command::Resume(..) => {
+            Some(PlayerAction::InterruptPlayback)
+        },
+        _ => None,
+    }
+}
+
+/// Execute a command from the UI.  Returns when Spotify state should be
+/// refreshed to show the result, and whether Spotify accepted the command.
+pub fn handle_command(player_state: Option<&PlayerState>,
+                      spotify: &mut SpotifyConnectr,
+                      cmd: &Command) -> (RefreshTime, CommandResult) {
+    if let Some(player_state) = player_state {
+        if let Some(action) = player_action(cmd, player_state) {
+            if !player_state.allows(action) {
+                // Don't bother asking, Spotify already said no.
+                info!("Skipped disallowed action: {:?}", cmd);
+                return (RefreshTime::Later, Err(SpotifyError::Disallowed(action)));
+            }
+        }
+    }
+    info!("Executed action: {:?}", cmd);
+    let mut refresh = RefreshTime::Now;
+    let mut result = Ok(());
+    match *cmd {
+        Command::SelectDevice(ref device) => {
+            result = require(spotify.transfer(device.clone(), true));
+        },
+        Command::PlayPause => {
+            if let Some(player_state) = player_state {
+                result = match player_state.is_playing {
+                    true => require(spotify.pause()),
+                    false => require(spotify.play(None)),
+                };
+            }
+            else {
+                // Nothing is playing, so start on the default device
+                result = idle_default_device(spotify, player_state).and_then(|device| match device {
+                    Some(id) => require(spotify.transfer(id, true)),
+                    None => Ok(()),
+                });
+            }
+        },
+        Command::Preset(ref preset) => {
+            result = play_preset(spotify, &on_default_device(spotify, player_state, preset));
+        }
+        Command::Surprise(ref group) => {
+            // The Spotify thread picks the preset and sends it as a
+            // Command::Preset, so this one had nothing to choose from.
+            warn!("No preset to choose from.");
+            result = Err(SpotifyError::NoPresets(group.clone()));
+        }
+        Command::Resume(ref uri, position_ms) => {
+            let ctx = PlayContext::new()
+                .uri(uri)
+                .build();
+            result = idle_default_device(spotify, player_state).map(|device| spotify.set_target_device(device));
+            if result.is_ok() {
+                result = require(spotify.play(Some(&ctx)));
+            }
+            if result.is_ok() {
+                result = require(spotify.seek(position_ms as u32));
+            }
+            spotify.set_target_device(None);
+        }
+        Command::SkipNext => {
+            result = require(spotify.next());
+        }
+        Command::SkipPrev => {
+            result = require(spotify.previous());
+        }
+        Command::Volume(vol) => {
+            result = require(spotify.volume(vol));
+        }
+        Command::Shuffle(shuffle) => {
+            result = require(spotify.shuffle(shuffle));
+        }
+        Command::Redraw => {
+            refresh = RefreshTime::Redraw;
+        }
+        Command::SaveTrack => {
+            let now = time::now();
+            let minute = (now.tm_hour * 60 + now.tm_min) as u32;
+            if let Some(save) = player_state.and_then(|s| QuickSave::from_player(s, minute)) {
+                result = spotify.quick_save(&save).map(|_| ());
+            }
+        }
+        Command::UndoQuickSave => {
+            result = spotify.undo_quick_save().map(|undone| {
+                if undone.is_none() {
+                    info!("No quick-save to undo.");
+                }
+            });
+        }
+        Command::RemoveTrack => {
+            if let Some(player_state) = player_state {
+                result = remove_playing_track(spotify, player_state);
+            }
+        }
+        Command::SavePreset => {
+            result = match player_state.and_then(preset_context) {
+                Some(uri) => spotify.request_context_name(&uri).map(|name| {
+                    spotify.add_preset(Preset::new(&name, &uri.to_string()));
+                }),
+                None => Err(SpotifyError::NothingToSave),
+            };
+            refresh = RefreshTime::Redraw;
+        }
+        Command::ImportPresets => {}
+        Command::Reconfigure => {}
+        Command::EditAlarms => {}
+        Command::Quit => {}
+    }
+    (refresh, result)
+}
+
+/// Remove the playing track from the playlist it's playing from, then skip
+/// to the next track.  Refuses anything but the user's own playlists.
+///
+/// Spotify doesn't say where in the playlist the playing track is, so every
+/// copy of it is removed, not just the one that's playing.
+pub fn remove_playing_track(spotify: &mut SpotifyConnectr, player_state: &PlayerState) -> CommandResult {
+    let context = player_state.context.as_ref().map(|c| c.uri.clone()).unwrap_or_default();
+    let playlist = match context.parse::<SpotifyUri>() {
+        Ok(ref uri) if uri.kind() == SpotifyUriKind::Playlist => uri.clone(),
+        _ => return Err(SpotifyError::NotYourPlaylist(context)),
+    };
+    let track = match player_state.item {
+        Some(ref item) => item.uri().to_string(),
+        None => return Ok(()),
+    };
+    if spotify.request_playlist_owner(&playlist)? != spotify.request_user_id()? {
+        return Err(SpotifyError::NotYourPlaylist(context));
+    }
+    info!("Removing {} from {}", track, playlist);
+    require(spotify.remove_track(&track, &playlist, None, None))?;
+    match player_state.allows(PlayerAction::SkipNext) {
+        true => require(spotify.next()),
+        false => Ok(()),
+    }
+}
+
+/// The context that decides where quick-save puts the current item.
+/// Episodes played on their own count as playing from their show.
+pub fn quick_save_context(player_state: &PlayerState) -> Option<String> {
+    match (player_state.context.as_ref(), player_state.item.as_ref()) {
+        (Some(ctx), _) => Some(ctx.uri.clone()),
+        (None, Some(&ConnectPlaybackItem::Episode(ref episode))) => Some(episode.show.uri.clone()),
+        _ => None,
+    }
+}
+
+/// The context that "Save as Preset" saves, if what's playing has one.
+pub fn preset_context(player_state: &PlayerState) -> Option<SpotifyUri> {
+    quick_save_context(player_state)
+        .and_then(|c| c.parse::<SpotifyUri>().ok())
+        .filter(|uri| uri.is_context())
+}
+
+pub fn refresh_time(player_state: Option<&PlayerState>, now: i64) -> i64 {
+    let refresh_offset = match player_state {
+        Some(ref state) => {
+            match state.is_playing {
+                true => {
+                    let duration_ms = match state.item {
+                        Some(ref item) => item.duration_ms(),
+                        _ => 0,
+                    };
+                    let track_end = match state.progress_ms {
+                        Some(prog) => {
+                            if prog < duration_ms {
+                                duration_ms - prog
+                            }
+                            else {
+                                0
+                            }
+                        },
+                        None => duration_ms,
+                    } as i64;
+                    // Refresh 1 second after track ends
+                    track_end/1000 + 1
+                },
+                false => REFRESH_PERIOD,
+            }
+        }
+        None => REFRESH_PERIOD,
+    };
+    let refresh_offset = ::std::cmp::min(REFRESH_PERIOD, refresh_offset) as i64;
+    info!("State refresh in {} seconds.", refresh_offset);
+    now + refresh_offset
+}
+
+pub fn scrobble(spotify: &mut SpotifyConnectr,
+               state: Option<&PlayerState>,
+               played_ms: u64,
+               done: bool) {
+    if let Some(state) = flatten_player_state(state) {
+        if state.is_episode {
+            // Last.fm is for music
+            return;
+        }
+        let len = state.duration_ms;
+        let artist = state.artist.clone();
+        let track = state.track.clone();
+        let album = state.album.clone();
+        let devtype = state.device_type.clone();
+        if done {
+            // Scrobbling API rules:
+            //  - Track longer than 30 seconds
+            //  - Track played at least half its duration OR 4 minutes
+            if len > 30000 &&
+                (played_ms > 4*60000 || played_ms >= len / 2)
+            {
+                spotify.scrobble(artist, track, album, devtype);
+            }
+        }
+        else {
+            spotify.scrobbler_now_playing(artist, track, album, devtype);
+        }
+    }
+}
+
+/// The parts of a `PlayerState` that matter for scrobbling.
+pub struct FlatPlayState {
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    pub device_type: String,
+    pub uri: String,
+    pub progress_ms: u64,
+    pub duration_ms: u64,
+    pub is_playing: bool,
+    pub is_episode: bool,
+}
+
+pub fn flatten_player_state(state: Option<&PlayerState>) -> Option<FlatPlayState> {
+    match state {
+        Some(state) => {
+            match state.item {
+                Some(ref item) => {
+                    Some(FlatPlayState {
+                        artist: item.artist().unwrap_or("").to_string(),
+                        track: item.name().to_string(),
+                        album: item.collection().to_string(),
+                        device_type: state.device.device_type.clone(),
+                        uri: item.uri().to_string(),
+                        progress_ms: state.progress_ms.unwrap_or(0),
+                        duration_ms: item.duration_ms(),
+                        is_playing: state.is_playing,
+                        is_episode: item.is_episode(),
+                    })
+                },
+                None => None,
+            }
+        },
+        None => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StateChange {
+    Stopped(u64),
+    Changed(u64),
+    Played(u64),
+    Unchanged,
+}
+
+/// Decide how a new poll of Spotify changes what is playing, and how much
+/// of the old track played in between.
+pub fn compare_playback_states(old: Option<&PlayerState>,
+                               new: Option<&PlayerState>) -> StateChange {
+    let old_track = flatten_player_state(old);
+    let new_track = flatten_player_state(new);
+    if old_track.is_none() && new_track.is_none() {
+        return StateChange::Unchanged;
+    }
+    if old_track.is_some() && new_track.is_none() {
+        return StateChange::Stopped(0);
+    }
+    if old_track.is_none() && new_track.is_some() {
+        match new_track.unwrap().is_playing {
+            true => { return StateChange::Changed(0); },
+            false => { return StateChange::Stopped(0); },
+        }
+    }
+    let old_track = old_track.unwrap();
+    let new_track = new_track.unwrap();
+    let new_time = new_track.progress_ms;
+    let old_time = old_track.progress_ms;
+    if old_track.uri != new_track.uri {
+        // Assume that up to 31 seconds of the previous track played.
+        let played = ::std::cmp::min(
+            ::std::cmp::max(old_track.duration_ms as i64 - old_time as i64, 0),
+            31000) as u64;
+        if old_track.is_playing && !new_track.is_playing {
+            // End of context
+            return StateChange::Stopped(played);
+        }
+        return StateChange::Changed(played);
+    }
+    let played = ::std::cmp::max(new_time as i64 - old_time as i64, 0) as u64;
+    if (new_time == 0 && !new_track.is_playing) && (old_time > 0 && old_track.is_playing) {
+        // Playlist finished and reset.  Normally caught above, but this
+        // is a special case for a 1-track playlist.
+        let played = ::std::cmp::min(
+            ::std::cmp::max(old_track.duration_ms as i64 - old_time as i64, 0),
+            31000) as u64;
+        return StateChange::Stopped(played);
+    }
+    if played == 0 {
+        return StateChange::Unchanged;
+    }
+    if !old_track.is_playing {
+        return StateChange::Changed(played);
+    }
+    StateChange::Played(played)
+}
+
+/// Handle to the thread that owns the Spotify connection.
+///
+/// The thread publishes its latest view of Spotify in the shared state, and
+/// notifies the frontend through `rx` whenever it changes.
+pub struct SpotifyThread {
+    pub handle: thread::JoinHandle<()>,
+    /// Send anything to shut the thread down.
+    pub tx: Sender<()>,
+    pub rx: Receiver<SpotifyThreadCommand>,
+    pub device_list: Arc<RwLock<Option<ConnectDeviceList>>>,
+    pub player_state: Arc<RwLock<Option<PlayerState>>>,
+    pub presets: Arc<RwLock<Vec<Preset>>>,
+    /// Long items that can be resumed where they were left, most recent first.
+    pub resume_points: Arc<RwLock<Vec<ResumePoint>>>,
+    /// Recent quick-saves, most recent first.
+    pub quick_saves: Arc<RwLock<Vec<SavedTrack>>>,
+    /// Most recent command that failed, until the problem goes away.
+    pub failure: Arc<RwLock<Option<Failure>>>,
+}
+
+impl SpotifyThread {
+    /// Ask the thread to save its state and stop, and wait up to `timeout`
+    /// for it.  While a settings form is open the thread doesn't read
+    /// commands until the form is closed, so it may not stop in time.
+    /// Returns whether it stopped.
+    pub fn quit(self, commands: &Sender<CommandMessage>, timeout: Duration) -> bool {
+        let _ = commands.send(CommandMessage::new(Command::Quit));
+        let (tx, rx) = channel::<()>();
+        let handle = self.handle;
+        thread::spawn(move || {
+            let _ = handle.join();
+            let _ = tx.send(());
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(_) => true,
+            Err(_) => {
+                warn!("Spotify thread is busy, exiting without it.");
+                false
+            },
+        }
+    }
+}
+
+/// Create a Spotify controller from the settings file and authenticate it.
+pub fn connect_spotify() -> Option<SpotifyConnectr<'static>> {
+    let mut spotify = SpotifyConnectr::new().build()?;
+    info!("Created Spotify controller.");
+    spotify.connect();
+    Some(spotify)
+}
+
+/// Spawn the thread that owns the Spotify connection.  `connect` is called
+/// until it returns a controller, normally `connect_spotify()`.
+pub fn create_spotify_thread<F>(rx_cmd: Receiver<CommandMessage>, connect: F) -> SpotifyThread
+    where F: Fn() -> Option<SpotifyConnectr<'static>> + Send + 'static {
+    let (tx_in,rx_in) = channel::<()>();
+    let (tx_out,rx_out) = channel::<SpotifyThreadCommand>();
+    let device_list = Arc::new(RwLock::new(Some(Default::default())));
+    let player_state = Arc::new(RwLock::new(None));
+    let presets = Arc::new(RwLock::new(vec![]));
+    let failure = Arc::new(RwLock::new(None));
+    let resume_points = Arc::new(RwLock::new(vec![]));
+    let quick_saves = Arc::new(RwLock::new(vec![]));
+    let thread_device_list = device_list.clone();
+    let thread_player_state = player_state.clone();
+    let thread_presets = presets.clone();
+    let thread_failure = failure.clone();
+    let thread_resume_points = resume_points.clone();
+    let thread_quick_saves = quick_saves.clone();
+    let thread = thread::spawn(move || {
+        let tx = tx_out;
+        let rx = rx_in;
+        let rx_cmd = rx_cmd;
+        let mut refresh_time_utc = 0;
+        let mut track_play_time_ms: u64 = 0;
+        // Latest state reported by Spotify, without optimistic changes
+        let mut last_state: Option<PlayerState> = None;
+        let mut optimistic = OptimisticState::new();
+        // Devices Spotify reported last, to tell which come and go
+        let mut last_devices: Option<ConnectDeviceList> = None;
+
+        // Continuously try to create a connection to Spotify web API.
+        // If it fails, assume that the settings file is corrupt and inform
+        // the main thread of it.  The main thread can request that the
+        // settings file be re-configured.
+        let mut spotify: Option<SpotifyConnectr>;
+        loop {
+            spotify = connect();
+            match spotify {
+                Some(_) => { break; },
+                None => {
+                    let _ = tx.send(SpotifyThreadCommand::InvalidSettings);
+                    if let Ok(msg) = rx_cmd.recv_timeout(Duration::from_secs(120)) {
+                        match msg.command {
+                            Command::Reconfigure => {
+                                reconfigure(None);
+                                msg.reply(Ok(()));
+                            },
+                            Command::Quit => {
+                                msg.reply(Ok(()));
+                                let _ = tx.send(SpotifyThreadCommand::Quit);
+                                return;
+                            },
+                            _ => {
+                                let err = "not connected to Spotify".to_string();
+                                msg.reply(Err(SpotifyError::Connection(err)));
+                            },
+                        }
+                    }
+                },
+            }
+        }
+        let mut spotify = spotify.unwrap();
+        let device_list = thread_device_list;
+        let player_state = thread_player_state;
+        let presets = thread_presets;
+        let failure = thread_failure;
+        let resume_points = thread_resume_points;
+        let quick_saves = thread_quick_saves;
+        info!("Created Spotify connection.");
+        spotify.set_target_device(None);
+        let mut resume = ResumeHistory::load(spotify.settings().state_file.as_ref().map(|f| f.as_str()));
+        {
+            let mut preset_writer = presets.write().unwrap();
+            *preset_writer = spotify.get_presets().clone();
+            *resume_points.write().unwrap() = resume.points().clone();
+            *quick_saves.write().unwrap() = spotify.quick_saves().clone();
+            let _ = tx.send(SpotifyThreadCommand::Update);
+        }
+        loop {
+            if rx.try_recv().is_ok() {
+                // Main thread tells us to shutdown
+                resume.save_contexts();
+                break;
+            }
+            let now = time::now_utc().to_timespec().sec as i64;
+            spotify.await_once(false);
+            // Block for 200ms while waiting for UI input.  This throttles the
+            // thread CPU usage, at the expense of slight delays for metadata
+            // updates.  Optimizes for UI response.
+            if let Ok(msg) = rx_cmd.recv_timeout(Duration::from_millis(200)) {
+                info!("Received {:?}", msg.command);
+                if msg.command == Command::Quit {
+                    resume.save_contexts();
+                    msg.reply(Ok(()));
+                    let _ = tx.send(SpotifyThreadCommand::Quit);
+                    break;
+                }
+                if msg.command == Command::EditAlarms {
+                    let devs = device_list.read().unwrap();
+                    let _ = tx.send(SpotifyThreadCommand::ConfigActive);
+                    spotify.alarm_configure((*devs).as_ref());
+                    let _ = tx.send(SpotifyThreadCommand::ConfigInactive);
+                }
+                if msg.command == Command::ImportPresets {
+                    let _ = tx.send(SpotifyThreadCommand::ConfigActive);
+                    match import::import_web(&mut spotify) {
+                        Ok(count) => info!("Imported {} presets.", count),
+                        Err(e) => warn!("Couldn't import presets: {}", e),
+                    }
+                    let _ = tx.send(SpotifyThreadCommand::ConfigInactive);
+                }
+                if msg.command == Command::Reconfigure {
+                    info!("Reconfiguring settings.");
+                    let _ = tx.send(SpotifyThreadCommand::ConfigActive);
+                    reconfigure(Some(spotify.settings()));
+                    spotify.reread_settings();
+                    let _ = tx.send(SpotifyThreadCommand::ConfigInactive);
+                    info!("Finished reconfiguring.");
+                }
+                // Surprises are chosen here, and only here, so each choice
+                // is recorded once.  Resuming presets start where they
+                // were left.
+                let command = match msg.command {
+                    Command::Preset(ref preset) => Command::Preset(resume.resume_preset(preset)),
+                    Command::Surprise(ref group) => {
+                        match spotify.surprise_preset(group.as_ref().map(|g| g.as_str())) {
+                            Some(preset) => Command::Preset(resume.resume_preset(&preset)),
+                            None => msg.command.clone(),
+                        }
+                    },
+                    ref cmd => cmd.clone(),
+                };
+                let expectation = Expectation::from_command(&command,
+                                                            player_state.read().unwrap().as_ref());
+                let (refresh_strategy, result) = handle_command(player_state.read().unwrap().as_ref(),
+                                                                &mut spotify, &command);
+                if let Command::Reconfigure | Command::SavePreset | Command::ImportPresets = msg.command {
+                    *presets.write().unwrap() = spotify.get_presets().clone();
+                }
+                if let Command::SaveTrack | Command::UndoQuickSave = msg.command {
+                    *quick_saves.write().unwrap() = spotify.quick_saves().clone();
+                }
+                if let (true, Some(expectation)) = (result.is_ok(), expectation) {
+                    // Show the result right away.  Spotify is often slow to
+                    // report changes, even after they happen.
+                    let mut player_writer = player_state.write().unwrap();
+                    let mut dev_writer = device_list.write().unwrap();
+                    expectation.apply(&mut *player_writer, &mut *dev_writer);
+                    optimistic.expect(expectation, now);
+                    let _ = tx.send(SpotifyThreadCommand::Update);
+                }
+                if msg.command != Command::Redraw {
+                    let mut failure_writer = failure.write().unwrap();
+                    let new_failure = match result {
+                        Err(ref e) => Some(Failure::new(msg.command.clone(), e.clone(), now)),
+                        Ok(_) => None,
+                    };
+                    if *failure_writer != new_failure {
+                        *failure_writer = new_failure;
+                        let _ = tx.send(SpotifyThreadCommand::Update);
+                    }
+                }
+                msg.reply(result);
+                refresh_time_utc = match refresh_strategy {
+                    RefreshTime::Now => now - 1,
+                    RefreshTime::Soon => now + 1,
+                    _ => refresh_time_utc,
+                };
+                if refresh_strategy == RefreshTime::Redraw {
+                    let _ = tx.send(SpotifyThreadCommand::Update);
+                }
+            }
+
+            if now > refresh_time_utc {
+                info!("Request update");
+                let polled_list = spotify.request_device_list();
+                let polled = polled_list.is_some();
+                // Compare with the last list that arrived, so failed polls
+                // don't look like every device went away
+                let events = match (last_devices.as_ref(), polled_list.as_ref()) {
+                    (Some(old), Some(new)) => device_events(old, new),
+                    _ => Vec::new(),
+                };
+                if polled {
+                    last_devices = polled_list.clone();
+                }
+                let mut dev_list = match polled_list {
+                    Some(list) => Some(spotify.settings().devices.arrange(list)),
+                    None => Some(Default::default()),
+                };
+                let play_state = spotify.request_player_state();
+                let transferred = match last_devices {
+                    Some(ref devices) if !events.is_empty() => {
+                        apply_transfer_rules(&mut spotify, &events, devices,
+                                             play_state.as_ref(), last_state.as_ref())
+                    },
+                    _ => false,
+                };
+                let change = compare_playback_states(last_state.as_ref(), play_state.as_ref());
+                match change {
+                    StateChange::Changed(time_ms) => {
+                        track_play_time_ms += time_ms;
+                        scrobble(&mut spotify, last_state.as_ref(), track_play_time_ms, true);
+                        track_play_time_ms = 0;
+                        scrobble(&mut spotify, play_state.as_ref(), track_play_time_ms, false);
+                    }
+                    StateChange::Stopped(time_ms) => {
+                        track_play_time_ms += time_ms;
+                        scrobble(&mut spotify, last_state.as_ref(), track_play_time_ms, true);
+                        track_play_time_ms = 0;
+                    },
+                    StateChange::Played(time_ms) => {
+                        track_play_time_ms += time_ms;
+                    },
+                    StateChange::Unchanged => {},
+                }
+                let resume_contexts: Vec<String> = spotify.get_presets().iter()
+                    .filter(|p| p.options.resume)
+                    .filter_map(|p| p.context().map(|c| c.to_string()))
+                    .collect();
+                resume.follow_context(play_state.as_ref(), &resume_contexts);
+                if let StateChange::Changed(_) | StateChange::Stopped(_) = change {
+                    // Keep the place in whatever long item was left
+                    if resume.remember(last_state.as_ref()) {
+                        *resume_points.write().unwrap() = resume.points().clone();
+                    }
+                    resume.save_contexts();
+                }
+                // Scrobbling follows what Spotify reports, but the UI keeps
+                // showing recent commands until Spotify agrees.
+                let mut shown_state = play_state.clone();
+                let pending = optimistic.reconcile(&mut shown_state, &mut dev_list, now);
+                last_state = play_state;
+                *device_list.write().unwrap() = dev_list;
+                *player_state.write().unwrap() = shown_state;
+                {
+                    let mut failure_writer = failure.write().unwrap();
+                    let devices = match polled {
+                        true => last_devices.as_ref(),
+                        false => None,
+                    };
+                    let resolved = match *failure_writer {
+                        Some(ref f) => f.resolved(last_state.as_ref(), devices, &spotify.settings().devices,
+                                                  polled, now),
+                        None => false,
+                    };
+                    if resolved {
+                        info!("Problem with earlier command went away.");
+                        *failure_writer = None;
+                    }
+                }
+                refresh_time_utc = match pending || transferred {
+                    true => now + 1,
+                    false => refresh_time(last_state.as_ref(), now),
+                };
+                info!("Refreshed Spotify state.");
+                let _ = tx.send(SpotifyThreadCommand::Update);
+            }
+        }
+    });
+    SpotifyThread {
+        handle: thread,
+        tx: tx_in,
+        rx: rx_out,
+        device_list: device_list,
+        player_state: player_state,
+        presets: presets,
+        failure: failure,
+        resume_points: resume_points,
+        quick_saves: quick_saves,
+    }
+}
+
+pub fn require(response: SpotifyResponse) -> CommandResult {
+    let result = check_response(&response);
+    match result {
+        Ok(_) => { info!("Response: {}", response.code.unwrap_or(0)); },
+        Err(ref e) => { warn!("Spotify action failed! ({})", e); }
+    }
+    result
+}
+
+/// Move playback as the first matching transfer rule in connectr.ini says,
+/// for each device that came online or went away.  `devices` is the current
+/// device list, `play_state` what's playing now and `last_state` what was
+/// playing before.  Returns whether playback was moved.
+pub fn apply_transfer_rules(spotify: &mut SpotifyConnectr, events: &[DeviceEvent],
+                            devices: &ConnectDeviceList, play_state: Option<&PlayerState>,
+                            last_state: Option<&PlayerState>) -> bool {
+    let mut moved = false;
+    for event in events {
+        info!("{}", event);
+        // A device that left may have stopped playing already
+        let playing_on = match event.kind {
+            DeviceEventKind::Appeared => play_state,
+            DeviceEventKind::Disappeared => play_state.or(last_state),
+        }.map(|s| &s.device);
+        let rule = spotify.settings().transfer_rules.iter()
+            .find(|r| r.matches(&spotify.settings().devices, event, playing_on))
+            .cloned();
+        let rule = match rule {
+            Some(rule) => rule,
+            None => continue,
+        };
+        let target = spotify.settings().devices.find(devices, &rule.target).and_then(|d| d.id.clone());
+        match target {
+            Some(ref id) if playing_on.and_then(|d| d.id.as_ref()) == Some(id) => {
+                info!("Transfer rule {}: already playing on {}", rule.name, rule.target);
+            },
+            Some(id) => {
+                info!("Transfer rule {}: moving playback to {}", rule.name, rule.target);
+                match require(spotify.transfer(id, rule.play)) {
+                    Ok(_) => moved = true,
+                    Err(e) => warn!("Transfer rule {} failed: {}", rule.name, e),
+                }
+            },
+            None => warn!("Transfer rule {}: device {} isn't online", rule.name, rule.target),
+        }
+    }
+    moved
+}
+
+/// ID of the default device, if one is set and nothing is playing.  Fails
+/// if the default device isn't online.
+fn idle_default_device(spotify: &mut SpotifyConnectr, player_state: Option<&PlayerState>)
+                       -> Result<Option<DeviceId>, SpotifyError> {
+    let name = match (player_state, spotify.settings().devices.default.clone()) {
+        (None, Some(name)) => name,
+        _ => return Ok(None),
+    };
+    match spotify.resolve_device(&name) {
+        Some(id) => Ok(Some(id)),
+        None => Err(SpotifyError::UnknownDevice(name)),
+    }
+}
+
+/// The preset, set to play on the default device if nothing is playing and
+/// it doesn't pick a device itself.
+fn on_default_device(spotify: &SpotifyConnectr, player_state: Option<&PlayerState>, preset: &Preset) -> Preset {
+    let mut preset = preset.clone();
+    if player_state.is_none() && preset.options.device.is_none() {
+        preset.options.device = spotify.settings().devices.default.clone();
+    }
+    preset
+}
+
+/// Set up the player as the preset asks, then start playing it.  Requests
+/// go out in order (device, volume, shuffle, repeat, play), stopping at the
+/// first one Spotify refuses.
+pub fn play_preset(spotify: &mut SpotifyConnectr, preset: &Preset) -> CommandResult {
+    let options = &preset.options;
+    let device = match options.device {
+        Some(ref name) => {
+            match spotify.resolve_device(name) {
+                Some(id) => Some(id),
+                None => return Err(SpotifyError::UnknownDevice(name.clone())),
+            }
+        },
+        None => None,
+    };
+    let mut result = Ok(());
+    if let Some(ref id) = device {
+        result = require(spotify.transfer(id.clone(), false));
+    }
+    spotify.set_target_device(device);
+    if let (Ok(_), Some(vol)) = (&result, options.volume) {
+        result = require(spotify.volume(vol));
+    }
+    if let (Ok(_), Some(shuffle)) = (&result, options.shuffle) {
+        result = require(spotify.shuffle(shuffle));
+    }
+    if let (Ok(_), Some(repeat)) = (&result, options.repeat) {
+        result = require(spotify.repeat(repeat));
+    }
+    if result.is_ok() {
+        result = require(spotify.play(Some(&preset.play_context())));
+    }
+    // Later commands go to whichever device is playing
+    spotify.set_target_device(None);
+    result
+}
+
+pub fn play_uri(spotify: &mut SpotifyConnectr, device: Option<&str>, uri: Option<&str>) -> CommandResult {
+    match device {
+        Some(dev) => { spotify.set_target_device(Some(dev.to_string())); },
+        None => { spotify.set_target_device(None); },
+    }
+    let result = match uri {
+        Some(s) => {
+            let ctx = PlayContext::new()
+                .context_uri(s)
+                .offset_position(0)
+                .build();
+            require(spotify.play(Some(&ctx)))
+        }
+        None => {
+            info!("Transfer!");
+            require(spotify.play(None))
+        }
+    };
+
+    // Always set it back to None, so commands go to the currently
+    // playing device.
+    spotify.set_target_device(None);
+    result
+}
@@ -0,0 +1,148 @@
+use super::Command;
+use super::super::{PlayerState, ConnectDeviceList, ConnectContext, DeviceId};
+
+// How long to show the expected result of a command when Spotify disagrees.
+// Spotify can take a few seconds to report changes that already happened.
+pub const OPTIMISTIC_GRACE: i64 = 5;
+
+/// What a successful command should do to the player.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    Playing(bool),
+    Volume(u32),
+    Shuffle(bool),
+    Device(DeviceId),
+    /// Skipped away from the track with this URI
+    Skipped(String),
+    /// Started playing this context
+    Context(String),
+}
+
+impl Expectation {
+    /// The expected result of a command, if it changes anything visible.
+    pub fn from_command(cmd: &Command, player: Option<&PlayerState>) -> Option<Expectation> {
+        match *cmd {
+            Command::PlayPause => player.map(|p| Expectation::Playing(!p.is_playing)),
+            Command::Volume(vol) => Some(Expectation::Volume(vol)),
+            Command::Shuffle(shuffle) => Some(Expectation::Shuffle(shuffle)),
+            Command::SelectDevice(ref id) => Some(Expectation::Device(id.clone())),
+            Command::SkipNext | Command::SkipPrev => {
+                match player.and_then(|p| p.item.as_ref()) {
+                    Some(item) => Some(Expectation::Skipped(item.uri().to_string())),
+                    None => None,
+                }
+            },
+            Command::Preset(ref preset) => preset.context().map(|uri| Expectation::Context(uri.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Whether Spotify reports the expected result.
+    pub fn met(&self, player: Option<&PlayerState>, devices: Option<&ConnectDeviceList>) -> bool {
+        let player = match player {
+            Some(p) => p,
+            None => {
+                // Only a device transfer is visible without a player
+                return match (self, devices) {
+                    (&Expectation::Device(ref id), Some(devices)) => {
+                        devices.devices.iter().any(|d| d.is_active && d.id.as_ref() == Some(id))
+                    },
+                    _ => false,
+                };
+            },
+        };
+        match *self {
+            Expectation::Playing(playing) => player.is_playing == playing,
+            Expectation::Volume(vol) => player.device.volume_percent == Some(vol),
+            Expectation::Shuffle(shuffle) => player.shuffle_state == shuffle,
+            Expectation::Device(ref id) => player.device.id.as_ref() == Some(id),
+            Expectation::Skipped(ref uri) => match player.item {
+                Some(ref item) => item.uri() != uri,
+                None => true,
+            },
+            Expectation::Context(ref uri) => player.playing_from_context(uri),
+        }
+    }
+
+    /// Change cached state to look like the command already took effect.
+    pub fn apply(&self, player: &mut Option<PlayerState>, devices: &mut Option<ConnectDeviceList>) {
+        if let Some(ref mut devices) = *devices {
+            match *self {
+                Expectation::Device(ref id) => {
+                    for dev in devices.devices.iter_mut() {
+                        dev.is_active = dev.id.as_ref() == Some(id);
+                    }
+                },
+                Expectation::Volume(vol) => {
+                    for dev in devices.devices.iter_mut().filter(|d| d.is_active) {
+                        dev.volume_percent = Some(vol);
+                    }
+                },
+                _ => {},
+            }
+        }
+        let player = match *player {
+            Some(ref mut p) => p,
+            None => return,
+        };
+        match *self {
+            Expectation::Playing(playing) => player.is_playing = playing,
+            Expectation::Volume(vol) => player.device.volume_percent = Some(vol),
+            Expectation::Shuffle(shuffle) => player.shuffle_state = shuffle,
+            Expectation::Device(ref id) => {
+                let device = devices.as_ref().and_then(|list| {
+                    list.devices.iter().find(|d| d.id.as_ref() == Some(id))
+                });
+                if let Some(device) = device {
+                    player.device = device.clone();
+                }
+            },
+            Expectation::Skipped(_) => {
+                player.progress_ms = Some(0);
+                player.is_playing = true;
+            },
+            Expectation::Context(ref uri) => {
+                player.context = Some(ConnectContext { uri: uri.clone() });
+                player.progress_ms = Some(0);
+                player.is_playing = true;
+            },
+        }
+    }
+}
+
+/// Expected results of recent commands, shown until Spotify catches up.
+pub struct OptimisticState {
+    pending: Vec<(Expectation, i64)>,
+}
+
+impl OptimisticState {
+    pub fn new() -> OptimisticState {
+        OptimisticState { pending: Vec::new() }
+    }
+    /// Remember an expected result, replacing any older one of the same kind.
+    pub fn expect(&mut self, expectation: Expectation, now: i64) {
+        self.pending.retain(|&(ref e, _)| {
+            ::std::mem::discriminant(e) != ::std::mem::discriminant(&expectation)
+        });
+        self.pending.push((expectation, now + OPTIMISTIC_GRACE));
+    }
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+    /// Reconcile fresh state from Spotify with the expected results.
+    ///
+    /// Expectations that Spotify confirms, or that are too old, are dropped.
+    /// The rest are applied on top of the fresh state.  Returns true if any
+    /// are still pending, in which case Spotify should be asked again soon.
+    pub fn reconcile(&mut self, player: &mut Option<PlayerState>,
+                     devices: &mut Option<ConnectDeviceList>, now: i64) -> bool {
+        self.pending.retain(|&(ref e, deadline)| {
+            now <= deadline && !e.met(player.as_ref(), devices.as_ref())
+        });
+        for &(ref e, _) in &self.pending {
+            info!("Spotify hasn't caught up with {:?} yet.", e);
+            e.apply(player, devices);
+        }
+        self.is_pending()
+    }
+}
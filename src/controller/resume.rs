@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use super::super::{PlayerState, ConnectPlaybackItem};
+use super::super::preset::{Preset, PresetOffset};
+use super::super::state_file;
+use super::super::uri::SpotifyUri;
+
+// Tracks at least this long get a resume point, like mixes and audiobook
+// chapters.  Episodes always do.
+pub const RESUME_MIN_DURATION_MS: u64 = 20 * 60 * 1000;
+// Items this close to the end count as finished.
+pub const RESUME_FINISHED_MS: u64 = 30 * 1000;
+// How many resume points to keep.
+pub const RESUME_MAX_ENTRIES: usize = 10;
+
+/// Where playback of a long item stopped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ResumePoint {
+    pub uri: String,
+    pub name: String,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+}
+
+impl ResumePoint {
+    /// Menu label, such as "Pilot (12:34)".
+    pub fn label(&self) -> String {
+        let min = self.position_ms / 1000 / 60;
+        let sec = (self.position_ms / 1000) % 60;
+        format!("{} ({}:{:02})", self.name, min, sec)
+    }
+}
+
+/// Where playback of a context, like a playlist, was left.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ContextPosition {
+    pub track_uri: String,
+    pub position_ms: u64,
+}
+
+/// Contents of the state file.
+#[derive(Serialize, Deserialize, Default)]
+struct ResumeFile {
+    #[serde(default)]
+    points: Vec<ResumePoint>,
+    #[serde(default)]
+    contexts: BTreeMap<String, ContextPosition>,
+}
+
+/// Recent resume points, most recent first, and where contexts of resuming
+/// presets were left.  Saved in a local state file.
+pub struct ResumeHistory {
+    path: Option<String>,
+    points: Vec<ResumePoint>,
+    contexts: BTreeMap<String, ContextPosition>,
+    /// Whether `contexts` changed since the last save
+    unsaved: bool,
+}
+
+impl ResumeHistory {
+    /// Load history from the state file.  Without a file, history is only
+    /// kept until Connectr exits.
+    pub fn load(path: Option<&str>) -> ResumeHistory {
+        let state: ResumeFile = path.and_then(|p| state_file::load(p, "resume history"))
+            .unwrap_or_default();
+        ResumeHistory {
+            path: path.map(|p| p.to_string()),
+            points: state.points,
+            contexts: state.contexts,
+            unsaved: false,
+        }
+    }
+    pub fn points(&self) -> &Vec<ResumePoint> {
+        &self.points
+    }
+    /// Remember where playback left the item in `state`, if it's long
+    /// enough to be worth resuming.  Returns true if the history changed.
+    pub fn remember(&mut self, state: Option<&PlayerState>) -> bool {
+        let state = match state {
+            Some(s) => s,
+            None => return false,
+        };
+        let item = match state.item {
+            Some(ref item) => item,
+            None => return false,
+        };
+        let long = match *item {
+            ConnectPlaybackItem::Episode(_) => true,
+            ConnectPlaybackItem::Track(ref t) => t.duration_ms >= RESUME_MIN_DURATION_MS,
+        };
+        if !long {
+            return false;
+        }
+        let position_ms = state.progress_ms.unwrap_or(0);
+        let old_len = self.points.len();
+        self.points.retain(|p| p.uri != item.uri());
+        let finished = position_ms + RESUME_FINISHED_MS >= item.duration_ms();
+        if position_ms > 0 && !finished {
+            self.points.insert(0, ResumePoint {
+                uri: item.uri().to_string(),
+                name: item.name().to_string(),
+                position_ms: position_ms,
+                duration_ms: item.duration_ms(),
+            });
+            self.points.truncate(RESUME_MAX_ENTRIES);
+        }
+        else if self.points.len() == old_len {
+            return false;
+        }
+        self.save();
+        true
+    }
+    /// Where the context was left, by any spelling of its URI.
+    pub fn context_position(&self, context: &str) -> Option<&ContextPosition> {
+        self.contexts.get(&canonical(context))
+    }
+    /// Note where the player is, if it's playing one of `contexts`.  Kept in
+    /// memory until the next `save_contexts()`.
+    pub fn follow_context(&mut self, state: Option<&PlayerState>, contexts: &[String]) {
+        let state = match state {
+            Some(s) => s,
+            None => return,
+        };
+        let (context, item) = match (state.context.as_ref(), state.item.as_ref()) {
+            (Some(c), Some(i)) => (canonical(&c.uri), i),
+            _ => return,
+        };
+        if !contexts.iter().any(|c| canonical(c) == context) {
+            return;
+        }
+        let position = ContextPosition {
+            track_uri: item.uri().to_string(),
+            position_ms: state.progress_ms.unwrap_or(0),
+        };
+        if self.contexts.get(&context) != Some(&position) {
+            self.contexts.insert(context, position);
+            self.unsaved = true;
+        }
+    }
+    /// Save context positions, if any changed.
+    pub fn save_contexts(&mut self) {
+        if self.unsaved {
+            self.save();
+        }
+    }
+    /// The preset, changed to start where its context was left if it
+    /// resumes and was played before.
+    pub fn resume_preset(&self, preset: &Preset) -> Preset {
+        let mut preset = preset.clone();
+        if !preset.options.resume {
+            return preset;
+        }
+        let position = preset.context().and_then(|c| self.context_position(c)).cloned();
+        if let Some(position) = position {
+            preset.offset = Some(PresetOffset::Uri(position.track_uri));
+            preset.position_ms = Some(position.position_ms);
+        }
+        preset
+    }
+    fn save(&mut self) {
+        self.unsaved = false;
+        if let Some(ref path) = self.path {
+            let state = ResumeFile { points: self.points.clone(), contexts: self.contexts.clone() };
+            state_file::save(path, "resume history", &state);
+        }
+    }
+}
+
+/// Context URIs are stored in Spotify's current format.
+fn canonical(uri: &str) -> String {
+    match uri.parse::<SpotifyUri>() {
+        Ok(u) => u.to_string(),
+        Err(_) => uri.to_string(),
+    }
+}
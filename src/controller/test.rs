@@ -0,0 +1,361 @@
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::super::*;
+    use devices::DeviceSettings;
+    use preset::{Preset, PresetOffset};
+
+    fn player_state(track: &str, playing: bool, progress_ms: u64) -> PlayerState {
+        let json = format!(r#"{{"timestamp": 0,
+                    "device": {{"id": "desktop", "is_active": true, "is_restricted": false,
+                               "name": "Desktop", "type": "Computer", "volume_percent": 50}},
+                    "progress_ms": {},
+                    "is_playing": {},
+                    "item": {{"type": "track", "duration_ms": 200000, "name": "{}", "uri": "spotify:track:{}",
+                             "album": {{"name": "Album", "uri": "spotify:album:album"}},
+                             "artists": [{{"name": "Artist", "uri": "spotify:artist:artist"}}]}},
+                    "shuffle_state": false,
+                    "repeat_state": "off",
+                    "context": null}}"#,
+                progress_ms, playing, track, track);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_refresh_time() {
+        let now = 1000;
+        // Nothing playing refreshes on the normal period
+        assert_eq!(refresh_time(None, now), now + REFRESH_PERIOD);
+        assert_eq!(refresh_time(Some(&player_state("First", false, 0)), now),
+                   now + REFRESH_PERIOD);
+        // Playing refreshes just after the track ends...
+        assert_eq!(refresh_time(Some(&player_state("First", true, 190000)), now), now + 11);
+        assert_eq!(refresh_time(Some(&player_state("First", true, 250000)), now), now + 1);
+        // ... but never later than the normal period
+        assert_eq!(refresh_time(Some(&player_state("First", true, 0)), now),
+                   now + REFRESH_PERIOD);
+    }
+
+    #[test]
+    fn test_compare_playback_states() {
+        let playing = player_state("First", true, 10000);
+        let later = player_state("First", true, 15000);
+        let paused = player_state("First", false, 10000);
+        let next = player_state("Second", true, 0);
+        assert_eq!(compare_playback_states(None, None), StateChange::Unchanged);
+        assert_eq!(compare_playback_states(None, Some(&playing)), StateChange::Changed(0));
+        assert_eq!(compare_playback_states(None, Some(&paused)), StateChange::Stopped(0));
+        assert_eq!(compare_playback_states(Some(&playing), None), StateChange::Stopped(0));
+        assert_eq!(compare_playback_states(Some(&playing), Some(&playing)), StateChange::Unchanged);
+        assert_eq!(compare_playback_states(Some(&playing), Some(&later)), StateChange::Played(5000));
+        assert_eq!(compare_playback_states(Some(&paused), Some(&later)), StateChange::Changed(5000));
+        // Up to 31 seconds of the old track is assumed to have played
+        assert_eq!(compare_playback_states(Some(&playing), Some(&next)), StateChange::Changed(31000));
+        let nearly_done = player_state("First", true, 190000);
+        assert_eq!(compare_playback_states(Some(&nearly_done), Some(&next)), StateChange::Changed(10000));
+        // Context ended while playing a single track
+        let reset = player_state("First", false, 0);
+        assert_eq!(compare_playback_states(Some(&nearly_done), Some(&reset)), StateChange::Stopped(10000));
+    }
+
+    #[test]
+    fn test_expectation() {
+        let paused = player_state("First", false, 10000);
+        let playing = player_state("First", true, 10000);
+        let next = player_state("Second", true, 0);
+        assert_eq!(Expectation::from_command(&Command::PlayPause, Some(&paused)),
+                   Some(Expectation::Playing(true)));
+        assert_eq!(Expectation::from_command(&Command::PlayPause, None), None);
+        assert_eq!(Expectation::from_command(&Command::SkipNext, Some(&paused)),
+                   Some(Expectation::Skipped("spotify:track:First".to_string())));
+        assert_eq!(Expectation::from_command(&Command::Redraw, Some(&paused)), None);
+
+        assert!(Expectation::Playing(true).met(Some(&playing), None));
+        assert!(!Expectation::Playing(true).met(Some(&paused), None));
+        assert!(Expectation::Skipped("spotify:track:First".to_string()).met(Some(&next), None));
+        assert!(!Expectation::Volume(30).met(Some(&playing), None));
+
+        let mut state = Some(paused.clone());
+        let mut devices = None;
+        Expectation::Volume(30).apply(&mut state, &mut devices);
+        assert_eq!(state.as_ref().unwrap().device.volume_percent, Some(30));
+        let uri = "spotify:playlist:evening".to_string();
+        Expectation::Context(uri.clone()).apply(&mut state, &mut devices);
+        let state = state.unwrap();
+        assert!(state.is_playing);
+        assert_eq!(state.progress_ms, Some(0));
+        assert!(state.playing_from_context(&uri));
+        assert!(state.playing_from_context("spotify:user:bob:playlist:evening"));
+        assert!(!state.playing_from_context("spotify:playlist:morning"));
+    }
+
+    #[test]
+    fn test_optimistic_state() {
+        let now = 1000;
+        let paused = player_state("First", false, 10000);
+        let mut optimistic = OptimisticState::new();
+        optimistic.expect(Expectation::Playing(false), now);
+        optimistic.expect(Expectation::Playing(true), now);
+
+        // Spotify hasn't caught up, so the expected state is shown
+        let mut state = Some(paused.clone());
+        assert!(optimistic.reconcile(&mut state, &mut None, now + 1));
+        assert!(state.unwrap().is_playing);
+
+        // Spotify confirms it
+        let mut state = Some(player_state("First", true, 11000));
+        assert!(!optimistic.reconcile(&mut state, &mut None, now + 2));
+        assert!(!optimistic.is_pending());
+
+        // Spotify never catches up, and eventually wins
+        optimistic.expect(Expectation::Playing(true), now);
+        let mut state = Some(paused.clone());
+        assert!(optimistic.reconcile(&mut state, &mut None, now + OPTIMISTIC_GRACE));
+        let mut state = Some(paused.clone());
+        assert!(!optimistic.reconcile(&mut state, &mut None, now + OPTIMISTIC_GRACE + 1));
+        assert!(!state.unwrap().is_playing);
+    }
+
+    #[test]
+    fn test_failure() {
+        let now = 1000;
+        let no_device = SpotifyError::Rejected {
+            status: 404,
+            message: "Player command failed: No active device found".to_string(),
+            reason: Some("NO_ACTIVE_DEVICE".to_string()),
+        };
+        let settings = DeviceSettings::default();
+        let failure = Failure::new(Command::PlayPause, no_device, now);
+        assert_eq!(failure.message(), "No active device \u{2014} pick one below");
+        assert!(!failure.resolved(None, Some(&Default::default()), &settings, true, now + 1));
+        assert!(failure.resolved(Some(&player_state("First", false, 0)), None, &settings, true, now + 1));
+        assert!(failure.resolved(None, None, &settings, false, now + FAILURE_TIMEOUT + 1));
+
+        let offline = Failure::new(Command::SkipNext,
+                                   SpotifyError::Connection("timed out".to_string()), now);
+        assert_eq!(offline.message(), "Can't reach Spotify \u{2014} check your connection");
+        assert!(!offline.resolved(None, None, &settings, false, now + 1));
+        assert!(offline.resolved(None, None, &settings, true, now + 1));
+
+        let refused = Failure::new(Command::Volume(30), SpotifyError::Rejected {
+            status: 500, message: "oops".to_string(), reason: None,
+        }, now);
+        assert_eq!(refused.message(), "Spotify couldn't change the volume");
+        assert!(!refused.resolved(Some(&player_state("First", false, 0)), None, &settings, true, now + 1));
+
+        // A missing device is found once it's online, by name or loosely
+        let missing = Failure::new(Command::PlayPause, SpotifyError::UnknownDevice("~desk".to_string()), now);
+        let devices: ConnectDeviceList = serde_json::from_str(r#"{"devices": [{"id": "desktop",
+            "is_active": false, "is_restricted": false, "name": "Desktop", "type": "Computer",
+            "volume_percent": 50}]}"#).unwrap();
+        assert!(!missing.resolved(None, Some(&Default::default()), &settings, true, now + 1));
+        assert!(missing.resolved(None, Some(&devices), &settings, true, now + 1));
+        // ...or by alias, though Spotify lists it by its own name
+        let aliased = Failure::new(Command::PlayPause, SpotifyError::UnknownDevice("Office".to_string()), now);
+        assert!(!aliased.resolved(None, Some(&devices), &settings, true, now + 1));
+        let office = DeviceSettings {
+            aliases: vec![("Desktop".to_string(), "Office".to_string())],
+            ..Default::default()
+        };
+        assert!(aliased.resolved(None, Some(&devices), &office, true, now + 1));
+        let by_name = Failure::new(Command::PlayPause, SpotifyError::UnknownDevice("desktop".to_string()), now);
+        assert!(by_name.resolved(None, Some(&devices), &office, true, now + 1));
+
+        // A playlist that's gone isn't a missing device, and stays until
+        // the failure times out
+        let gone = Failure::new(Command::SaveTrack, SpotifyError::Rejected {
+            status: 404, message: "Not found.".to_string(), reason: None,
+        }, now);
+        assert_eq!(gone.message(), "Spotify couldn't save the track \u{2014} it may have been deleted");
+        assert!(!gone.resolved(Some(&player_state("First", true, 0)), Some(&devices), &settings, true, now + 1));
+        assert!(gone.resolved(None, None, &settings, true, now + FAILURE_TIMEOUT + 1));
+        let skipped = Failure::new(Command::SkipNext, SpotifyError::Rejected {
+            status: 404, message: "Not found.".to_string(), reason: None,
+        }, now);
+        assert_eq!(skipped.message(), "No active device \u{2014} pick one below");
+        assert!(skipped.resolved(Some(&player_state("First", true, 0)), None, &settings, true, now + 1));
+
+        // Nothing to save goes away once a context plays
+        let nothing = Failure::new(Command::SavePreset, SpotifyError::NothingToSave, now);
+        assert_eq!(nothing.message(), "Nothing to save as a preset \u{2014} play a playlist or album");
+        let mut state = player_state("First", true, 0);
+        assert!(!nothing.resolved(Some(&state), None, &settings, true, now + 1));
+        state.context = serde_json::from_str(r#"{"uri": "spotify:album:album"}"#).unwrap();
+        assert!(nothing.resolved(Some(&state), None, &settings, true, now + 1));
+
+        // Surprise Me without presets stays until it times out
+        let empty = Failure::new(Command::Surprise(Some("Focus".to_string())),
+                                 SpotifyError::NoPresets(Some("Focus".to_string())), now);
+        assert_eq!(empty.message(), "No presets in Focus to choose from");
+        assert!(!empty.resolved(Some(&state), Some(&devices), &settings, true, now + 1));
+        assert!(empty.resolved(None, None, &settings, true, now + FAILURE_TIMEOUT + 1));
+
+        // Not being able to remove a track goes away with the context
+        let album = "spotify:album:album".to_string();
+        let not_mine = Failure::new(Command::RemoveTrack, SpotifyError::NotYourPlaylist(album.clone()), now);
+        assert_eq!(not_mine.message(), "Tracks can only be removed from your own playlists");
+        let mut state = player_state("First", true, 0);
+        state.context = serde_json::from_str(&format!(r#"{{"uri": "{}"}}"#, album)).unwrap();
+        assert!(!not_mine.resolved(Some(&state), None, &settings, true, now + 1));
+        state.context = None;
+        assert!(not_mine.resolved(Some(&state), None, &settings, true, now + 1));
+    }
+
+    #[test]
+    fn test_player_actions() {
+        let json = r#"{"timestamp": 0,
+                       "device": {"id": "desktop", "is_active": true, "is_restricted": false,
+                                  "name": "Desktop", "type": "Computer", "volume_percent": 50},
+                       "progress_ms": 0,
+                       "is_playing": true,
+                       "item": null,
+                       "shuffle_state": false,
+                       "repeat_state": "off",
+                       "context": null,
+                       "actions": {"disallows": {"resuming": true, "skipping_prev": true}}}"#;
+        let mut state: PlayerState = serde_json::from_str(json).unwrap();
+        assert!(state.allows(PlayerAction::Pause));
+        assert!(!state.allows(PlayerAction::Resume));
+        assert!(!state.allows(PlayerAction::SkipPrev));
+        assert!(state.allows(PlayerAction::SkipNext));
+        assert_eq!(player_action(&Command::PlayPause, &state), Some(PlayerAction::Pause));
+        assert_eq!(player_action(&Command::Preset(Preset::new("Album", "spotify:album:a")), &state),
+                   Some(PlayerAction::InterruptPlayback));
+        assert_eq!(player_action(&Command::SaveTrack, &state), None);
+
+        // Restricted devices only allow moving playback elsewhere
+        state.device.is_restricted = true;
+        assert!(!state.allows(PlayerAction::Pause));
+        assert!(!state.allows(PlayerAction::Volume));
+        assert!(state.allows(PlayerAction::TransferPlayback));
+
+        // Missing actions allow everything
+        let state = player_state("First", true, 0);
+        assert_eq!(state.actions, Default::default());
+        assert!(state.allows(PlayerAction::SkipPrev));
+    }
+
+    #[test]
+    fn test_episode() {
+        let json = r#"{"timestamp": 0,
+                       "device": {"id": "desktop", "is_active": true, "is_restricted": false,
+                                  "name": "Desktop", "type": "Computer", "volume_percent": 50},
+                       "progress_ms": 60000,
+                       "is_playing": true,
+                       "item": {"type": "episode", "duration_ms": 1800000, "name": "Pilot",
+                                "uri": "spotify:episode:pilot",
+                                "show": {"name": "The Show", "publisher": "Network",
+                                         "uri": "spotify:show:show"}},
+                       "shuffle_state": false,
+                       "repeat_state": "off",
+                       "context": null,
+                       "currently_playing_type": "episode"}"#;
+        let state: PlayerState = serde_json::from_str(json).unwrap();
+        let flat = flatten_player_state(Some(&state)).unwrap();
+        assert_eq!(flat.track, "Pilot");
+        assert_eq!(flat.artist, "Network");
+        assert_eq!(flat.album, "The Show");
+        assert_eq!(flat.uri, "spotify:episode:pilot");
+        assert!(flat.is_episode);
+        assert_eq!(quick_save_context(&state), Some("spotify:show:show".to_string()));
+
+        // Tracks without artists are fine too
+        let mut state = player_state("First", true, 0);
+        if let Some(ConnectPlaybackItem::Track(ref mut track)) = state.item {
+            track.artists.clear();
+        }
+        assert_eq!(state.item.as_ref().unwrap().artist(), None);
+        assert_eq!(flatten_player_state(Some(&state)).unwrap().artist, "");
+        assert!(!flatten_player_state(Some(&state)).unwrap().is_episode);
+        assert_eq!(quick_save_context(&state), None);
+    }
+
+    fn episode_state(name: &str, progress_ms: u64) -> PlayerState {
+        let mut state = player_state("First", false, progress_ms);
+        let json = format!(r#"{{"type": "episode", "duration_ms": 1800000, "name": "{}",
+                                "uri": "spotify:episode:{}",
+                                "show": {{"name": "The Show", "publisher": "Network",
+                                          "uri": "spotify:show:show"}}}}"#, name, name);
+        state.item = Some(serde_json::from_str(&json).unwrap());
+        state
+    }
+
+    #[test]
+    fn test_resume_history() {
+        let path = ::std::env::temp_dir().join(format!("connectr_resume_{}.json", ::std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = ::std::fs::remove_file(&path);
+        let mut history = ResumeHistory::load(Some(&path));
+        assert!(history.points().is_empty());
+
+        // Short tracks and unstarted items aren't worth resuming
+        assert!(!history.remember(None));
+        assert!(!history.remember(Some(&player_state("First", true, 60000))));
+        assert!(!history.remember(Some(&episode_state("pilot", 0))));
+
+        assert!(history.remember(Some(&episode_state("pilot", 754000))));
+        assert!(history.remember(Some(&episode_state("second", 1000))));
+        assert_eq!(history.points()[0].uri, "spotify:episode:second");
+        assert_eq!(history.points()[1].label(), "pilot (12:34)");
+
+        // Leaving an item again moves it to the top
+        assert!(history.remember(Some(&episode_state("pilot", 800000))));
+        assert_eq!(history.points()[0].position_ms, 800000);
+        assert_eq!(history.points().len(), 2);
+
+        // Saved between runs
+        let loaded = ResumeHistory::load(Some(&path));
+        assert_eq!(loaded.points(), history.points());
+
+        // Finished items are forgotten
+        assert!(history.remember(Some(&episode_state("pilot", 1790000))));
+        assert_eq!(history.points().len(), 1);
+        assert!(!history.remember(Some(&episode_state("pilot", 1790000))));
+
+        for i in 0..(RESUME_MAX_ENTRIES + 5) {
+            history.remember(Some(&episode_state(&format!("ep{}", i), 1000)));
+        }
+        assert_eq!(history.points().len(), RESUME_MAX_ENTRIES);
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_resume_context() {
+        let path = ::std::env::temp_dir().join(format!("connectr_context_{}.json", ::std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        {
+            let mut file = ::std::fs::File::create(&path).unwrap();
+            let _ = ::std::io::Write::write_all(&mut file, br#"{"points": [{"uri": "spotify:episode:pilot",
+                "name": "pilot", "position_ms": 1000, "duration_ms": 1800000}]}"#);
+        }
+        let mut history = ResumeHistory::load(Some(&path));
+        assert_eq!(history.points().len(), 1);
+
+        let mut state = player_state("Chapter3", true, 95000);
+        state.context = serde_json::from_str(r#"{"uri": "spotify:user:alice:playlist:book"}"#).unwrap();
+        let contexts = vec!["spotify:playlist:book".to_string()];
+        history.follow_context(Some(&state), &[]);
+        assert_eq!(history.context_position("spotify:playlist:book"), None);
+        history.follow_context(Some(&state), &contexts);
+        assert_eq!(history.context_position("spotify:playlist:book"), Some(&ContextPosition {
+            track_uri: "spotify:track:Chapter3".to_string(),
+            position_ms: 95000,
+        }));
+
+        // Only presets that ask to resume start where they were left
+        let mut preset = Preset::parse("Book", "spotify:playlist:book").unwrap();
+        assert_eq!(history.resume_preset(&preset), preset);
+        preset.options.resume = true;
+        let resumed = history.resume_preset(&preset);
+        assert_eq!(resumed.offset, Some(PresetOffset::Uri("spotify:track:Chapter3".to_string())));
+        assert_eq!(resumed.position_ms, Some(95000));
+
+        // Saved between runs, with the resume points
+        history.save_contexts();
+        let loaded = ResumeHistory::load(Some(&path));
+        assert_eq!(loaded.points().len(), 1);
+        assert_eq!(loaded.resume_preset(&preset), resumed);
+        let _ = ::std::fs::remove_file(&path);
+    }
+}
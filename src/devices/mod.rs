@@ -0,0 +1,186 @@
+// Device preferences.
+//
+// The [devices] section of connectr.ini picks a default device and how the
+// device list is shown, and [device_aliases] gives devices friendlier names:
+//
+//   [devices]
+//   default = Living Room
+//   order = Living Room, Kitchen, Desktop
+//   hidden = Old Phone, Web Player (Chrome)
+//
+//   [device_aliases]
+//   Echo Dot-4F2A = Kitchen
+//
+// Devices can be named by Spotify's name ignoring case, by ID, or by alias
+// everywhere.  Names are usually the better choice, since Spotify gives a
+// device a new ID now and then.  A name starting with ~ is matched loosely,
+// so "~living" finds "Living Room TV", and "~kitchen echo" finds
+// "Echo (Kitchen)".
+//
+// The [transfers] section holds rules that move playback when a device
+// comes online or goes away:
+//
+//   Living Room = appears Living Room, to Living Room
+//   Phone Gone = leaves My Phone, to Desktop, play
+//
+// A rule starts with "appears" or "leaves" and the device, then names the
+// device to move to.  "from <device>" only moves playback away from that
+// device, and "play" starts playing instead of keeping the playback state.
+// When several rules match, the one listed first wins.
+
+#[cfg(test)]
+mod test;
+
+mod transfer;
+pub use self::transfer::{DeviceEvent, DeviceEventKind, TransferRule, device_events};
+
+use super::{ConnectDevice, ConnectDeviceList};
+
+/// How devices are named, listed and chosen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceSettings {
+    /// Spotify's name or the ID of a device, and the name to show instead
+    pub aliases: Vec<(String, String)>,
+    /// Devices left out of the device list, unless they are playing
+    pub hidden: Vec<String>,
+    /// Devices listed first, in this order.  The rest follow in the order
+    /// Spotify reports them.
+    pub order: Vec<String>,
+    /// Where to play when nothing is playing anywhere
+    pub default: Option<String>,
+}
+
+impl DeviceSettings {
+    /// Parse a comma-separated list of devices, as in `order` and `hidden`.
+    pub fn parse_list(value: &str) -> Vec<String> {
+        value.split(",")
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty())
+            .collect()
+    }
+
+    /// The name to show for a device instead of Spotify's, if it has one.
+    pub fn alias(&self, device: &ConnectDevice) -> Option<&str> {
+        self.aliases.iter()
+            .find(|&&(ref key, _)| {
+                device.id.as_ref() == Some(key) || device.name.to_lowercase() == key.to_lowercase()
+            })
+            .map(|&(_, ref alias)| alias.as_str())
+    }
+
+    /// Whether `name` is the device's ID, name or alias, ignoring case.
+    pub fn is_named(&self, device: &ConnectDevice, name: &str) -> bool {
+        let name = name.to_lowercase();
+        device.id.as_ref().map(|id| id.to_lowercase()) == Some(name.clone()) ||
+            device.name.to_lowercase() == name ||
+            self.alias(device).map(|a| a.to_lowercase()) == Some(name)
+    }
+
+    /// Like `is_named`, but names starting with ~ are matched loosely.
+    pub fn matches(&self, device: &ConnectDevice, name: &str) -> bool {
+        if !name.starts_with('~') {
+            return self.is_named(device, name);
+        }
+        fuzzy_score(&device.name, &name[1..]) > 0 ||
+            self.alias(device).map(|a| fuzzy_score(a, &name[1..]) > 0).unwrap_or(false)
+    }
+
+    /// Find a device by ID, by name ignoring case, or by alias.  Names
+    /// starting with ~ are matched loosely, see `fuzzy_score`.
+    pub fn find<'a>(&self, devices: &'a ConnectDeviceList, name: &str) -> Option<&'a ConnectDevice> {
+        if name.starts_with('~') {
+            return self.find_fuzzy(devices, &name[1..]);
+        }
+        find_device(devices, name)
+            .or_else(|| devices.devices.iter().find(|d| self.is_named(d, name)))
+    }
+
+    /// The device whose name or alias best matches `name` loosely.  Among
+    /// equally good matches, the first one listed wins.
+    fn find_fuzzy<'a>(&self, devices: &'a ConnectDeviceList, name: &str) -> Option<&'a ConnectDevice> {
+        let mut best: Option<(&ConnectDevice, u32)> = None;
+        for device in &devices.devices {
+            let score = match self.alias(device) {
+                Some(alias) => fuzzy_score(&device.name, name).max(fuzzy_score(alias, name)),
+                None => fuzzy_score(&device.name, name),
+            };
+            match best {
+                Some((_, top)) if top >= score => {
+                    if top == score && score > 0 {
+                        info!("Device {} also matches {}", device.name, name);
+                    }
+                },
+                _ if score > 0 => best = Some((device, score)),
+                _ => {},
+            }
+        }
+        best.map(|(device, _)| device)
+    }
+
+    /// The default device, if it's online.
+    pub fn default_device<'a>(&self, devices: &'a ConnectDeviceList) -> Option<&'a ConnectDevice> {
+        self.default.as_ref().and_then(|name| self.find(devices, name))
+    }
+
+    pub fn is_hidden(&self, device: &ConnectDevice) -> bool {
+        self.hidden.iter().any(|name| self.is_named(device, name))
+    }
+
+    /// The device list as it's shown: hidden devices left out unless they
+    /// are playing, devices from `order` first, and aliases instead of
+    /// Spotify's names.
+    pub fn arrange(&self, list: ConnectDeviceList) -> ConnectDeviceList {
+        let mut devices: Vec<ConnectDevice> = list.devices.into_iter()
+            .filter(|d| d.is_active || !self.is_hidden(d))
+            .collect();
+        // Stable, so unlisted devices keep Spotify's order
+        devices.sort_by_key(|d| {
+            self.order.iter().position(|name| self.is_named(d, name)).unwrap_or(self.order.len())
+        });
+        for device in devices.iter_mut() {
+            if let Some(alias) = self.alias(device).map(|a| a.to_string()) {
+                device.name = alias;
+            }
+        }
+        ConnectDeviceList { devices: devices }
+    }
+}
+
+/// Find a device by ID, or by Spotify's name ignoring case.
+pub fn find_device<'a>(devices: &'a ConnectDeviceList, name: &str) -> Option<&'a ConnectDevice> {
+    devices.devices.iter()
+        .find(|d| d.id.as_ref().map(|id| id == name).unwrap_or(false))
+        .or_else(|| devices.devices.iter().find(|d| d.name.to_lowercase() == name.to_lowercase()))
+}
+
+/// Letters and digits of each word, in lowercase.
+fn words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// How well a device name matches a loosely written one, ignoring case,
+/// spaces and punctuation: 3 if they are the same, 2 if the name starts
+/// with it, 1 if every word of it starts a word of the name, in any order,
+/// and 0 otherwise.
+pub fn fuzzy_score(device_name: &str, name: &str) -> u32 {
+    let device_words = words(device_name);
+    let name_words = words(name);
+    if name_words.is_empty() {
+        return 0;
+    }
+    if device_words == name_words {
+        3
+    }
+    else if device_words.concat().starts_with(&name_words.concat()) {
+        2
+    }
+    else if name_words.iter().all(|n| device_words.iter().any(|d| d.starts_with(n.as_str()))) {
+        1
+    }
+    else {
+        0
+    }
+}
@@ -0,0 +1,135 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn device(id: &str, name: &str, active: bool) -> ConnectDevice {
+        ConnectDevice {
+            id: Some(id.to_string()),
+            is_active: active,
+            is_restricted: false,
+            name: name.to_string(),
+            device_type: "Speaker".to_string(),
+            volume_percent: Some(50),
+        }
+    }
+
+    fn devices() -> ConnectDeviceList {
+        ConnectDeviceList { devices: vec![
+            device("phone-id", "Old Phone", false),
+            device("desktop-id", "Desktop", true),
+            device("echo-id", "Echo Dot-4F2A", false),
+            device("tv-id", "Living Room TV", false),
+            device("web-id", "Web Player (Chrome)", false),
+        ]}
+    }
+
+    fn settings() -> DeviceSettings {
+        DeviceSettings {
+            aliases: vec![("echo dot-4f2a".to_string(), "Kitchen".to_string()),
+                          ("tv-id".to_string(), "Living Room".to_string())],
+            hidden: DeviceSettings::parse_list("Old Phone, , web-id, Desktop"),
+            order: DeviceSettings::parse_list("living room,Kitchen"),
+            default: Some("Kitchen".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let settings = settings();
+        let list = devices();
+        let id = |name: &str| settings.find(&list, name).and_then(|d| d.id.clone());
+        assert_eq!(id("echo-id"), Some("echo-id".to_string()));
+        assert_eq!(id("echo dot-4F2A"), Some("echo-id".to_string()));
+        assert_eq!(id("KITCHEN"), Some("echo-id".to_string()));
+        assert_eq!(id("Living Room"), Some("tv-id".to_string()));
+        assert_eq!(id("Bedroom"), None);
+        assert_eq!(settings.default_device(&list).and_then(|d| d.id.clone()), Some("echo-id".to_string()));
+        assert!(DeviceSettings::default().default_device(&list).is_none());
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        assert_eq!(fuzzy_score("Living Room TV", "living-room tv"), 3);
+        assert_eq!(fuzzy_score("Living Room TV", "livingroom"), 2);
+        assert_eq!(fuzzy_score("Echo (Kitchen)", "kitchen echo"), 1);
+        assert_eq!(fuzzy_score("Echo (Kitchen)", "kit"), 1);
+        assert_eq!(fuzzy_score("Echo (Kitchen)", "bedroom"), 0);
+        assert_eq!(fuzzy_score("Echo (Kitchen)", " - "), 0);
+
+        let settings = settings();
+        let list = devices();
+        let id = |name: &str| settings.find(&list, name).and_then(|d| d.id.clone());
+        assert_eq!(id("~living"), Some("tv-id".to_string()));
+        assert_eq!(id("~echo dot"), Some("echo-id".to_string()));
+        // Aliases match loosely too, and the best match wins
+        assert_eq!(id("~kitch"), Some("echo-id".to_string()));
+        assert_eq!(id("~web player chrome"), Some("web-id".to_string()));
+        assert_eq!(id("~player"), Some("web-id".to_string()));
+        assert_eq!(id("~bedroom"), None);
+        // Without ~, names must match exactly
+        assert_eq!(id("living"), None);
+    }
+
+    #[test]
+    fn test_device_events() {
+        let old = devices();
+        let mut new = devices();
+        new.devices.remove(0);
+        new.devices.push(device("bedroom-id", "Bedroom", false));
+        // Same name with a new ID is the same device
+        new.devices[0].id = Some("desktop-id-2".to_string());
+        let events: Vec<String> = device_events(&old, &new).iter().map(|e| e.to_string()).collect();
+        assert_eq!(events, vec!["Device appeared: Bedroom", "Device disappeared: Old Phone"]);
+        assert!(device_events(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_transfer_rules() {
+        let rule = TransferRule::parse(" Phone Gone", "Leaves Old Phone, to ~desk, play").unwrap();
+        assert_eq!(rule, TransferRule {
+            name: "Phone Gone".to_string(),
+            event: DeviceEventKind::Disappeared,
+            device: "Old Phone".to_string(),
+            target: "~desk".to_string(),
+            from: None,
+            play: true,
+        });
+        assert!(TransferRule::parse("Bad", "arrives Kitchen, to Kitchen").is_err());
+        assert!(TransferRule::parse("Bad", "appears, to Kitchen").is_err());
+        assert!(TransferRule::parse("Bad", "appears Kitchen").is_err());
+        assert!(TransferRule::parse("Bad", "appears Kitchen, to Kitchen, loudly").is_err());
+
+        let settings = settings();
+        let list = devices();
+        let event = |kind: DeviceEventKind, idx: usize| {
+            DeviceEvent { kind: kind, device: list.devices[idx].clone() }
+        };
+        let phone = &list.devices[0];
+        let desktop = &list.devices[1];
+
+        // Playback only moves away from a device that leaves if it was playing there
+        assert!(rule.matches(&settings, &event(DeviceEventKind::Disappeared, 0), Some(phone)));
+        assert!(!rule.matches(&settings, &event(DeviceEventKind::Disappeared, 0), Some(desktop)));
+        assert!(!rule.matches(&settings, &event(DeviceEventKind::Disappeared, 0), None));
+        assert!(!rule.matches(&settings, &event(DeviceEventKind::Appeared, 0), Some(desktop)));
+        assert!(!rule.matches(&settings, &event(DeviceEventKind::Disappeared, 2), Some(phone)));
+
+        // ...and to a device that appears if something plays somewhere else
+        let kitchen = TransferRule::parse("Kitchen", "appears ~kitch, to Kitchen, from Desktop").unwrap();
+        assert!(kitchen.matches(&settings, &event(DeviceEventKind::Appeared, 2), Some(desktop)));
+        assert!(!kitchen.matches(&settings, &event(DeviceEventKind::Appeared, 2), Some(phone)));
+        assert!(!kitchen.matches(&settings, &event(DeviceEventKind::Appeared, 2), None));
+    }
+
+    #[test]
+    fn test_arrange() {
+        let list = settings().arrange(devices());
+        let names: Vec<&str> = list.devices.iter().map(|d| d.name.as_str()).collect();
+        // The playing device is shown even though it's hidden
+        assert_eq!(names, vec!["Living Room", "Kitchen", "Desktop"]);
+
+        // Without settings, nothing changes
+        assert_eq!(DeviceSettings::default().arrange(devices()).devices.len(), 5);
+        assert_eq!(DeviceSettings::default().arrange(devices()).devices[0].name, "Old Phone");
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use super::DeviceSettings;
+use super::super::{ConnectDevice, ConnectDeviceList};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceEventKind {
+    Appeared,
+    Disappeared,
+}
+
+/// A device that came online or went away between two device lists.
+#[derive(Clone, Debug)]
+pub struct DeviceEvent {
+    pub kind: DeviceEventKind,
+    pub device: ConnectDevice,
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DeviceEventKind::Appeared => write!(f, "Device appeared: {}", self.device.name),
+            DeviceEventKind::Disappeared => write!(f, "Device disappeared: {}", self.device.name),
+        }
+    }
+}
+
+/// Whether two entries are the same device.  IDs change now and then, so a
+/// device with the same name counts too.
+fn same_device(a: &ConnectDevice, b: &ConnectDevice) -> bool {
+    (a.id.is_some() && a.id == b.id) || a.name == b.name
+}
+
+/// Devices in `new` but not `old`, then devices in `old` but not `new`.
+pub fn device_events(old: &ConnectDeviceList, new: &ConnectDeviceList) -> Vec<DeviceEvent> {
+    let appeared = new.devices.iter()
+        .filter(|d| !old.devices.iter().any(|o| same_device(o, d)))
+        .map(|d| DeviceEvent { kind: DeviceEventKind::Appeared, device: d.clone() });
+    let disappeared = old.devices.iter()
+        .filter(|d| !new.devices.iter().any(|n| same_device(n, d)))
+        .map(|d| DeviceEvent { kind: DeviceEventKind::Disappeared, device: d.clone() });
+    appeared.chain(disappeared).collect()
+}
+
+/// Where to move playback when a device comes online or goes away.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferRule {
+    pub name: String,
+    pub event: DeviceEventKind,
+    /// The device that appears or disappears
+    pub device: String,
+    /// The device to move playback to
+    pub target: String,
+    /// Only move playback from this device
+    pub from: Option<String>,
+    /// Start playing after moving, instead of keeping the playback state
+    pub play: bool,
+}
+
+impl TransferRule {
+    /// Parse a rule from its key and value in connectr.ini, such as
+    /// "appears Living Room, to Living Room, play".
+    pub fn parse(name: &str, value: &str) -> Result<TransferRule, String> {
+        let mut fields = value.split(",").map(|f| f.trim()).filter(|f| !f.is_empty());
+        let (key, device) = split_field(fields.next().unwrap_or(""));
+        let event = match key.to_lowercase().as_str() {
+            "appears" => DeviceEventKind::Appeared,
+            "leaves" => DeviceEventKind::Disappeared,
+            _ => return Err(format!("Transfer rule {} must start with appears or leaves", name.trim())),
+        };
+        if device.is_empty() {
+            return Err(format!("Transfer rule {} has no device", name.trim()));
+        }
+        let mut rule = TransferRule {
+            name: name.trim().to_string(),
+            event: event,
+            device: device.to_string(),
+            target: String::new(),
+            from: None,
+            play: false,
+        };
+        for field in fields {
+            let (key, value) = split_field(field);
+            match (key.to_lowercase().as_str(), value) {
+                ("to", target) if !target.is_empty() => rule.target = target.to_string(),
+                ("from", from) if !from.is_empty() => rule.from = Some(from.to_string()),
+                ("play", "") => rule.play = true,
+                _ => return Err(format!("Unknown transfer rule field: {}", field)),
+            }
+        }
+        if rule.target.is_empty() {
+            return Err(format!("Transfer rule {} has no device to move to", rule.name));
+        }
+        Ok(rule)
+    }
+
+    /// Whether the rule applies to `event`, while playing on `playing_on`.
+    /// Playback is moved to a device that appears only if something is
+    /// playing elsewhere, and away from a device that leaves only if it
+    /// was playing there.
+    pub fn matches(&self, devices: &DeviceSettings, event: &DeviceEvent,
+                   playing_on: Option<&ConnectDevice>) -> bool {
+        let playing_on = match playing_on {
+            Some(device) => device,
+            None => return false,
+        };
+        if event.kind != self.event || !devices.matches(&event.device, &self.device) {
+            return false;
+        }
+        if let Some(ref from) = self.from {
+            if !devices.matches(playing_on, from) {
+                return false;
+            }
+        }
+        match event.kind {
+            DeviceEventKind::Appeared => !same_device(playing_on, &event.device),
+            DeviceEventKind::Disappeared => same_device(playing_on, &event.device),
+        }
+    }
+}
+
+/// Split "to Living Room" into "to" and "Living Room".
+fn split_field(field: &str) -> (&str, &str) {
+    match field.find(char::is_whitespace) {
+        Some(idx) => (&field[..idx], field[idx..].trim()),
+        None => (field, ""),
+    }
+}
@@ -21,6 +21,7 @@ pub enum HttpMethod {
     GET,
     POST,
     PUT,
+    DELETE,
 }
 
 pub type HttpErrorString = String;
@@ -84,6 +85,10 @@ pub fn http(url: &str, query: Option<&str>, body: Option<&str>,
                 }
             }
         },
+        HttpMethod::DELETE => {
+            headers.append("Content-Type: application/json").unwrap();
+            body.unwrap_or("").to_string()
+        },
         _ => { body.unwrap_or("").to_string() },
     };
     let mut data = data.as_bytes();
@@ -108,7 +113,14 @@ pub fn http(url: &str, query: Option<&str>, body: Option<&str>,
                 easy.post_field_size(data.len() as u64).unwrap();
             }
             HttpMethod::PUT => {
-                easy.put(true).unwrap();
+                // Setting a POST size makes newer libcurl send a POST
+                easy.upload(true).unwrap();
+                easy.in_filesize(data.len() as u64).unwrap();
+            }
+            HttpMethod::DELETE => {
+                // Sent like a POST, so the body goes along
+                easy.custom_request("DELETE").unwrap();
+                easy.post(true).unwrap();
                 easy.post_field_size(data.len() as u64).unwrap();
             }
             _ => {}
@@ -130,7 +142,7 @@ pub fn http(url: &str, query: Option<&str>, body: Option<&str>,
 
         {
             let mut transfer = easy.transfer();
-            if method == HttpMethod::POST || method == HttpMethod::PUT {
+            if method != HttpMethod::GET {
                 transfer.read_function(|buf| {
                     Ok(data.read(buf).unwrap_or(0))
                 }).unwrap();
@@ -0,0 +1,178 @@
+// Importing presets from the user's Spotify library.
+//
+// The web form and the command line both list the user's playlists and
+// saved albums.  Chosen entries become presets, each optionally paired with
+// one of the playlists as its quick-save playlist.  Only the [presets]
+// section of the config file is changed.
+
+#[cfg(test)]
+mod test;
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+use super::{SpotifyConnectr, LibraryEntry};
+use super::http;
+use super::preset::Preset;
+use super::uri::SpotifyUri;
+use super::settings::WEB_PORT;
+
+/// A library entry to import, by index, and the entry to quick-save to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportChoice {
+    pub entry: usize,
+    pub quicksave: Option<usize>,
+}
+
+/// Presets for the chosen entries.  Quick-save entries that aren't
+/// playlists are ignored.
+pub fn choices_to_presets(library: &[LibraryEntry],
+                          choices: &[ImportChoice]) -> Vec<(Preset, Option<SpotifyUri>)> {
+    choices.iter().filter_map(|choice| {
+        let entry = library.get(choice.entry)?;
+        let mut preset = match Preset::parse(&entry.name, &entry.uri) {
+            Ok(p) => p,
+            Err(e) => { warn!("Not importing {}: {}", entry.name, e); return None; },
+        };
+        // Spotify names aren't group names, even with a slash
+        preset.name = entry.name.clone();
+        preset.group = None;
+        let quicksave = choice.quicksave
+            .and_then(|idx| library.get(idx))
+            .filter(|e| e.is_playlist)
+            .and_then(|e| e.uri.parse::<SpotifyUri>().ok());
+        Some((preset, quicksave))
+    }).collect()
+}
+
+/// Parse a command line selection like "1 3=2 5", counting from 1.  "3=2"
+/// imports entry 3 and quick-saves from it to entry 2.
+pub fn parse_selection(selection: &str, count: usize) -> Result<Vec<ImportChoice>, String> {
+    let index = |num: &str| -> Result<usize, String> {
+        match num.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= count => Ok(n - 1),
+            _ => Err(format!("Not a number from 1 to {}: {}", count, num)),
+        }
+    };
+    selection.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|choice| {
+            let mut parts = choice.splitn(2, "=");
+            let entry = index(parts.next().unwrap())?;
+            let quicksave = match parts.next() {
+                Some(save) => Some(index(save)?),
+                None => None,
+            };
+            Ok(ImportChoice { entry: entry, quicksave: quicksave })
+        }).collect()
+}
+
+/// Choices from the submitted web form, or None if it was canceled.
+pub fn parse_web_form(library: &[LibraryEntry],
+                      config: &BTreeMap<String,String>) -> Option<Vec<ImportChoice>> {
+    if config.contains_key("cancel") {
+        return None;
+    }
+    Some((0..library.len())
+         .filter(|idx| config.contains_key(&format!("import_{}", idx)))
+         .map(|idx| {
+             ImportChoice {
+                 entry: idx,
+                 quicksave: config.get(&format!("save_{}", idx)).and_then(|s| s.parse().ok()),
+             }
+         }).collect())
+}
+
+/// Whether the entry is already one of the presets.
+fn is_preset(entry: &LibraryEntry, presets: &[Preset]) -> bool {
+    match entry.uri.parse::<SpotifyUri>() {
+        Ok(uri) => presets.iter().any(|p| p.uri() == uri.to_string()),
+        Err(_) => false,
+    }
+}
+
+fn web_form(library: &[LibraryEntry], presets: &[Preset]) -> String {
+    let mut playlists = String::from(r#"<option value="">None</option>"#);
+    for (idx, entry) in library.iter().enumerate().filter(|&(_, e)| e.is_playlist) {
+        playlists.push_str(&format!(r#"<option value="{}">{}</option>"#, idx, html_escape(&entry.name)));
+    }
+    let mut form = format!(r###"{}
+<!DOCTYPE HTML>
+<html><head><title>Import Connectr Presets</title><style>
+tr:nth-child(even) {{ background: #f2f2f2; }}
+th {{ border-bottom: 1px solid #ddd; text-align: left; }}
+</style>
+<meta http-equiv="cache-control" content="no-cache" /><meta http-equiv="expires" content="0"></head>
+<body><h2>Import Presets</h2>
+    <div style="background-color: #9e9e9e; padding: 10px 10px 10px 10px;"><strong>IMPORTANT:</strong> Do NOT close this window without importing or cancelling.  Connectr is paused internally until this is dismissed!</div><br/>
+    Tick the playlists and albums to add as presets.  Each can save tracks to one of your playlists when 'Quick-Save' is clicked while it plays.<br/><br/>
+<form method="POST" action="#" accept-charset="UTF-8"><table>
+<tr><th>Import</th><th style="width:350px;">Name</th><th style="width:200px;">By</th><th>Quick-save Playlist</th></tr>
+"###,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nCache-Control: no-cache, no-store, must-revalidate, max-age=0\r\n\r\n");
+    for (idx, entry) in library.iter().enumerate() {
+        let kind = match entry.is_playlist { true => "playlist", false => "album" };
+        let import = match is_preset(entry, presets) {
+            true => "(preset)".to_string(),
+            false => format!(r#"<input type="checkbox" name="import_{}">"#, idx),
+        };
+        form.push_str(&format!(
+            r###"<tr><td align="center">{}</td><td>{} <small>({})</small></td><td>{}</td><td><select name="save_{}">{}</select></td></tr>
+"###, import, html_escape(&entry.name), kind, html_escape(&entry.by), idx, playlists));
+    }
+    form.push_str(r###"
+<tr><td colspan="4"><br/><center><input type="submit" name="cancel" value="Cancel" style="height:50px; width: 300px; font-size:20px;"> &nbsp; <input type="submit" name="submit" value="Import Presets" style="height:50px; width: 300px; font-size:20px;"></center></td></tr>
+</table></form>
+</body></html>
+"###);
+    form
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// Let the user pick presets to import in a web browser.  Returns how many
+/// were added.
+pub fn import_web(spotify: &mut SpotifyConnectr) -> Result<usize, String> {
+    let library = spotify.request_library().map_err(|e| e.to_string())?;
+    let form = web_form(&library, spotify.get_presets());
+    let reply = format!("{}Presets imported.  You can close this window.",
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n");
+    let config = http::config_request_local_webserver(WEB_PORT, form, reply);
+    match parse_web_form(&library, &config) {
+        Some(choices) => spotify.import_presets(choices_to_presets(&library, &choices)),
+        None => Ok(0),
+    }
+}
+
+/// Let the user pick presets to import on the terminal.  Returns how many
+/// were added.
+pub fn import_cli(spotify: &mut SpotifyConnectr) -> Result<usize, String> {
+    let library = spotify.request_library().map_err(|e| e.to_string())?;
+    if library.is_empty() {
+        println!("No playlists or saved albums found.");
+        return Ok(0);
+    }
+    for (idx, entry) in library.iter().enumerate() {
+        let kind = match entry.is_playlist { true => "playlist", false => "album" };
+        let mark = match is_preset(entry, spotify.get_presets()) { true => "*", false => " " };
+        println!("{:>4}{} {} ({}, {})", idx + 1, mark, entry.name, kind, entry.by);
+    }
+    println!("\n* is already a preset.");
+    println!("Enter the numbers to import, like \"1 4 7\".  Write \"4=2\" to quick-save");
+    println!("from 4 to playlist 2.  Leave empty to cancel.");
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(0);
+        }
+        match parse_selection(&line, library.len()) {
+            Ok(choices) => return spotify.import_presets(choices_to_presets(&library, &choices)),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn library() -> Vec<LibraryEntry> {
+        let entry = |name: &str, uri: &str, is_playlist: bool| {
+            LibraryEntry {
+                name: name.to_string(),
+                uri: uri.to_string(),
+                by: "someone".to_string(),
+                is_playlist: is_playlist,
+            }
+        };
+        vec![
+            entry("Mixtape", "spotify:user:alice:playlist:mixtape", true),
+            entry("Favorites", "spotify:playlist:favorites", true),
+            entry("Bakesale", "spotify:album:bakesale", false),
+        ]
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("1  3=2\n", 3), Ok(vec![
+            ImportChoice { entry: 0, quicksave: None },
+            ImportChoice { entry: 2, quicksave: Some(1) },
+        ]));
+        assert_eq!(parse_selection("1,2", 3).unwrap().len(), 2);
+        assert_eq!(parse_selection("", 3), Ok(vec![]));
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("1=x", 3).is_err());
+    }
+
+    #[test]
+    fn test_import_choices() {
+        let library = library();
+        let mut config = BTreeMap::new();
+        config.insert("import_0".to_string(), "on".to_string());
+        config.insert("save_0".to_string(), "".to_string());
+        config.insert("import_2".to_string(), "on".to_string());
+        config.insert("save_2".to_string(), "1".to_string());
+        config.insert("save_1".to_string(), "0".to_string());
+        let choices = parse_web_form(&library, &config).unwrap();
+        assert_eq!(choices, vec![
+            ImportChoice { entry: 0, quicksave: None },
+            ImportChoice { entry: 2, quicksave: Some(1) },
+        ]);
+
+        // URIs are cleaned up, and only playlists can be quick-saved to
+        let presets = choices_to_presets(&library, &choices);
+        assert_eq!(presets, vec![
+            (Preset::new("Mixtape", "spotify:playlist:mixtape"), None),
+            (Preset::new("Bakesale", "spotify:album:bakesale"), "spotify:playlist:favorites".parse().ok()),
+        ]);
+        let album_save = [ImportChoice { entry: 0, quicksave: Some(2) }];
+        assert_eq!(choices_to_presets(&library, &album_save)[0].1, None);
+
+        config.insert("cancel".to_string(), "Cancel".to_string());
+        assert_eq!(parse_web_form(&library, &config), None);
+    }
+}
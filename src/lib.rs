@@ -1,5 +1,14 @@
+pub mod controller;
+pub mod devices;
 pub mod http;
+pub mod import;
+pub mod menu;
+pub mod preset;
+pub mod quicksave;
+pub mod recording;
 pub mod settings;
+pub mod state_file;
+pub mod uri;
 pub mod webapi;
 
 // Re-export webapi interface to connectr root
@@ -20,6 +29,9 @@ pub mod osx;
 #[cfg(target_os = "windows")]
 pub mod win;
 
+#[cfg(target_os = "linux")]
+pub mod linux;
+
 #[cfg(target_os = "macos")]
 #[macro_use]
 extern crate objc;
@@ -29,27 +41,30 @@ extern crate serde_derive;
 
 #[derive(Clone, Copy)]
 pub struct SpotifyEndpoints<'a> {
-    scopes: &'a str,
-    scopes_version: u32,
-    authorize: &'a str,
-    token: &'a str,
-    devices: &'a str,
-    player_state: &'a str,
-    play: &'a str,
-    pause: &'a str,
-    next: &'a str,
-    previous: &'a str,
-    seek: &'a str,
-    volume: &'a str,
-    shuffle: &'a str,
-    repeat: &'a str,
-    player: &'a str,
-    add_to_playlist: &'a str,
+    pub scopes: &'a str,
+    pub scopes_version: u32,
+    pub authorize: &'a str,
+    pub token: &'a str,
+    pub devices: &'a str,
+    pub player_state: &'a str,
+    pub play: &'a str,
+    pub pause: &'a str,
+    pub next: &'a str,
+    pub previous: &'a str,
+    pub seek: &'a str,
+    pub volume: &'a str,
+    pub shuffle: &'a str,
+    pub repeat: &'a str,
+    pub player: &'a str,
+    pub add_to_playlist: &'a str,
+    pub metadata: &'a str,
+    pub playlists: &'a str,
+    pub saved_albums: &'a str,
 }
 
 pub const SPOTIFY_API: SpotifyEndpoints = SpotifyEndpoints {
-    scopes: "user-read-private streaming user-read-playback-state playlist-modify-public playlist-modify-private",
-    scopes_version: 1, // increment if scopes change
+    scopes: "user-read-private streaming user-read-playback-state playlist-modify-public playlist-modify-private playlist-read-private user-library-read",
+    scopes_version: 2, // increment if scopes change
     authorize: "https://accounts.spotify.com/en/authorize",
     token: "https://accounts.spotify.com/api/token",
     devices: "https://api.spotify.com/v1/me/player/devices",
@@ -63,18 +78,21 @@ pub const SPOTIFY_API: SpotifyEndpoints = SpotifyEndpoints {
     shuffle: "https://api.spotify.com/v1/me/player/shuffle",
     repeat: "https://api.spotify.com/v1/me/player/repeat",
     player: "https://api.spotify.com/v1/me/player",
-    add_to_playlist: "https://api.spotify.com/v1/users",
+    add_to_playlist: "https://api.spotify.com/v1/playlists",
+    metadata: "https://api.spotify.com/v1",
+    playlists: "https://api.spotify.com/v1/me/playlists",
+    saved_albums: "https://api.spotify.com/v1/me/albums",
 };
 
 #[cfg(target_os = "linux")]
-pub type Object = u64;
+pub type Object = linux::Object;
 #[cfg(target_os = "windows")]
 pub type Object = u32;
 #[cfg(target_os = "macos")]
 pub type Object = osx::Object;
 
 #[cfg(target_os = "linux")]
-pub type StatusBar = DummyStatusBar;
+pub type StatusBar = linux::LinuxStatusBar;
 #[cfg(target_os = "macos")]
 pub type StatusBar = osx::OSXStatusBar;
 #[cfg(target_os = "windows")]
@@ -83,11 +101,12 @@ pub type StatusBar = win::WindowsStatusBar;
 pub type MenuItem = *mut Object;
 pub trait TStatusBar {
     type S: TStatusBar;
-    fn new(tx: Sender<String>) -> Self::S;
+    fn new(tx: Sender<CommandMessage>) -> Self::S;
     fn can_redraw(&mut self) -> bool;
     fn clear_items(&mut self);
     fn add_separator(&mut self);
     fn add_label(&mut self, label: &str);
+    fn supports_submenus(&self) -> bool;
     fn add_submenu(&mut self, label: &str, callback: NSCallback) -> *mut Object;
     fn add_item(&mut self, menu: Option<*mut Object>,item: &str, callback: NSCallback, selected: bool) -> *mut Object;
     fn add_quit(&mut self, label: &str);
@@ -97,18 +116,23 @@ pub trait TStatusBar {
     fn set_tooltip(&mut self, text: &str);
     fn register_url_handler(&mut self);
     fn run(&mut self, block: bool);
+    /// Whether the user chose to quit from a menu the main loop doesn't
+    /// otherwise hear from.
+    fn quit_requested(&self) -> bool { false }
 }
 
 use std::sync::mpsc::Sender;
-pub type NSCallback = Box<dyn Fn(u64, &Sender<String>)>;
+use controller::CommandMessage;
+pub type NSCallback = Box<dyn Fn(u64, &Sender<CommandMessage>)>;
 
 pub struct DummyStatusBar {}
 impl TStatusBar for DummyStatusBar {
     type S = DummyStatusBar;
-    fn new(_: Sender<String>) -> Self::S { DummyStatusBar {} }
+    fn new(_: Sender<CommandMessage>) -> Self::S { DummyStatusBar {} }
     fn can_redraw(&mut self) -> bool { true }
     fn clear_items(&mut self) {}
     fn add_separator(&mut self) {}
+    fn supports_submenus(&self) -> bool { true }
     fn add_submenu(&mut self, _: &str, _: NSCallback) -> *mut Object { 0 as *mut Object }
     fn add_label(&mut self, _: &str) {}
     fn add_item(&mut self, _: Option<*mut Object>, _: &str, _: NSCallback, _: bool) -> *mut Object{ 0 as *mut Object }
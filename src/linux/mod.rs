@@ -0,0 +1,267 @@
+extern crate ksni;
+
+pub use ::TStatusBar;
+pub use ::NSCallback;
+use ::controller::CommandMessage;
+
+use std::path::Path;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+pub type Object = u64;
+
+// Menu entries as they are handed over to the D-Bus service thread.  The
+// callbacks themselves stay on the main thread (they aren't Send), so the
+// service only knows an entry's ID and reports clicks back by ID.
+#[derive(Clone)]
+enum TrayEntry {
+    Label(String),
+    Separator,
+    Item { id: u64, label: String, checked: bool },
+    Submenu { id: u64, label: String, entries: Vec<TrayEntry> },
+    Quit(String),
+}
+
+struct ConnectrTray {
+    entries: Vec<TrayEntry>,
+    tooltip: String,
+    icon_path: String,
+    clicks: Sender<u64>,
+    /// Set by Quit, so the main loop shuts down like the other frontends
+    /// instead of exiting from the D-Bus thread
+    quit: Arc<AtomicBool>,
+}
+
+fn tray_menu(entries: &Vec<TrayEntry>) -> Vec<ksni::MenuItem<ConnectrTray>> {
+    use self::ksni::menu::{StandardItem, CheckmarkItem, SubMenu};
+    entries.iter().map(|entry| {
+        match entry {
+            &TrayEntry::Label(ref label) => StandardItem {
+                label: label.clone(),
+                enabled: false,
+                ..Default::default()
+            }.into(),
+            &TrayEntry::Separator => ksni::MenuItem::Separator,
+            &TrayEntry::Item { id, ref label, checked } => CheckmarkItem {
+                label: label.clone(),
+                checked: checked,
+                activate: Box::new(move |tray: &mut ConnectrTray| {
+                    let _ = tray.clicks.send(id);
+                }),
+                ..Default::default()
+            }.into(),
+            &TrayEntry::Submenu { ref label, ref entries, .. } => SubMenu {
+                label: label.clone(),
+                submenu: tray_menu(entries),
+                ..Default::default()
+            }.into(),
+            &TrayEntry::Quit(ref label) => StandardItem {
+                label: label.clone(),
+                activate: Box::new(|tray: &mut ConnectrTray| {
+                    tray.quit.store(true, Ordering::SeqCst);
+                }),
+                ..Default::default()
+            }.into(),
+        }
+    }).collect()
+}
+
+impl ksni::Tray for ConnectrTray {
+    fn id(&self) -> String {
+        "connectr".to_string()
+    }
+    fn title(&self) -> String {
+        "Connectr".to_string()
+    }
+    fn icon_theme_path(&self) -> String {
+        self.icon_path.clone()
+    }
+    fn icon_name(&self) -> String {
+        "connectr_80px_300dpi".to_string()
+    }
+    fn tool_tip(&self) -> ksni::ToolTip {
+        ksni::ToolTip {
+            title: "Connectr".to_string(),
+            description: self.tooltip.clone(),
+            ..Default::default()
+        }
+    }
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        tray_menu(&self.entries)
+    }
+}
+
+pub struct LinuxStatusBar {
+    handle: ksni::Handle<ConnectrTray>,
+    tx: Sender<CommandMessage>,
+    rx_click: Receiver<u64>,
+    idx: u64,
+    entries: Vec<TrayEntry>,
+    tooltip: String,
+    callbacks: BTreeMap<u64, NSCallback>,
+    dirty: bool,
+    quit: Arc<AtomicBool>,
+}
+
+impl LinuxStatusBar {
+    fn entry_mut(entries: &mut Vec<TrayEntry>, id: u64) -> Option<&mut TrayEntry> {
+        for entry in entries.iter_mut() {
+            let matches = match *entry {
+                TrayEntry::Item { id: item_id, .. } |
+                TrayEntry::Submenu { id: item_id, .. } => item_id == id,
+                _ => false,
+            };
+            if matches {
+                return Some(entry);
+            }
+            if let TrayEntry::Submenu { ref mut entries, .. } = *entry {
+                if let Some(child) = Self::entry_mut(entries, id) {
+                    return Some(child);
+                }
+            }
+        }
+        None
+    }
+    fn next_id(&mut self) -> u64 {
+        // IDs start at 1 so a null pointer never refers to a real item
+        self.idx += 1;
+        self.idx
+    }
+    fn set_checked(&mut self, sender: u64, selected: bool) {
+        if let Some(&mut TrayEntry::Item { ref mut checked, .. }) =
+            Self::entry_mut(&mut self.entries, sender) {
+                *checked = selected;
+                self.dirty = true;
+            }
+    }
+}
+
+impl TStatusBar for LinuxStatusBar {
+    type S = LinuxStatusBar;
+    fn new(tx: Sender<CommandMessage>) -> LinuxStatusBar {
+        let (tx_click, rx_click) = channel::<u64>();
+        // Icon is looked up by name in the first search path that has it.
+        let icon_path = ::search_paths().into_iter().find(|dir| {
+            Path::new(dir).join("connectr_80px_300dpi.png").exists()
+        }).unwrap_or(String::new());
+        let quit = Arc::new(AtomicBool::new(false));
+        let service = ksni::TrayService::new(ConnectrTray {
+            entries: Vec::new(),
+            tooltip: String::new(),
+            icon_path: icon_path,
+            clicks: tx_click,
+            quit: quit.clone(),
+        });
+        let handle = service.handle();
+        service.spawn();
+        LinuxStatusBar {
+            handle: handle,
+            tx: tx,
+            rx_click: rx_click,
+            idx: 0,
+            entries: Vec::new(),
+            tooltip: String::new(),
+            callbacks: BTreeMap::new(),
+            dirty: true,
+            quit: quit,
+        }
+    }
+    fn can_redraw(&mut self) -> bool {
+        true
+    }
+    fn clear_items(&mut self) {
+        self.entries.clear();
+        self.callbacks.clear();
+        self.dirty = true;
+    }
+    fn set_tooltip(&mut self, text: &str) {
+        self.tooltip = text.to_string();
+        self.dirty = true;
+    }
+    fn add_label(&mut self, label: &str) {
+        self.entries.push(TrayEntry::Label(label.to_string()));
+        self.dirty = true;
+    }
+    fn add_quit(&mut self, label: &str) {
+        self.entries.push(TrayEntry::Quit(label.to_string()));
+        self.dirty = true;
+    }
+    fn add_separator(&mut self) {
+        self.entries.push(TrayEntry::Separator);
+        self.dirty = true;
+    }
+    fn supports_submenus(&self) -> bool {
+        true
+    }
+    fn add_submenu(&mut self, label: &str, callback: NSCallback) -> *mut Object {
+        let id = self.next_id();
+        self.callbacks.insert(id, callback);
+        self.entries.push(TrayEntry::Submenu {
+            id: id,
+            label: label.to_string(),
+            entries: Vec::new(),
+        });
+        self.dirty = true;
+        id as *mut Object
+    }
+    fn add_item(&mut self, menu: Option<*mut Object>, item: &str, callback: NSCallback, selected: bool) -> *mut Object {
+        let id = self.next_id();
+        self.callbacks.insert(id, callback);
+        let entry = TrayEntry::Item {
+            id: id,
+            label: item.to_string(),
+            checked: selected,
+        };
+        self.dirty = true;
+        if let Some(menu) = menu {
+            if let Some(&mut TrayEntry::Submenu { ref mut entries, .. }) =
+                Self::entry_mut(&mut self.entries, menu as u64) {
+                    entries.push(entry);
+                    return id as *mut Object;
+                }
+        }
+        self.entries.push(entry);
+        id as *mut Object
+    }
+    fn update_item(&mut self, item: *mut Object, label: &str) {
+        if let Some(&mut TrayEntry::Item { label: ref mut old_label, .. }) =
+            Self::entry_mut(&mut self.entries, item as u64) {
+                *old_label = label.to_string();
+                self.dirty = true;
+            }
+    }
+    fn sel_item(&mut self, sender: u64) {
+        self.set_checked(sender, true);
+    }
+    fn unsel_item(&mut self, sender: u64) {
+        self.set_checked(sender, false);
+    }
+    fn register_url_handler(&mut self) {
+    }
+    fn run(&mut self, block: bool) {
+        if self.dirty {
+            let entries = self.entries.clone();
+            let tooltip = self.tooltip.clone();
+            self.handle.update(move |tray| {
+                tray.entries = entries;
+                tray.tooltip = tooltip;
+            });
+            self.dirty = false;
+        }
+        let mut clicks: Vec<u64> = match block {
+            true => self.rx_click.recv().into_iter().collect(),
+            false => Vec::new(),
+        };
+        clicks.extend(self.rx_click.try_iter());
+        for id in clicks {
+            if let Some(cb) = self.callbacks.get(&id) {
+                cb(id, &self.tx);
+            }
+        }
+    }
+    fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::SeqCst)
+    }
+}
@@ -1,10 +1,16 @@
 extern crate connectr;
-use connectr::SpotifyResponse;
 use connectr::TStatusBar;
-use connectr::MenuItem;
 use connectr::NSCallback;
+use connectr::menu::{Menu, MenuEntry, MenuRenderer};
+use connectr::controller::{SpotifyThread, SpotifyThreadCommand};
+use connectr::controller::{Command, CommandMessage};
+use connectr::controller::{create_spotify_thread, connect_spotify, quick_save_context};
 use connectr::ConnectDeviceList;
 use connectr::PlayerState;
+use connectr::PlayerAction;
+use connectr::ConnectPlaybackItem;
+use connectr::preset::{self, Preset, PresetMenuItem};
+use connectr::uri::{SpotifyUri, SpotifyUriKind};
 
 extern crate rubrail;
 use rubrail::Touchbar;
@@ -29,106 +35,103 @@ use std::sync::RwLock;
 #[macro_use]
 extern crate log;
 
-use std::ptr;
-use std::thread;
+#[cfg(test)]
+#[macro_use]
+extern crate lazy_static;
+
 use std::time::Duration;
 use std::sync::mpsc::channel;
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::mpsc::Sender;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 extern crate time;
 extern crate open;
 
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_json;
 
 use std::process;
 
-// How often to refresh Spotify state (if nothing triggers a refresh earlier).
-pub const REFRESH_PERIOD: i64 = 30;
+mod tui;
 
-enum SpotifyThreadCommand {
-    Update,
-    InvalidSettings,
-    ConfigActive,
-    ConfigInactive,
-}
+#[cfg(test)]
+mod test;
 
-#[allow(dead_code)]
-#[derive(PartialEq, Debug)]
-enum RefreshTime {
-    Now,   // immediately
-    Soon,  // after ~1 sec
-    Later, // don't change whatever the current schedule is
-    Redraw, // instantly, with stale data
-}
+// How long to wait for the Spotify thread to save its state when exiting
+const QUIT_TIMEOUT_SECS: u64 = 5;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
-enum CallbackAction {
-    SelectDevice,
-    PlayPause,
-    SkipNext,
-    SkipPrev,
-    Volume,
-    Preset,
-    Redraw,
-    Reconfigure,
-    SaveTrack,
-    EditAlarms,
+#[derive(Clone, Debug, PartialEq)]
+enum MenuAction {
+    Command(Command),
+    OpenUrl(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MenuCallbackCommand {
-    action: CallbackAction,
-    sender: u64,
-    data: String,
+struct ConnectrApp {
+    menu: MenuRenderer<MenuAction>,
+    // TODO: move touchbar in
 }
 
-struct MenuItems {
-    device: Vec<(MenuItem, String)>,
-    play: MenuItem,
-    next: MenuItem,
-    prev: MenuItem,
-    save: MenuItem,
-    preset: Vec<MenuItem>,
-    volume: Vec<MenuItem>,
+/// An entry in a touchbar scrubber: a command to send, or a group of
+/// entries that opens in place of the scrubber's contents.
+#[derive(Clone)]
+enum ScrubberItem {
+    Command(String, Command),
+    Group(String, Vec<ScrubberItem>),
+    /// Closes the open group
+    Back(String),
 }
-struct ConnectrApp {
-    menu: MenuItems,
-    // TODO: move touchbar in
+impl ScrubberItem {
+    fn label(&self) -> String {
+        match *self {
+            ScrubberItem::Command(ref label, _) => label.clone(),
+            ScrubberItem::Group(ref label, _) => format!("{} \u{25b8}", label),
+            ScrubberItem::Back(ref label) => format!("\u{25c2} {}", label),
+        }
+    }
 }
 
+/// Labels in a touchbar scrubber, and what touching each one does.
 struct TouchbarScrubberData {
-    action: CallbackAction,
-    entries: RefCell<Vec<(String,String)>>,
-    tx: Sender<String>,
+    entries: RefCell<Vec<ScrubberItem>>,
+    /// Group shown instead of the top level entries
+    open_group: RefCell<Option<String>>,
+    tx: Sender<CommandMessage>,
 }
 impl TouchbarScrubberData {
-    fn new(action: CallbackAction,
-           tx: Sender<String>) -> Rc<TouchbarScrubberData> {
+    fn new(tx: Sender<CommandMessage>) -> Rc<TouchbarScrubberData> {
         Rc::new(TouchbarScrubberData {
-            action: action,
-            entries: RefCell::new(Vec::<(String,String)>::new()),
+            entries: RefCell::new(Vec::new()),
+            open_group: RefCell::new(None),
             tx: tx,
         })
     }
-    fn fill(&self, items: Vec<(String,String)>) {
-        let mut entries = self.entries.borrow_mut();
-        entries.clear();
-        for item in items {
-            entries.push(item);
+    fn fill(&self, items: Vec<ScrubberItem>) {
+        *self.entries.borrow_mut() = items;
+    }
+    /// Entries shown right now: the open group, or the top level if no
+    /// group is open or it went away.
+    fn visible(&self) -> Vec<ScrubberItem> {
+        let entries = self.entries.borrow();
+        if let Some(ref open) = *self.open_group.borrow() {
+            for entry in entries.iter() {
+                if let ScrubberItem::Group(ref label, ref items) = *entry {
+                    if label == open {
+                        let mut visible = vec![ScrubberItem::Back(label.clone())];
+                        visible.extend(items.iter().cloned());
+                        return visible;
+                    }
+                }
+            }
         }
+        entries.clone()
     }
 }
 impl TScrubberData for TouchbarScrubberData {
     fn count(&self, _item: rubrail::ItemId) -> u32 {
-        self.entries.borrow().len() as u32
+        self.visible().len() as u32
     }
     fn text(&self, _item: rubrail::ItemId, idx: u32) -> String {
-        match self.entries.borrow().get(idx as usize) {
-            Some(e) => e.0.to_string(),
+        match self.visible().get(idx as usize) {
+            Some(e) => e.label(),
             None => String::new(),
         }
     }
@@ -136,22 +139,29 @@ impl TScrubberData for TouchbarScrubberData {
         // 10px per character + some padding seems to work nicely for the default
         // font.  no idea what it's like on other machines.  does the touchbar
         // font change? ¯\_(ツ)_/¯
-        let len = match self.entries.borrow().get(idx as usize) {
-            Some(e) => e.0.len() as u32,
+        let len = match self.visible().get(idx as usize) {
+            Some(e) => e.label().chars().count() as u32,
             None => 1,
         };
         let width = len * 8 + 20;
         width
     }
-    fn touch(&self, ui_item: rubrail::ItemId, idx: u32) {
+    fn touch(&self, _item: rubrail::ItemId, idx: u32) {
         info!("scrub touch: {}", idx);
-        if let Some(item) = self.entries.borrow().get(idx as usize) {
-            let cmd = MenuCallbackCommand {
-                action: self.action,
-                sender: ui_item,
-                data: item.1.clone(),
-            };
-            let _ = self.tx.send(serde_json::to_string(&cmd).unwrap());
+        match self.visible().get(idx as usize) {
+            Some(&ScrubberItem::Command(_, ref cmd)) => {
+                let _ = self.tx.send(CommandMessage::new(cmd.clone()));
+            },
+            Some(&ScrubberItem::Group(ref label, _)) => {
+                *self.open_group.borrow_mut() = Some(label.clone());
+                // Redraw to refill the scrubber with the group
+                let _ = self.tx.send(CommandMessage::new(Command::Redraw));
+            },
+            Some(&ScrubberItem::Back(_)) => {
+                *self.open_group.borrow_mut() = None;
+                let _ = self.tx.send(CommandMessage::new(Command::Redraw));
+            },
+            None => {},
         }
     }
 }
@@ -192,7 +202,7 @@ struct TouchbarUI {
 }
 
 impl TouchbarUI {
-    fn init(tx: Sender<String>) -> TouchbarUI {
+    fn init(tx: Sender<CommandMessage>) -> TouchbarUI {
         let mut touchbar = Touchbar::alloc("cnr");
         let icon = rubrail::util::bundled_resource_path("connectr_80px_300dpi", "png");
         if let Some(path) = icon {
@@ -203,19 +213,14 @@ impl TouchbarUI {
         let label_state = Arc::new(RwLock::new(TouchbarLabelState::TrackArtist));
         let cb_label_state = label_state.clone();
         let tx_clone = tx.clone();
-        touchbar.add_item_tap_gesture(&playing_label, 2, 1, Box::new(move |s| {
+        touchbar.add_item_tap_gesture(&playing_label, 2, 1, Box::new(move |_s| {
             let mut state = cb_label_state.write().unwrap();
             *state = match *state {
                 TouchbarLabelState::TrackArtist => TouchbarLabelState::Track,
                 TouchbarLabelState::Track => TouchbarLabelState::Artist,
                 TouchbarLabelState::Artist => TouchbarLabelState::TrackArtist,
             };
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::Redraw,
-                sender: *s,
-                data: String::new(),
-            };
-            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+            let _ = tx_clone.send(CommandMessage::new(Command::Redraw));
         }));
 
         // Fade text color to green as finger swipes right across the label, and
@@ -225,6 +230,7 @@ impl TouchbarUI {
         touchbar.add_item_swipe_gesture(&playing_label, Box::new(move |item,state,translation| {
             let rgba = match translation {
                 t if t > 170. => (0.1, 1.0, 0.7, 1.0),
+                t if t < -170. => (1.0, 0.3, 0.3, 1.0),
                 _ => (0.9, 0.9, 0.9, 1.0),
             };
             match state {
@@ -235,12 +241,10 @@ impl TouchbarUI {
                     unsafe { rubrail::util::set_text_color(item, 1., 1., 1., 1.); }
                     match translation {
                         t if t > 170. => {
-                            let cmd = MenuCallbackCommand {
-                                action: CallbackAction::SaveTrack,
-                                sender: 0,
-                                data: String::new(),
-                            };
-                            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+                            let _ = tx_clone.send(CommandMessage::new(Command::SaveTrack));
+                        },
+                        t if t < -170. => {
+                            let _ = tx_clone.send(CommandMessage::new(Command::RemoveTrack));
                         },
                         _ => {},
                     }
@@ -253,40 +257,24 @@ impl TouchbarUI {
 
         let image = touchbar.create_image_from_template(ImageTemplate::RewindTemplate);
         let tx_clone = tx.clone();
-        let prev_button = touchbar.create_button(Some(&image), None, Box::new(move |s| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SkipPrev,
-                sender: *s,
-                data: String::new(),
-            };
-            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+        let prev_button = touchbar.create_button(Some(&image), None, Box::new(move |_s| {
+            let _ = tx_clone.send(CommandMessage::new(Command::SkipPrev));
         }));
         touchbar.update_button_width(&prev_button, 40);
         let image = touchbar.create_image_from_template(ImageTemplate::PlayPauseTemplate);
         let tx_clone = tx.clone();
-        let play_pause_button = touchbar.create_button(Some(&image), None, Box::new(move |s| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::PlayPause,
-                sender: *s,
-                data: String::new(),
-            };
-            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+        let play_pause_button = touchbar.create_button(Some(&image), None, Box::new(move |_s| {
+            let _ = tx_clone.send(CommandMessage::new(Command::PlayPause));
         }));
         touchbar.update_button_width(&play_pause_button, 40);
         let image = touchbar.create_image_from_template(ImageTemplate::FastForwardTemplate);
         let tx_clone = tx.clone();
-        let next_button = touchbar.create_button(Some(&image), None, Box::new(move |s| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SkipNext,
-                sender: *s,
-                data: String::new(),
-            };
-            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+        let next_button = touchbar.create_button(Some(&image), None, Box::new(move |_s| {
+            let _ = tx_clone.send(CommandMessage::new(Command::SkipNext));
         }));
         touchbar.update_button_width(&next_button, 40);
 
-        let preset_scrubber_data = TouchbarScrubberData::new(CallbackAction::Preset,
-                                                             tx.clone());
+        let preset_scrubber_data = TouchbarScrubberData::new(tx.clone());
         let preset_scrubber = touchbar.create_text_scrubber(preset_scrubber_data.clone());
         let preset_bar = touchbar.create_bar();
         touchbar.add_items_to_bar(&preset_bar, vec![preset_scrubber]);
@@ -296,8 +284,7 @@ impl TouchbarUI {
             &preset_bar);
         touchbar.update_button_width(&preset_popover, 200);
 
-        let device_scrubber_data = TouchbarScrubberData::new(CallbackAction::SelectDevice,
-                                                             tx.clone());
+        let device_scrubber_data = TouchbarScrubberData::new(tx.clone());
         let device_scrubber = touchbar.create_text_scrubber(device_scrubber_data.clone());
         let device_bar = touchbar.create_bar();
         touchbar.add_items_to_bar(&device_bar, vec![device_scrubber]);
@@ -309,13 +296,8 @@ impl TouchbarUI {
 
         let tx_clone = tx.clone();
         let volume_slider = touchbar.create_slider(0., 100., Some("Volume"),
-                                                   false, Box::new(move |s,v| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::Volume,
-                sender: *s,
-                data: (v as u32).to_string(),
-            };
-            let _ = tx_clone.send(serde_json::to_string(&cmd).unwrap());
+                                                   false, Box::new(move |_s,v| {
+            let _ = tx_clone.send(CommandMessage::new(Command::Volume(v as u32)));
         }));
         let volume_bar = touchbar.create_bar();
         touchbar.add_items_to_bar(&volume_bar, vec![volume_slider]);
@@ -419,55 +401,58 @@ fn play_action_label(is_playing: bool) -> &'static str {
     }
 }
 
-fn loading_menu<T: TStatusBar>(status: &mut T) {
-    status.add_label("Syncing with Spotify...");
-    status.add_separator();
-    status.add_quit("Exit");
+fn menu_callback(action: &MenuAction) -> NSCallback {
+    match action.clone() {
+        MenuAction::Command(cmd) => Box::new(move |_sender, tx| {
+            let _ = tx.send(CommandMessage::new(cmd.clone()));
+        }),
+        MenuAction::OpenUrl(url) => Box::new(move |_sender, _tx| {
+            let _ = open::that(&url);
+        }),
+    }
 }
-fn reconfig_menu<T: TStatusBar>(status: &mut T) {
-    status.add_label("Invalid Configuration!");
-    status.add_separator();
-    let cb: NSCallback = Box::new(move |sender, tx| {
-        let cmd = MenuCallbackCommand {
-            action: CallbackAction::Reconfigure,
-            sender: sender,
-            data: String::new(),
-        };
-        let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-    });
-    let _ = status.add_item(None, "Reconfigure Connectr", cb, false);
-    status.add_separator();
-    let cb: NSCallback = Box::new(move |_sender, _tx| {
-        let _ = open::that("https://github.com/mrmekon/connectr");
-    });
-    let _ = status.add_item(None, "Help!", cb, false);
-    status.add_separator();
-    status.add_quit("Exit");
+
+fn loading_menu() -> Menu<MenuAction> {
+    let mut menu = Menu::new();
+    menu.label("Syncing with Spotify...")
+        .section(None)
+        .quit("Exit");
+    menu
+}
+fn reconfig_menu() -> Menu<MenuAction> {
+    let mut menu = Menu::new();
+    menu.label("Invalid Configuration!")
+        .section(None)
+        .action("Reconfigure Connectr",
+                MenuAction::Command(Command::Reconfigure), false)
+        .section(None)
+        .action("Help!", MenuAction::OpenUrl("https://github.com/mrmekon/connectr".to_string()), false)
+        .section(None)
+        .quit("Exit");
+    menu
 }
 
-fn fill_menu<T: TStatusBar>(app: &mut ConnectrApp,
-                            spotify: &SpotifyThread,
-                            status: &mut T,
-                            touchbar: &mut TouchbarUI,
-                            web_config_active: bool) {
+fn build_menu(spotify: &SpotifyThread, web_config_active: bool) -> Menu<MenuAction> {
+    let mut menu = Menu::new();
     if web_config_active {
         // This is a leaky-abstraction way of handling the webapi thread being
         // blocked, and thus unable to respond to a second 'Edit Alarms' request.
         // That's problematic if you close the browser window, since you'll have
         // to wait an hour for it to time out... so block all actions until it
         // is answered.
-        let cb: NSCallback = Box::new(move |_sender, _tx| {
-            let _ = open::that(format!("http://127.0.0.1:{}", connectr::settings::WEB_PORT));
-        });
-        let _ = status.add_item(None, "Re-launch Config", cb, false);
-        status.add_separator();
-        status.add_quit("Exit");
-        return;
+        let url = format!("http://127.0.0.1:{}", connectr::settings::WEB_PORT);
+        menu.action("Re-launch Config", MenuAction::OpenUrl(url), false)
+            .section(None)
+            .quit("Exit");
+        return menu;
     }
 
     let device_list = spotify.device_list.read().unwrap();
     let player_state = spotify.player_state.read().unwrap();
     let presets = spotify.presets.read().unwrap();
+    let failure = spotify.failure.read().unwrap();
+    let resume_points = spotify.resume_points.read().unwrap();
+    let quick_saves = spotify.quick_saves.read().unwrap();
 
     let empty_device_list: ConnectDeviceList = Default::default();
     let empty_player_state: PlayerState = Default::default();
@@ -480,163 +465,165 @@ fn fill_menu<T: TStatusBar>(app: &mut ConnectrApp,
         None => &empty_player_state,
     };
 
-    let track = match player_state.item {
-        Some(ref item) => item.name.clone(),
-        _ => "unknown".to_string()
-    };
-    let artist = match player_state.item {
-        Some(ref item) => item.artists[0].name.clone(),
-        _ => "unknown".to_string()
-    };
-    let album = match player_state.item {
-        Some(ref item) => item.album.name.clone(),
-        _ => "unknown".to_string()
+    // Episodes show the podcast where a track shows its artist
+    let (track, artist, album) = match player_state.item {
+        Some(ConnectPlaybackItem::Track(ref t)) => {
+            let artist = match t.artists.get(0) {
+                Some(a) => a.name.clone(),
+                None => "unknown".to_string(),
+            };
+            (t.name.clone(), artist, t.album.name.clone())
+        },
+        Some(ConnectPlaybackItem::Episode(ref e)) => {
+            (e.name.clone(), e.show.name.clone(), e.show.publisher.clone())
+        },
+        None => ("unknown".to_string(), "unknown".to_string(), "unknown".to_string()),
     };
 
     let duration_ms = match player_state.item {
-        Some(ref item) => item.duration_ms,
+        Some(ref item) => item.duration_ms(),
         _ => 0,
     };
     let min = duration_ms / 1000 / 60;
     let sec = (duration_ms - (min * 60 * 1000)) / 1000;
 
     info!("Playback State:\n{}", player_state);
-    match player_state.item {
-        Some(_) => {
-            let play_str = format!("{}\n{}\n{}", track, artist, album);
-            status.set_tooltip(&play_str);
-        },
-        None => status.set_tooltip("unknown"),
+    let mut tooltip = match player_state.item {
+        Some(_) => format!("{}\n{}\n{}", track, artist, album),
+        None => "unknown".to_string(),
+    };
+    if let Some(ref failure) = *failure {
+        // Explain why the last click did nothing, until it's fixed.
+        let message = format!("\u{26a0} {}", failure.message());
+        tooltip = format!("{}\n{}", tooltip, message);
+        menu.section(None)
+            .label(&message);
     }
+    menu.tooltip(&tooltip);
 
-    status.add_label("Now Playing:");
-    status.add_separator();
+    menu.section(Some("Now Playing:"));
     match player_state.item {
         Some(_) => {
-            status.add_label(&format!("{:<50}", track));
-            status.add_label(&format!("{:<50}", artist));
-            status.add_label(&format!("{:<50}", album));
-            status.add_label(&format!("{:<50}", format!("{}:{:02}", min, sec)));
+            menu.label(&format!("{:<50}", track))
+                .label(&format!("{:<50}", artist))
+                .label(&format!("{:<50}", album))
+                .label(&format!("{:<50}", format!("{}:{:02}", min, sec)));
         },
         None => {
-            status.add_label(&format!("{:<50}", "unknown"));
+            menu.label(&format!("{:<50}", "unknown"));
         }
     }
-    touchbar.update_now_playing(&track, &artist);
 
-    status.add_label("");
-    status.add_label("Actions:");
-    status.add_separator();
-    {
-        let play_str = play_action_label(player_state.is_playing);
-        let is_playing = player_state.is_playing.clone();
-        let cb: NSCallback = Box::new(move |sender, tx| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::PlayPause,
-                sender: sender,
-                data: is_playing.to_string(),
-            };
-            let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-        });
-        app.menu.play = status.add_item(None, &play_str, cb, false);
-        touchbar.update_play_button(is_playing);
-
-        let cb: NSCallback = Box::new(move |sender, tx| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SkipNext,
-                sender: sender,
-                data: String::new(),
-            };
-            let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-        });
-        app.menu.next = status.add_item(None, "Next", cb, false);
-
-        let cb: NSCallback = Box::new(move |sender, tx| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SkipPrev,
-                sender: sender,
-                data: String::new(),
-            };
-            let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-        });
-        app.menu.prev = status.add_item(None, "Previous", cb, false);
-
-        let cb: NSCallback = Box::new(move |sender, tx| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SaveTrack,
-                sender: sender,
-                data: String::new(),
-            };
-            let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-        });
-        app.menu.save = status.add_item(None, "Quick-Save", cb, false);
+    // Leave out controls that Spotify would reject
+    let play_action = match player_state.is_playing {
+        true => PlayerAction::Pause,
+        false => PlayerAction::Resume,
+    };
+    menu.section(Some("Actions:"));
+    if player_state.allows(play_action) {
+        menu.action(play_action_label(player_state.is_playing),
+                    MenuAction::Command(Command::PlayPause), false);
+    }
+    if player_state.allows(PlayerAction::SkipNext) {
+        menu.action("Next", MenuAction::Command(Command::SkipNext), false);
+    }
+    if player_state.allows(PlayerAction::SkipPrev) {
+        menu.action("Previous", MenuAction::Command(Command::SkipPrev), false);
+    }
+    menu.action("Quick-Save", MenuAction::Command(Command::SaveTrack), false);
+    // Only the user's own playlists can be changed, which is checked when
+    // the command runs
+    let from_playlist = player_state.context.as_ref()
+        .and_then(|c| c.uri.parse::<SpotifyUri>().ok())
+        .map(|uri| uri.kind() == SpotifyUriKind::Playlist)
+        .unwrap_or(false);
+    if from_playlist && player_state.item.is_some() {
+        menu.action("Remove All Copies from Playlist", MenuAction::Command(Command::RemoveTrack), false);
+    }
+    if let Some(last) = quick_saves.first() {
+        menu.action(&format!("Undo Quick-Save of {}", last.name),
+                    MenuAction::Command(Command::UndoQuickSave), false);
+    }
+    // Offer to keep the playlist, album or show as a preset, if it isn't one
+    let new_context = quick_save_context(player_state).is_some() &&
+        !presets.iter().any(|p| p.is_playing(player_state));
+    if new_context {
+        menu.action("Save as Preset", MenuAction::Command(Command::SavePreset), false);
+    }
+
+    menu.section(Some("Presets:"));
+    let can_interrupt = player_state.allows(PlayerAction::InterruptPlayback);
+    let preset_entry = |preset: &Preset| -> MenuEntry<MenuAction> {
+        match can_interrupt {
+            true => MenuEntry::Action {
+                label: preset.name.clone(),
+                action: MenuAction::Command(Command::Preset(preset.clone())),
+                checked: preset.is_playing(player_state),
+            },
+            false => MenuEntry::Label(preset.name.clone()),
+        }
+    };
+    let surprise_entry = |group: Option<&str>| -> MenuEntry<MenuAction> {
+        match can_interrupt {
+            true => MenuEntry::Action {
+                label: "Surprise Me".to_string(),
+                action: MenuAction::Command(Command::Surprise(group.map(|g| g.to_string()))),
+                checked: false,
+            },
+            false => MenuEntry::Label("Surprise Me".to_string()),
+        }
+    };
+    if presets.len() > 1 {
+        menu.entry(surprise_entry(None));
+    }
+    for item in preset::menu_items(&presets) {
+        match item {
+            PresetMenuItem::Preset(preset) => { menu.entry(preset_entry(preset)); },
+            PresetMenuItem::Group(group, members) => {
+                let mut entries = Vec::new();
+                if members.len() > 1 {
+                    entries.push(surprise_entry(Some(group)));
+                }
+                entries.extend(members.into_iter().map(|p| preset_entry(p)));
+                menu.submenu(group, entries);
+            },
+        }
     }
-
-    status.add_label("");
-    status.add_label("Presets:");
-    status.add_separator();
-    {
-        let preset_tuples: Vec<(String,String)> = presets.iter().map(|p| {
-            (p.0.clone(), p.1.clone())
+    let playing_uri = player_state.item.as_ref().map(|i| i.uri());
+    let resume: Vec<MenuEntry<MenuAction>> = resume_points.iter()
+        .filter(|p| Some(p.uri.as_str()) != playing_uri)
+        .map(|p| {
+            MenuEntry::Action {
+                label: p.label(),
+                action: MenuAction::Command(Command::Resume(p.uri.clone(), p.position_ms)),
+                checked: false,
+            }
         }).collect();
-        touchbar.preset_data.fill(preset_tuples);
-        for preset in presets.iter() {
-            let ref name = preset.0;
-            let uri = preset.1.clone();
-            let cb: NSCallback = Box::new(move |sender, tx| {
-                let cmd = MenuCallbackCommand {
-                    action: CallbackAction::Preset,
-                    sender: sender,
-                    data: uri.to_owned(),
-                };
-                let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-            });
-            let selected = player_state.playing_from_context(&preset.1);
-            let item = status.add_item(None, &name.clone(), cb, selected);
-            app.menu.preset.push(item);
-        }
+    if can_interrupt && !resume.is_empty() {
+        menu.submenu("Resume", resume);
     }
 
-    status.add_label("");
-    status.add_label("Devices:");
-    status.add_separator();
+    menu.section(Some("Devices:"));
     info!("Visible Devices:");
-
-    let devices: Vec<(String,String)> = device_list.into_iter().map(|d| {
-        (d.name.clone(), d.id.clone().unwrap_or(String::new()))
-    }).collect();
-    touchbar.device_data.fill(devices);
-
     if device_list.len() == 0 {
-        status.add_label("unavailable");
+        menu.label("unavailable");
     }
-
-    let selected_arr: Vec<bool> = device_list.into_iter().map(|d| {d.is_active}).collect();
-    if let Ok(selected) = selected_arr.binary_search(&true) {
-        touchbar.set_selected_device(selected as u32);
-    }
-    touchbar.update_scrubbers();
-
     let mut cur_volume: u32 = 0;
-    let mut cur_volume_exact: u32 = 0;
     for dev in device_list {
         info!("{}", dev);
         let id = match dev.id {
             Some(ref id) => id.clone(),
             None => "".to_string(),
         };
-        let cb_id = id.clone();
-        let cb: NSCallback = Box::new(move |sender, tx| {
-            let cmd = MenuCallbackCommand {
-                action: CallbackAction::SelectDevice,
-                sender: sender,
-                data: cb_id.to_owned(),
-            };
-            let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-        });
-        let item = status.add_item(None, &dev.name, cb, dev.is_active);
+        match dev.is_restricted {
+            // Restricted devices can't be controlled, even to start playing
+            true => { menu.label(&format!("{} (restricted)", dev.name)); },
+            false => {
+                menu.action(&dev.name, MenuAction::Command(Command::SelectDevice(id)),
+                            dev.is_active);
+            },
+        }
         if dev.is_active {
-            cur_volume_exact = dev.volume_percent.unwrap_or(0);
             cur_volume = match dev.volume_percent {
                 Some(v) => {
                     (v as f32 / 10.0).round() as u32 * 10
@@ -644,166 +631,83 @@ fn fill_menu<T: TStatusBar>(app: &mut ConnectrApp,
                 None => 100,
             };
         }
-        app.menu.device.push((item, id));
-        touchbar.update_volume(cur_volume_exact);
     }
     info!("");
 
-    status.add_label("");
-
-    status.add_separator();
-    let volume_menu = status.add_submenu("Volume", Box::new(move |_,_| {}));
-    {
-        let mut i = 0;
-        while i <= 100 {
-            let vol_str = format!("{}%", i);
-            let cb: NSCallback = Box::new(move |sender, tx| {
-                let cmd = MenuCallbackCommand {
-                    action: CallbackAction::Volume,
-                    sender: sender,
-                    data: i.to_string(),
-                };
-                let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-            });
-            let item = status.add_item(Some(volume_menu), &vol_str, cb, i == cur_volume);
-            app.menu.volume.push(item);
-            i += 10;
+    let volume: Vec<MenuEntry<MenuAction>> = (0..11).map(|i| i * 10).map(|i| {
+        MenuEntry::Action {
+            label: format!("{}%", i),
+            action: MenuAction::Command(Command::Volume(i)),
+            checked: i == cur_volume,
         }
-    }
-
-    status.add_separator();
-    let cb: NSCallback = Box::new(move |sender, tx| {
-        let cmd = MenuCallbackCommand {
-            action: CallbackAction::EditAlarms,
-            sender: sender,
-            data: String::new(),
-        };
-        let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-    });
-    let _ = status.add_item(None, "Edit Alarms", cb, false);
-
-    let cb: NSCallback = Box::new(move |sender, tx| {
-        let cmd = MenuCallbackCommand {
-            action: CallbackAction::Reconfigure,
-            sender: sender,
-            data: String::new(),
-        };
-        let _ = tx.send(serde_json::to_string(&cmd).unwrap());
-    });
-    let _ = status.add_item(None, "Reconfigure Connectr", cb, false);
-
-    status.add_separator();
-    let cb: NSCallback = Box::new(move |_sender, _tx| {
-        let _ = open::that("https://open.spotify.com/search/");
-    });
-    let _ = status.add_item(None, "Search Spotify", cb, false);
-
-    status.add_separator();
-    status.add_quit("Exit");
+    }).collect();
+    if player_state.allows(PlayerAction::Volume) {
+        menu.section(None)
+            .submenu("Volume", volume);
+    }
+
+    let url = "https://open.spotify.com/search/".to_string();
+    menu.section(None)
+        .action("Edit Alarms", MenuAction::Command(Command::EditAlarms), false)
+        .action("Import Presets", MenuAction::Command(Command::ImportPresets), false)
+        .action("Reconfigure Connectr", MenuAction::Command(Command::Reconfigure), false)
+        .section(None)
+        .action("Search Spotify", MenuAction::OpenUrl(url), false)
+        .section(None)
+        .quit("Exit");
+    menu
 }
 
-fn clear_menu<T: TStatusBar>(app: &mut ConnectrApp, status: &mut T) {
-    app.menu = MenuItems {
-        device: Vec::<(MenuItem, String)>::new(),
-        play: ptr::null_mut(),
-        next: ptr::null_mut(),
-        prev: ptr::null_mut(),
-        save: ptr::null_mut(),
-        preset: Vec::<MenuItem>::new(),
-        volume: Vec::<MenuItem>::new(),
+fn update_touchbar(touchbar: &mut TouchbarUI, spotify: &SpotifyThread) {
+    let device_list = spotify.device_list.read().unwrap();
+    let player_state = spotify.player_state.read().unwrap();
+    let presets = spotify.presets.read().unwrap();
+
+    let empty_device_list: ConnectDeviceList = Default::default();
+    let empty_player_state: PlayerState = Default::default();
+    let device_list = match device_list.as_ref() {
+        Some(x) => x,
+        None => &empty_device_list,
+    };
+    let player_state = match player_state.as_ref() {
+        Some(x) => x,
+        None => &empty_player_state,
     };
-    status.clear_items();
-}
 
-fn handle_callback(player_state: Option<&connectr::PlayerState>,
-                   spotify: &mut connectr::SpotifyConnectr,
-                   cmd: &MenuCallbackCommand) -> RefreshTime {
-    info!("Executed action: {:?}", cmd.action);
-    let mut refresh = RefreshTime::Now;
-    match cmd.action {
-        CallbackAction::SelectDevice => {
-            require(spotify.transfer(cmd.data.clone(), true));
-        },
-        CallbackAction::PlayPause => {
-            if let Some(player_state) = player_state {
-                match player_state.is_playing {
-                    true => {require(spotify.pause());},
-                    false => {require(spotify.play(None));},
-                }
-            }
-        },
-        CallbackAction::Preset => {
-            play_uri(spotify, None, Some(&cmd.data));
-        }
-        CallbackAction::SkipNext => {
-            require(spotify.next());
-        }
-        CallbackAction::SkipPrev => {
-            require(spotify.previous());
-        }
-        CallbackAction::Volume => {
-            let vol = cmd.data.parse::<u32>().unwrap();
-            require(spotify.volume(vol));
-        }
-        CallbackAction::Redraw => {
-            refresh = RefreshTime::Redraw;
-        }
-        CallbackAction::SaveTrack => {
-            if let Some(player_state) = player_state {
-                if let Some(ref ctx) = player_state.context {
-                    let playlist: Option<String>;
-                    {
-                        match spotify.quick_save_playlist(&ctx.uri) {
-                            Some(u) => playlist = Some(u.to_owned()),
-                            None => playlist = None,
-                        }
-                    }
-                    if let Some(playlist) = playlist {
-                        if let Some(ref item) = player_state.item {
-                            let track = item.uri.to_owned();
-                            require(spotify.save_track(track, playlist));
-                        }
-                    }
-                }
-            }
+    let (track, artist) = match player_state.item {
+        Some(ref item) => (item.name().to_string(), item.artist().unwrap_or("unknown").to_string()),
+        _ => ("unknown".to_string(), "unknown".to_string()),
+    };
+    touchbar.update_now_playing(&track, &artist);
+    touchbar.update_play_button(player_state.is_playing);
+
+    let preset_item = |p: &Preset| ScrubberItem::Command(p.name.clone(), Command::Preset(p.clone()));
+    let preset_items: Vec<ScrubberItem> = preset::menu_items(&presets).into_iter().map(|item| {
+        match item {
+            PresetMenuItem::Preset(p) => preset_item(p),
+            PresetMenuItem::Group(group, members) => {
+                ScrubberItem::Group(group.to_string(), members.into_iter().map(|p| preset_item(p)).collect())
+            },
         }
-        CallbackAction::Reconfigure => {}
-        CallbackAction::EditAlarms => {}
+    }).collect();
+    touchbar.preset_data.fill(preset_items);
+
+    let devices: Vec<ScrubberItem> = device_list.into_iter().map(|d| {
+        ScrubberItem::Command(d.name.clone(), Command::SelectDevice(d.id.clone().unwrap_or(String::new())))
+    }).collect();
+    touchbar.device_data.fill(devices);
+
+    let selected_arr: Vec<bool> = device_list.into_iter().map(|d| {d.is_active}).collect();
+    if let Ok(selected) = selected_arr.binary_search(&true) {
+        touchbar.set_selected_device(selected as u32);
     }
-    refresh
-}
+    touchbar.update_scrubbers();
 
-fn refresh_time(player_state: Option<&connectr::PlayerState>, now: i64) -> i64 {
-    let refresh_offset = match player_state {
-        Some(ref state) => {
-            match state.is_playing {
-                true => {
-                    let duration_ms = match state.item {
-                        Some(ref item) => item.duration_ms,
-                        _ => 0,
-                    };
-                    let track_end = match state.progress_ms {
-                        Some(prog) => {
-                            if prog < duration_ms {
-                                duration_ms - prog
-                            }
-                            else {
-                                0
-                            }
-                        },
-                        None => duration_ms,
-                    } as i64;
-                    // Refresh 1 second after track ends
-                    track_end/1000 + 1
-                },
-                false => REFRESH_PERIOD,
-            }
+    for dev in device_list {
+        if dev.is_active {
+            touchbar.update_volume(dev.volume_percent.unwrap_or(0));
         }
-        None => REFRESH_PERIOD,
-    };
-    let refresh_offset = std::cmp::min(REFRESH_PERIOD, refresh_offset) as i64;
-    info!("State refresh in {} seconds.", refresh_offset);
-    now + refresh_offset
+    }
 }
 
 fn find_wine_path() -> Option<std::path::PathBuf> {
@@ -818,288 +722,41 @@ fn find_wine_path() -> Option<std::path::PathBuf> {
     None
 }
 
-fn scrobble(spotify: &mut connectr::SpotifyConnectr,
-            state: Option<&connectr::PlayerState>,
-            played_ms: u64,
-            done: bool) {
-    if let Some(state) = flatten_player_state(state) {
-        let len = state.duration_ms;
-        let artist = state.artist.clone();
-        let track = state.track.clone();
-        let album = state.album.clone();
-        let devtype = state.device_type.clone();
-        if done {
-            // Scrobbling API rules:
-            //  - Track longer than 30 seconds
-            //  - Track played at least half its duration OR 4 minutes
-            if len > 30000 &&
-                (played_ms > 4*60000 || played_ms >= len / 2)
-            {
-                spotify.scrobble(artist, track, album, devtype);
-            }
-        }
-        else {
-            spotify.scrobbler_now_playing(artist, track, album, devtype);
-        }
-    }
-}
-
-struct FlatPlayState {
-    #[allow(dead_code)]
-    artist: String,
-    #[allow(dead_code)]
-    track: String,
-    #[allow(dead_code)]
-    album: String,
-    device_type: String,
-    uri: String,
-    progress_ms: u64,
-    duration_ms: u64,
-    is_playing: bool,
-}
-
-fn flatten_player_state(state: Option<&connectr::PlayerState>) -> Option<FlatPlayState> {
-    match state {
-        Some(state) => {
-            match state.item {
-                Some(ref item) => {
-                    let artist = match item.artists.get(0) {
-                        Some(ref a) => a.name.clone(),
-                        None => String::new(),
-                    };
-                    Some(FlatPlayState {
-                        artist: artist,
-                        track: item.name.clone(),
-                        album: item.album.name.clone(),
-                        device_type: state.device.device_type.clone(),
-                        uri: item.uri.clone(),
-                        progress_ms: state.progress_ms.unwrap_or(0),
-                        duration_ms: item.duration_ms,
-                        is_playing: state.is_playing,
-                    })
-                },
-                None => None,
-            }
-        },
-        None => None,
-    }
-}
-
-#[derive(Debug)]
-enum StateChange {
-    Stopped(u64),
-    Changed(u64),
-    Played(u64),
-    Unchanged,
-}
+fn main() {
+    fruitbasket::create_logger(".connectr.log", fruitbasket::LogDir::Home, 5, 2).unwrap();
 
-fn compare_playback_states(old: Option<&connectr::PlayerState>,
-                           new: Option<&connectr::PlayerState>) -> StateChange {
-    let old_track = flatten_player_state(old);
-    let new_track = flatten_player_state(new);
-    if old_track.is_none() && new_track.is_none() {
-        return StateChange::Unchanged;
-    }
-    if old_track.is_some() && new_track.is_none() {
-        return StateChange::Stopped(0);
-    }
-    if old_track.is_none() && new_track.is_some() {
-        match new_track.unwrap().is_playing {
-            true => { return StateChange::Changed(0); },
-            false => { return StateChange::Stopped(0); },
-        }
-    }
-    let old_track = old_track.unwrap();
-    let new_track = new_track.unwrap();
-    let new_time = new_track.progress_ms;
-    let old_time = old_track.progress_ms;
-    if old_track.uri != new_track.uri {
-        // Assume that up to 31 seconds of the previous track played.
-        let played = std::cmp::min(
-            std::cmp::max(old_track.duration_ms as i64 - old_time as i64, 0),
-            31000) as u64;
-        if old_track.is_playing && !new_track.is_playing {
-            // End of context
-            return StateChange::Stopped(played);
+    if std::env::args().any(|arg| arg == "--import-presets") {
+        info!("Started Connectr (importing presets)");
+        let mut spotify = match connect_spotify() {
+            Some(s) => s,
+            None => {
+                println!("Couldn't connect to Spotify.  Run Connectr once to configure it.");
+                process::exit(1);
+            },
+        };
+        match connectr::import::import_cli(&mut spotify) {
+            Ok(count) => println!("Added {} presets.", count),
+            Err(e) => {
+                println!("Couldn't import presets: {}", e);
+                process::exit(1);
+            },
         }
-        return StateChange::Changed(played);
-    }
-    let played = std::cmp::max(new_time as i64 - old_time as i64, 0) as u64;
-    if (new_time == 0 && !new_track.is_playing) && (old_time > 0 && old_track.is_playing) {
-        // Playlist finished and reset.  Normally caught above, but this
-        // is a special case for a 1-track playlist.
-        let played = std::cmp::min(
-            std::cmp::max(old_track.duration_ms as i64 - old_time as i64, 0),
-            31000) as u64;
-        return StateChange::Stopped(played);
-    }
-    if played == 0 {
-        return StateChange::Unchanged;
-    }
-    if !old_track.is_playing {
-        return StateChange::Changed(played);
+        return;
     }
-    StateChange::Played(played)
-}
-
-struct SpotifyThread {
-    #[allow(dead_code)]
-    handle: std::thread::JoinHandle<()>,
-    #[allow(dead_code)]
-    tx: Sender<String>,
-    rx: Receiver<SpotifyThreadCommand>,
-    device_list: Arc<RwLock<Option<connectr::ConnectDeviceList>>>,
-    player_state: Arc<RwLock<Option<connectr::PlayerState>>>,
-    presets: Arc<RwLock<Vec<(String,String)>>>,
-}
-
-fn create_spotify_thread(rx_cmd: Receiver<String>) -> SpotifyThread {
-    let (tx_in,rx_in) = channel::<String>();
-    let (tx_out,rx_out) = channel::<SpotifyThreadCommand>();
-    let device_list = Arc::new(RwLock::new(Some(Default::default())));
-    let player_state = Arc::new(RwLock::new(None));
-    let presets = Arc::new(RwLock::new(vec![]));
-    let thread_device_list = device_list.clone();
-    let thread_player_state = player_state.clone();
-    let thread_presets = presets.clone();
-    let thread = thread::spawn(move || {
-        let tx = tx_out;
-        let rx = rx_in;
-        let rx_cmd = rx_cmd;
-        let mut refresh_time_utc = 0;
-        let mut track_play_time_ms: u64 = 0;
-
-        // Continuously try to create a connection to Spotify web API.
-        // If it fails, assume that the settings file is corrupt and inform
-        // the main thread of it.  The main thread can request that the
-        // settings file be re-configured.
-        let mut spotify: Option<connectr::SpotifyConnectr>;
-        loop {
-            spotify = connectr::SpotifyConnectr::new().build();
-            match spotify {
-                Some(_) => { break; },
-                None => {
-                    let _ = tx.send(SpotifyThreadCommand::InvalidSettings);
-                    if let Ok(s) = rx_cmd.recv_timeout(Duration::from_secs(120)) {
-                        let cmd: MenuCallbackCommand = serde_json::from_str(&s).unwrap();
-                        if cmd.action == CallbackAction::Reconfigure {
-                            connectr::reconfigure(None);
-                        }
-                    }
-                },
-            }
-        }
-        let mut spotify = spotify.unwrap();
-        let device_list = thread_device_list;
-        let player_state = thread_player_state;
-        let presets = thread_presets;
-        info!("Created Spotify controller.");
-        spotify.connect();
-        info!("Created Spotify connection.");
-        spotify.set_target_device(None);
-        {
-            let mut preset_writer = presets.write().unwrap();
-            *preset_writer = spotify.get_presets().clone();
-            let _ = tx.send(SpotifyThreadCommand::Update);
-        }
-        loop {
-            if rx.try_recv().is_ok() {
-                // Main thread tells us to shutdown
-                break;
-            }
-            let now = time::now_utc().to_timespec().sec as i64;
-            spotify.await_once(false);
-            // Block for 200ms while waiting for UI input.  This throttles the
-            // thread CPU usage, at the expense of slight delays for metadata
-            // updates.  Optimizes for UI response.
-            if let Ok(s) = rx_cmd.recv_timeout(Duration::from_millis(200)) {
-                info!("Received {}", s);
-                let cmd: MenuCallbackCommand = serde_json::from_str(&s).unwrap();
-                if cmd.action == CallbackAction::EditAlarms {
-                    let devs = device_list.read().unwrap();
-                    let _ = tx.send(SpotifyThreadCommand::ConfigActive);
-                    spotify.alarm_configure((*devs).as_ref());
-                    let _ = tx.send(SpotifyThreadCommand::ConfigInactive);
-                }
-                if cmd.action == CallbackAction::Reconfigure {
-                    info!("Reconfiguring settings.");
-                    let _ = tx.send(SpotifyThreadCommand::ConfigActive);
-                    connectr::reconfigure(Some(spotify.settings()));
-                    spotify.reread_settings();
-                    let _ = tx.send(SpotifyThreadCommand::ConfigInactive);
-                    info!("Finished reconfiguring.");
-                }
-                let refresh_strategy =  handle_callback(player_state.read().unwrap().as_ref(),
-                                                        &mut spotify, &cmd);
-                refresh_time_utc = match refresh_strategy {
-                    RefreshTime::Now => {
-                        // Let the other thread run, and hope that the command
-                        // gets through.  The Spotify backend is really slow to
-                        // show changes sometimes, even after they happen.
-                        // TODO: change the UI before the real backend changes
-                        // go through.
-                        thread::sleep(Duration::from_millis(100));
-                        now - 1
-                    },
-                    RefreshTime::Soon => now + 1,
-                    _ => refresh_time_utc,
-                };
-                if refresh_strategy == RefreshTime::Redraw {
-                    let _ = tx.send(SpotifyThreadCommand::Update);
-                }
-            }
 
-            if now > refresh_time_utc {
-                info!("Request update");
-                let dev_list = spotify.request_device_list();
-                {
-                    let mut dev_writer = device_list.write().unwrap();
-                    *dev_writer = match dev_list {
-                        Some(_) => dev_list,
-                        None => Some(Default::default()),
-                    };
-                }
-                let play_state = spotify.request_player_state();
-                {
-                    let mut player_writer = player_state.write().unwrap();
-                    let cmp = compare_playback_states(player_writer.as_ref(), play_state.as_ref());
-                    match cmp {
-                        StateChange::Changed(time_ms) => {
-                            track_play_time_ms += time_ms;
-                            scrobble(&mut spotify, player_writer.as_ref(), track_play_time_ms, true);
-                            track_play_time_ms = 0;
-                            scrobble(&mut spotify, play_state.as_ref(), track_play_time_ms, false);
-                        }
-                        StateChange::Stopped(time_ms) => {
-                            track_play_time_ms += time_ms;
-                            scrobble(&mut spotify, player_writer.as_ref(), track_play_time_ms, true);
-                            track_play_time_ms = 0;
-                        },
-                        StateChange::Played(time_ms) => {
-                            track_play_time_ms += time_ms;
-                        },
-                        StateChange::Unchanged => {},
-                    }
-                    *player_writer = play_state;
-                }
-                refresh_time_utc = refresh_time(player_state.read().unwrap().as_ref(), now);
-                info!("Refreshed Spotify state.");
-                let _ = tx.send(SpotifyThreadCommand::Update);
-            }
+    // Terminal frontend runs in the foreground, so skip the app bundle
+    // relaunch and the menu bar entirely.
+    if std::env::args().any(|arg| arg == "--tui") {
+        info!("Started Connectr (terminal UI)");
+        let (tx,rx) = channel::<CommandMessage>();
+        let spotify_thread = create_spotify_thread(rx, connect_spotify);
+        if let Err(e) = tui::run(&spotify_thread, tx.clone()) {
+            error!("Terminal UI failed: {}", e);
         }
-    });
-    SpotifyThread {
-        handle: thread,
-        tx: tx_in,
-        rx: rx_out,
-        device_list: device_list,
-        player_state: player_state,
-        presets: presets,
+        info!("Exiting.\n");
+        spotify_thread.quit(&tx, Duration::from_secs(QUIT_TIMEOUT_SECS));
+        return;
     }
-}
-
-fn main() {
-    fruitbasket::create_logger(".connectr.log", fruitbasket::LogDir::Home, 5, 2).unwrap();
 
     // Relaunch in a Mac app bundle if running on OS X and not already bundled.
     let icon = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -1150,23 +807,16 @@ fn main() {
     }
 
     let mut app = ConnectrApp {
-        menu: MenuItems {
-            device: Vec::<(MenuItem, String)>::new(),
-            play: ptr::null_mut(),
-            next: ptr::null_mut(),
-            prev: ptr::null_mut(),
-            save: ptr::null_mut(),
-            preset: Vec::<MenuItem>::new(),
-            volume: Vec::<MenuItem>::new(),
-        },
+        menu: MenuRenderer::new(),
     };
-    let (tx,rx) = channel::<String>();
-    let spotify_thread = create_spotify_thread(rx);
+    let (tx,rx) = channel::<CommandMessage>();
+    let spotify_thread = create_spotify_thread(rx, connect_spotify);
 
     let mut status = connectr::StatusBar::new(tx.clone());
     info!("Created status bar.");
     status.register_url_handler();
-    loading_menu(&mut status);
+    app.menu.update(&mut status, loading_menu(), menu_callback);
+    let quit_tx = tx.clone();
     let mut touchbar = TouchbarUI::init(tx);
     info!("Created touchbar.");
 
@@ -1190,13 +840,15 @@ fn main() {
     let mut web_config_active: bool = false;
     let mut need_redraw: bool = false;
     while running.load(Ordering::SeqCst) {
+        if status.quit_requested() {
+            break;
+        }
         match spotify_thread.rx.recv_timeout(Duration::from_millis(100)) {
             Ok(cmd) => {
                 match cmd {
                     SpotifyThreadCommand::Update => { need_redraw = true; },
                     SpotifyThreadCommand::InvalidSettings => {
-                        clear_menu(&mut app, &mut status);
-                        reconfig_menu(&mut status);
+                        app.menu.update(&mut status, reconfig_menu(), menu_callback);
                     }
                     SpotifyThreadCommand::ConfigActive => {
                         web_config_active = true;
@@ -1206,51 +858,33 @@ fn main() {
                         web_config_active = false;
                         need_redraw = true;
                     },
+                    SpotifyThreadCommand::Quit => {
+                        running.store(false, Ordering::SeqCst);
+                    },
                 }
             },
             Err(_) => {}
         }
         if need_redraw && status.can_redraw() {
-            clear_menu(&mut app, &mut status);
-            fill_menu(&mut app, &spotify_thread, &mut status, &mut touchbar, web_config_active);
+            let menu = build_menu(&spotify_thread, web_config_active);
+            app.menu.update(&mut status, menu, menu_callback);
+            if !web_config_active {
+                update_touchbar(&mut touchbar, &spotify_thread);
+            }
             need_redraw = false;
         }
         status.run(false);
     }
     info!("Exiting.\n");
+    // Let the Spotify thread save its state, unless it already stopped or
+    // is stuck behind a settings form
+    let timeout = match web_config_active {
+        true => 0,
+        false => QUIT_TIMEOUT_SECS,
+    };
+    spotify_thread.quit(&quit_tx, Duration::from_secs(timeout));
     if let Some(mut tiny_proc) = tiny {
         let _ = tiny_proc.kill();
         let _ = tiny_proc.wait();
     }
 }
-
-fn require(response: SpotifyResponse) {
-    match response.code.unwrap() {
-        200 ..= 299 => { info!("Response: {}", response.code.unwrap()); },
-        _ => { warn!("Spotify action failed! ({})", response); }
-    }
-}
-
-fn play_uri(spotify: &mut connectr::SpotifyConnectr, device: Option<&str>, uri: Option<&str>) {
-    match device {
-        Some(dev) => { spotify.set_target_device(Some(dev.to_string())); },
-        None => { spotify.set_target_device(None); },
-    }
-    match uri {
-        Some(s) => {
-            let ctx = connectr::PlayContext::new()
-                .context_uri(s)
-                .offset_position(0)
-                .build();
-            require(spotify.play(Some(&ctx)));
-        }
-        None => {
-            info!("Transfer!");
-            require(spotify.play(None));
-        }
-    };
-
-    // Always set it back to None, so commands go to the currently
-    // playing device.
-    spotify.set_target_device(None);
-}
@@ -0,0 +1,274 @@
+#[cfg(test)]
+mod test;
+
+use super::TStatusBar;
+use super::NSCallback;
+use super::MenuItem;
+
+/// One entry in a platform-neutral menu.
+///
+/// `A` is whatever the application wants to get back when an entry is
+/// clicked.  Menus are compared with `PartialEq`, so two menus built from the
+/// same state should compare equal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MenuEntry<A> {
+    Label(String),
+    Separator,
+    Action { label: String, action: A, checked: bool },
+    Submenu { label: String, entries: Vec<MenuEntry<A>> },
+    Quit(String),
+}
+
+/// A titled group of entries.  Sections are visually separated when drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MenuSection<A> {
+    pub title: Option<String>,
+    pub entries: Vec<MenuEntry<A>>,
+}
+
+/// A complete menu, built from application state and drawn by a `TStatusBar`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu<A> {
+    pub tooltip: Option<String>,
+    pub sections: Vec<MenuSection<A>>,
+}
+
+impl<A> Default for Menu<A> {
+    fn default() -> Menu<A> { Menu { tooltip: None, sections: Vec::new() } }
+}
+
+impl<A> Menu<A> {
+    pub fn new() -> Menu<A> {
+        Menu::default()
+    }
+    pub fn tooltip(&mut self, text: &str) -> &mut Menu<A> {
+        self.tooltip = Some(text.to_string());
+        self
+    }
+    /// Start a new section.  Entries are added to the most recent section.
+    pub fn section(&mut self, title: Option<&str>) -> &mut Menu<A> {
+        self.sections.push(MenuSection {
+            title: title.map(|t| t.to_string()),
+            entries: Vec::new(),
+        });
+        self
+    }
+    fn push(&mut self, entry: MenuEntry<A>) -> &mut Menu<A> {
+        if self.sections.is_empty() {
+            self.section(None);
+        }
+        self.sections.last_mut().unwrap().entries.push(entry);
+        self
+    }
+    pub fn label(&mut self, label: &str) -> &mut Menu<A> {
+        self.push(MenuEntry::Label(label.to_string()))
+    }
+    pub fn separator(&mut self) -> &mut Menu<A> {
+        self.push(MenuEntry::Separator)
+    }
+    pub fn action(&mut self, label: &str, action: A, checked: bool) -> &mut Menu<A> {
+        self.push(MenuEntry::Action { label: label.to_string(), action: action, checked: checked })
+    }
+    pub fn submenu(&mut self, label: &str, entries: Vec<MenuEntry<A>>) -> &mut Menu<A> {
+        self.push(MenuEntry::Submenu { label: label.to_string(), entries: entries })
+    }
+    /// Add an entry built elsewhere, such as one also used in a submenu.
+    pub fn entry(&mut self, entry: MenuEntry<A>) -> &mut Menu<A> {
+        self.push(entry)
+    }
+    pub fn quit(&mut self, label: &str) -> &mut Menu<A> {
+        self.push(MenuEntry::Quit(label.to_string()))
+    }
+    /// All clickable entries, depth first, in the order they are drawn.
+    pub fn actions(&self) -> Vec<(&str, &A, bool)> {
+        fn collect<'a, A>(entries: &'a Vec<MenuEntry<A>>, out: &mut Vec<(&'a str, &'a A, bool)>) {
+            for entry in entries {
+                match entry {
+                    &MenuEntry::Action { ref label, ref action, checked } => {
+                        out.push((label, action, checked));
+                    },
+                    &MenuEntry::Submenu { ref entries, .. } => collect(entries, out),
+                    _ => {},
+                }
+            }
+        }
+        let mut out = Vec::new();
+        for section in &self.sections {
+            collect(&section.entries, &mut out);
+        }
+        out
+    }
+}
+
+/// What has to happen to get from one menu to another.
+#[derive(Debug, PartialEq)]
+pub enum MenuDiff {
+    /// Nothing visible changed.
+    Unchanged,
+    /// Only checkmarks changed, given as (action index, checked) pairs.
+    Checkmarks(Vec<(usize, bool)>),
+    /// Structure, labels or actions changed.  Clear and draw from scratch.
+    Rebuild,
+}
+
+impl<A: PartialEq> Menu<A> {
+    pub fn diff(&self, new: &Menu<A>) -> MenuDiff {
+        if self == new {
+            return MenuDiff::Unchanged;
+        }
+        // Checkmarks can be changed in place if everything else is the same.
+        let mut unchecked = self.clone_structure();
+        let mut new_unchecked = new.clone_structure();
+        unchecked.tooltip = None;
+        new_unchecked.tooltip = None;
+        if unchecked != new_unchecked {
+            return MenuDiff::Rebuild;
+        }
+        let changes: Vec<(usize, bool)> = self.actions().iter()
+            .zip(new.actions().iter())
+            .enumerate()
+            .filter(|&(_, (old, new))| old.2 != new.2)
+            .map(|(idx, (_, new))| (idx, new.2))
+            .collect();
+        match changes.len() {
+            0 => MenuDiff::Unchanged,
+            _ => MenuDiff::Checkmarks(changes),
+        }
+    }
+    // A copy with every checkmark cleared, referencing the original actions.
+    fn clone_structure(&self) -> Menu<&A> {
+        fn strip<A>(entries: &Vec<MenuEntry<A>>) -> Vec<MenuEntry<&A>> {
+            entries.iter().map(|entry| match entry {
+                &MenuEntry::Label(ref l) => MenuEntry::Label(l.clone()),
+                &MenuEntry::Separator => MenuEntry::Separator,
+                &MenuEntry::Action { ref label, ref action, .. } =>
+                    MenuEntry::Action { label: label.clone(), action: action, checked: false },
+                &MenuEntry::Submenu { ref label, ref entries } =>
+                    MenuEntry::Submenu { label: label.clone(), entries: strip(entries) },
+                &MenuEntry::Quit(ref l) => MenuEntry::Quit(l.clone()),
+            }).collect()
+        }
+        Menu {
+            tooltip: self.tooltip.clone(),
+            sections: self.sections.iter().map(|s| MenuSection {
+                title: s.title.clone(),
+                entries: strip(&s.entries),
+            }).collect(),
+        }
+    }
+}
+
+/// Draws menus on a status bar, skipping work when nothing changed.
+///
+/// Backends only need the primitive `TStatusBar` calls; all layout decisions
+/// are made here.
+pub struct MenuRenderer<A> {
+    current: Option<Menu<A>>,
+    items: Vec<MenuItem>,
+}
+
+impl<A: Clone + PartialEq> MenuRenderer<A> {
+    pub fn new() -> MenuRenderer<A> {
+        MenuRenderer { current: None, items: Vec::new() }
+    }
+    /// The menu that is currently drawn, if any.
+    pub fn current(&self) -> Option<&Menu<A>> {
+        self.current.as_ref()
+    }
+    /// Forget the drawn menu, so the next update redraws it completely.
+    pub fn invalidate(&mut self) {
+        self.current = None;
+        self.items.clear();
+    }
+    /// Bring the status bar up to date with `menu`.  `callback` creates the
+    /// click handler for each action.  Returns true if anything was redrawn.
+    pub fn update<T, F>(&mut self, status: &mut T, menu: Menu<A>, callback: F) -> bool
+        where T: TStatusBar, F: Fn(&A) -> NSCallback {
+        let diff = match self.current {
+            Some(ref current) => current.diff(&menu),
+            None => MenuDiff::Rebuild,
+        };
+        let tooltip_changed = match self.current {
+            Some(ref current) => current.tooltip != menu.tooltip,
+            None => true,
+        };
+        if tooltip_changed {
+            status.set_tooltip(menu.tooltip.as_ref().map_or("", |t| t));
+        }
+        let redrawn = match diff {
+            MenuDiff::Unchanged => tooltip_changed,
+            MenuDiff::Checkmarks(changes) => {
+                for (idx, checked) in changes {
+                    if let Some(item) = self.items.get(idx) {
+                        match checked {
+                            true => status.sel_item(*item as u64),
+                            false => status.unsel_item(*item as u64),
+                        }
+                    }
+                }
+                true
+            },
+            MenuDiff::Rebuild => {
+                status.clear_items();
+                self.items = render(status, &menu, &callback);
+                true
+            },
+        };
+        self.current = Some(menu);
+        redrawn
+    }
+}
+
+fn render_entries<T, A, F>(status: &mut T, parent: Option<MenuItem>,
+                           entries: &Vec<MenuEntry<A>>, callback: &F,
+                           items: &mut Vec<MenuItem>)
+    where T: TStatusBar, F: Fn(&A) -> NSCallback {
+    for entry in entries {
+        match entry {
+            &MenuEntry::Label(ref label) => status.add_label(label),
+            &MenuEntry::Separator => status.add_separator(),
+            &MenuEntry::Action { ref label, ref action, checked } => {
+                items.push(status.add_item(parent, label, callback(action), checked));
+            },
+            &MenuEntry::Submenu { ref label, ref entries } => {
+                match status.supports_submenus() {
+                    true => {
+                        let submenu = status.add_submenu(label, Box::new(move |_,_| {}));
+                        render_entries(status, Some(submenu), entries, callback, items);
+                    },
+                    false => {
+                        // Flatten into the parent menu, under a heading
+                        status.add_label(label);
+                        render_entries(status, parent, entries, callback, items);
+                    },
+                }
+            },
+            &MenuEntry::Quit(ref label) => status.add_quit(label),
+        }
+    }
+}
+
+/// Draw a complete menu onto an empty status bar.  Returns the handles of the
+/// drawn actions, in the same order as `Menu::actions()`.
+pub fn render<T, A, F>(status: &mut T, menu: &Menu<A>, callback: &F) -> Vec<MenuItem>
+    where T: TStatusBar, F: Fn(&A) -> NSCallback {
+    let mut items = Vec::new();
+    for (idx, section) in menu.sections.iter().enumerate() {
+        match section.title {
+            Some(ref title) => {
+                if idx > 0 {
+                    status.add_label("");
+                }
+                status.add_label(title);
+                status.add_separator();
+            },
+            None => {
+                if idx > 0 {
+                    status.add_separator();
+                }
+            },
+        }
+        render_entries(status, None, &section.entries, callback, &mut items);
+    }
+    items
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::super::super::{DummyStatusBar, TStatusBar};
+    use super::super::super::controller::CommandMessage;
+
+    use std::sync::mpsc::channel;
+
+    fn player_menu(playing: bool, device: usize) -> Menu<u32> {
+        let mut menu = Menu::new();
+        menu.tooltip("Track\nArtist\nAlbum")
+            .section(Some("Actions:"))
+            .action(if playing { "Pause" } else { "Play" }, 1, false)
+            .action("Next", 2, false)
+            .section(Some("Devices:"))
+            .action("Desktop", 10, device == 0)
+            .action("Speaker", 11, device == 1);
+        let volume = (0..3).map(|i| MenuEntry::Action {
+            label: format!("{}%", i * 50),
+            action: 100 + i,
+            checked: i == 1,
+        }).collect();
+        menu.section(None)
+            .submenu("Volume", volume)
+            .section(None)
+            .quit("Exit");
+        menu
+    }
+
+    #[test]
+    fn test_menu_actions_in_draw_order() {
+        let menu = player_menu(true, 0);
+        let actions: Vec<u32> = menu.actions().iter().map(|a| *a.1).collect();
+        assert_eq!(actions, vec![1, 2, 10, 11, 100, 101, 102]);
+        assert_eq!(menu.actions()[2].2, true);
+        assert_eq!(menu.actions()[5].2, true);
+    }
+
+    #[test]
+    fn test_menu_diff() {
+        // Identical state gives an identical menu
+        assert_eq!(player_menu(true, 0).diff(&player_menu(true, 0)), MenuDiff::Unchanged);
+
+        // Switching device only moves checkmarks
+        assert_eq!(player_menu(true, 0).diff(&player_menu(true, 1)),
+                   MenuDiff::Checkmarks(vec![(2, false), (3, true)]));
+
+        // Changing a label requires a redraw
+        assert_eq!(player_menu(true, 0).diff(&player_menu(false, 0)), MenuDiff::Rebuild);
+
+        // Adding an entry requires a redraw
+        let mut bigger = player_menu(true, 0);
+        bigger.action("Another", 200, false);
+        assert_eq!(player_menu(true, 0).diff(&bigger), MenuDiff::Rebuild);
+
+        // The tooltip isn't part of the drawn menu
+        let mut tooltip = player_menu(true, 0);
+        tooltip.tooltip("Something else");
+        assert_eq!(player_menu(true, 0).diff(&tooltip), MenuDiff::Unchanged);
+    }
+
+    #[test]
+    fn test_menu_renderer_skips_unchanged() {
+        let (tx, _rx) = channel::<CommandMessage>();
+        let mut status = DummyStatusBar::new(tx);
+        let mut renderer = MenuRenderer::new();
+        let cb = |_: &u32| -> NSCallback { Box::new(|_, _| {}) };
+        assert!(renderer.update(&mut status, player_menu(true, 0), &cb));
+        assert!(!renderer.update(&mut status, player_menu(true, 0), &cb));
+        assert!(renderer.update(&mut status, player_menu(true, 1), &cb));
+        assert_eq!(renderer.current(), Some(&player_menu(true, 1)));
+        renderer.invalidate();
+        assert!(renderer.update(&mut status, player_menu(true, 1), &cb));
+    }
+}
@@ -9,6 +9,7 @@ use self::fruitbasket::FruitApp;
 
 pub use ::TStatusBar;
 pub use ::NSCallback;
+use ::controller::CommandMessage;
 
 use objc::runtime::Class;
 
@@ -39,7 +40,7 @@ pub struct OSXStatusBar {
 
 impl TStatusBar for OSXStatusBar {
     type S = OSXStatusBar;
-    fn new(tx: Sender<String>) -> OSXStatusBar {
+    fn new(tx: Sender<CommandMessage>) -> OSXStatusBar {
         let mut bar;
         unsafe {
             let nsapp = FruitApp::new();
@@ -176,6 +177,9 @@ impl TStatusBar for OSXStatusBar {
             item
         }
     }
+    fn supports_submenus(&self) -> bool {
+        true
+    }
     fn add_submenu(&mut self, label: &str, callback: NSCallback) -> *mut Object {
         unsafe {
             let submenu = NSMenu::new(nil);
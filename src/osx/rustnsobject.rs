@@ -7,6 +7,7 @@ extern crate objc_foundation;
 extern crate objc_id;
 
 pub use ::NSCallback;
+use ::controller::CommandMessage;
 
 use std::sync::Once;
 
@@ -27,7 +28,7 @@ pub struct RustWrapperClass {
     pub objc: Id<ObjcSubclass, Shared>,
     pub cb_fn: Option<Box<dyn Fn(&mut RustWrapperClass, u64)>>,
     pub map: BTreeMap<u64, NSCallback>,
-    pub tx: Sender<String>,
+    pub tx: Sender<CommandMessage>,
 }
 
 pub type NSObj = Box<RustWrapperClass>;
@@ -48,7 +49,7 @@ impl NSObjCallbackTrait for RustWrapperClass {
 }
 
 pub trait NSObjTrait {
-    fn alloc(tx: Sender<String>) -> NSObj;
+    fn alloc(tx: Sender<CommandMessage>) -> NSObj;
     fn selector(&self) -> Sel;
     fn take_objc(&mut self) -> NSObjc;
     fn add_callback(&mut self, *const Object, NSCallback);
@@ -59,7 +60,7 @@ impl NSObjTrait for NSObj {
         let sender: u64 = item as u64;
         self.set_value(sender, cb);
     }
-    fn alloc(tx: Sender<String>) -> NSObj {
+    fn alloc(tx: Sender<CommandMessage>) -> NSObj {
         let objc = ObjcSubclass::new().share();
         let rust = Box::new(RustWrapperClass {
             objc: objc,
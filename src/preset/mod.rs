@@ -0,0 +1,369 @@
+// Presets: named things to play from the menu.
+//
+// In connectr.ini, a preset is a space-separated list of Spotify URIs
+// followed by optional settings:
+//
+//   Wedding = spotify:playlist:2p2UgYlbg4yG44IKDp08Q8 track=5
+//   First Dance = spotify:track:6rqhFgbbKwnb9MLmUQDhG6 start=1:23
+//   Encore = spotify:track:6rqhFgbbKwnb9MLmUQDhG6 spotify:track:4uLU6hMCjMI75M1A2tKUQC
+//
+// A preset is either one context (playlist, album, artist or show), or a
+// list of tracks and episodes.  `track=` picks where to start, either as a
+// track number counting from 1 or as a track URI.  `start=` is the time
+// into that track, as M:SS or seconds.
+//
+// More comma-separated fields can set up the player before it starts:
+//
+//   Dinner = spotify:playlist:37i9dQZF1DX4xuWVBs4FgJ, device Kitchen, volume 25, shuffle on
+//
+// `device` is a device name or ID, `volume` is a percentage, `shuffle` is
+// on or off, and `repeat` is off, track or context.  `resume` starts a
+// context where it was left last time, instead of from the beginning.
+// `weight` makes "Surprise Me" pick the preset more or less often; the
+// default is 1, and 0 leaves it out.
+//
+// Names can start with a group, which the menu shows as a submenu:
+//
+//   Focus/Deep Work = spotify:playlist:37i9dQZF1DWZeKCadgRdKQ
+//
+// A doubled slash is a slash in the name, so "AC//DC" is a preset called
+// AC/DC, and "Rock/AC//DC" is the same preset in the Rock group.
+
+#[cfg(test)]
+mod test;
+
+mod rotation;
+pub use self::rotation::{Rotation, Surprise, random_roll};
+
+use std::fmt;
+
+use super::{PlayContext, PlayerState, SpotifyRepeat};
+use super::uri::SpotifyUri;
+
+/// Which track of a preset to start on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PresetOffset {
+    /// Track number, counting from 0
+    Position(u32),
+    Uri(String),
+}
+
+/// What a preset plays.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PresetTarget {
+    Context(String),
+    Tracks(Vec<String>),
+}
+
+/// Player settings to apply before a preset starts playing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PresetOptions {
+    /// Device name or ID
+    pub device: Option<String>,
+    pub volume: Option<u32>,
+    pub shuffle: Option<bool>,
+    pub repeat: Option<SpotifyRepeat>,
+    /// Start where the context was left, instead of at the beginning
+    pub resume: bool,
+    /// How likely "Surprise Me" is to pick the preset
+    pub weight: Option<u32>,
+}
+
+impl PresetOptions {
+    /// Parse one option field, such as "volume 25".  Returns false if the
+    /// field isn't an option.
+    pub fn parse_field(&mut self, field: &str) -> Result<bool, String> {
+        let field = field.trim();
+        let (key, value) = match field.find(char::is_whitespace) {
+            Some(idx) => (&field[..idx], field[idx..].trim()),
+            None => (field, ""),
+        };
+        match key.to_lowercase().as_str() {
+            "device" => {
+                if value.is_empty() {
+                    return Err("Missing device name".to_string());
+                }
+                self.device = Some(value.to_string());
+            },
+            "volume" => {
+                self.volume = match value.trim_end_matches("%").parse::<u32>() {
+                    Ok(vol) if vol <= 100 => Some(vol),
+                    _ => return Err(format!("Invalid volume: {}", value)),
+                };
+            },
+            "shuffle" => {
+                self.shuffle = Some(match value.to_lowercase().as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Shuffle must be on or off: {}", value)),
+                });
+            },
+            "resume" => {
+                self.resume = match value.to_lowercase().as_str() {
+                    "" | "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Resume must be on or off: {}", value)),
+                };
+            },
+            "weight" => {
+                self.weight = match value.parse::<u32>() {
+                    Ok(weight) => Some(weight),
+                    _ => return Err(format!("Invalid weight: {}", value)),
+                };
+            },
+            "repeat" => {
+                self.repeat = Some(match value.to_lowercase().as_str() {
+                    "off" => SpotifyRepeat::Off,
+                    "track" => SpotifyRepeat::Track,
+                    "context" => SpotifyRepeat::Context,
+                    _ => return Err(format!("Repeat must be off, track or context: {}", value)),
+                });
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Option fields to write to connectr.ini.
+    pub fn fields(&self) -> Vec<String> {
+        let mut fields = Vec::new();
+        if let Some(ref device) = self.device {
+            fields.push(format!("device {}", device));
+        }
+        if let Some(vol) = self.volume {
+            fields.push(format!("volume {}", vol));
+        }
+        if let Some(shuffle) = self.shuffle {
+            fields.push(format!("shuffle {}", match shuffle { true => "on", false => "off" }));
+        }
+        if let Some(repeat) = self.repeat {
+            fields.push(format!("repeat {}", repeat.to_string()));
+        }
+        if self.resume {
+            fields.push("resume".to_string());
+        }
+        if let Some(weight) = self.weight {
+            fields.push(format!("weight {}", weight));
+        }
+        fields
+    }
+
+    /// Weight for choosing the preset at random.
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    /// Menu group the preset is listed under
+    pub group: Option<String>,
+    pub target: PresetTarget,
+    pub offset: Option<PresetOffset>,
+    pub position_ms: Option<u64>,
+    pub options: PresetOptions,
+}
+
+impl Preset {
+    /// Preset that plays a context from the beginning.
+    pub fn new(name: &str, context: &str) -> Preset {
+        Preset {
+            name: name.to_string(),
+            group: None,
+            target: PresetTarget::Context(context.to_string()),
+            offset: None,
+            position_ms: None,
+            options: PresetOptions::default(),
+        }
+    }
+
+    /// Parse a preset from its key and the first field of its value in
+    /// connectr.ini.  A key like "Group/Name" puts the preset in a group,
+    /// and "//" is a slash in the name.  Options are added with
+    /// `PresetOptions::parse_field`.
+    pub fn parse(key: &str, spec: &str) -> Result<Preset, String> {
+        let (group, name) = match group_separator(key) {
+            Some(idx) if !key[..idx].trim().is_empty() && !key[idx+1..].trim().is_empty() => {
+                (Some(key[..idx].trim().replace("//", "/")), key[idx+1..].trim().replace("//", "/"))
+            },
+            _ => (None, key.replace("//", "/")),
+        };
+        let name = name.as_str();
+        let mut uris: Vec<SpotifyUri> = Vec::new();
+        let mut offset = None;
+        let mut position_ms = None;
+        for token in spec.split_whitespace() {
+            if token.starts_with("track=") {
+                let track = &token["track=".len()..];
+                offset = Some(match track.parse::<u32>() {
+                    Ok(n) if n > 0 => PresetOffset::Position(n - 1),
+                    Ok(_) => return Err("Track numbers start at 1".to_string()),
+                    Err(_) => PresetOffset::Uri(track.parse::<SpotifyUri>()?.to_string()),
+                });
+            }
+            else if token.starts_with("start=") {
+                position_ms = Some(parse_time(&token["start=".len()..])?);
+            }
+            else {
+                uris.push(token.parse()?);
+            }
+        }
+        let target = match uris.len() {
+            0 => return Err(format!("Preset {} has nothing to play", name)),
+            1 if uris[0].is_context() => PresetTarget::Context(uris[0].to_string()),
+            _ if uris.iter().all(|u| u.is_item()) => {
+                PresetTarget::Tracks(uris.iter().map(|u| u.to_string()).collect())
+            },
+            _ => return Err(format!("Preset {} must be one context, or only tracks", name)),
+        };
+        Ok(Preset {
+            name: name.to_string(),
+            group: group,
+            target: target,
+            offset: offset,
+            position_ms: position_ms,
+            options: PresetOptions::default(),
+        })
+    }
+
+    /// Key to write to connectr.ini, which includes the group.
+    pub fn key(&self) -> String {
+        match self.group {
+            Some(ref group) => format!("{}/{}", group.replace("/", "//"), self.name.replace("/", "//")),
+            None => self.name.replace("/", "//"),
+        }
+    }
+
+    /// First field to write to connectr.ini, which parses back to the same
+    /// preset, without its options.
+    pub fn spec(&self) -> String {
+        let mut spec = match self.target {
+            PresetTarget::Context(ref uri) => uri.clone(),
+            PresetTarget::Tracks(ref uris) => uris.join(" "),
+        };
+        match self.offset {
+            Some(PresetOffset::Position(n)) => spec.push_str(&format!(" track={}", n + 1)),
+            Some(PresetOffset::Uri(ref uri)) => spec.push_str(&format!(" track={}", uri)),
+            None => {},
+        }
+        if let Some(ms) = self.position_ms {
+            spec.push_str(&format!(" start={}:{:02}", ms / 60000, (ms / 1000) % 60));
+        }
+        spec
+    }
+
+    /// URI that identifies the preset: its context, or its first track.
+    pub fn uri(&self) -> &str {
+        match self.target {
+            PresetTarget::Context(ref uri) => uri,
+            PresetTarget::Tracks(ref uris) => &uris[0],
+        }
+    }
+
+    /// The context the preset plays, if it isn't a list of tracks.
+    pub fn context(&self) -> Option<&str> {
+        match self.target {
+            PresetTarget::Context(ref uri) => Some(uri),
+            PresetTarget::Tracks(_) => None,
+        }
+    }
+
+    /// Request body to start playing the preset.
+    pub fn play_context(&self) -> PlayContext {
+        let mut ctx = PlayContext::new();
+        match self.target {
+            PresetTarget::Context(ref uri) => { ctx.context_uri(uri); },
+            PresetTarget::Tracks(ref uris) => {
+                for uri in uris {
+                    ctx.uri(uri);
+                }
+            },
+        }
+        match self.offset {
+            Some(PresetOffset::Position(n)) => { ctx.offset_position(n); },
+            Some(PresetOffset::Uri(ref uri)) => { ctx.offset_uri(uri); },
+            None => { ctx.offset_position(0); },
+        }
+        if let Some(ms) = self.position_ms {
+            ctx.position_ms(ms);
+        }
+        ctx.build()
+    }
+
+    /// Whether the player is playing from this preset.
+    pub fn is_playing(&self, player: &PlayerState) -> bool {
+        match self.target {
+            PresetTarget::Context(ref uri) => player.playing_from_context(uri),
+            PresetTarget::Tracks(ref uris) => {
+                player.context.is_none() && match player.item {
+                    Some(ref item) => uris.iter().any(|u| u == item.uri()),
+                    None => false,
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.key(), self.spec())?;
+        for field in self.options.fields() {
+            write!(f, ", {}", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// A preset, or a group of them, as listed in a menu.
+#[derive(Debug, PartialEq)]
+pub enum PresetMenuItem<'a> {
+    Preset(&'a Preset),
+    Group(&'a str, Vec<&'a Preset>),
+}
+
+/// Presets in menu order.  Each group is listed where its first preset is.
+pub fn menu_items<'a>(presets: &'a [Preset]) -> Vec<PresetMenuItem<'a>> {
+    let mut items = Vec::new();
+    for (idx, preset) in presets.iter().enumerate() {
+        match preset.group {
+            None => items.push(PresetMenuItem::Preset(preset)),
+            Some(ref group) => {
+                if presets[..idx].iter().any(|p| p.group.as_ref() == Some(group)) {
+                    continue;
+                }
+                let members = presets[idx..].iter()
+                    .filter(|p| p.group.as_ref() == Some(group))
+                    .collect();
+                items.push(PresetMenuItem::Group(group, members));
+            },
+        }
+    }
+    items
+}
+
+/// Index of the first slash in a preset key that isn't part of a "//".
+fn group_separator(key: &str) -> Option<usize> {
+    let bytes = key.as_bytes();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'/' {
+            if bytes.get(idx + 1) != Some(&b'/') {
+                return Some(idx);
+            }
+            idx += 1;
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// Parse "M:SS" or plain seconds into milliseconds.
+fn parse_time(time: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid start time: {}", time);
+    let mut secs: u64 = 0;
+    for field in time.split(":") {
+        secs = secs * 60 + field.parse::<u64>().map_err(|_| invalid())?;
+    }
+    Ok(secs * 1000)
+}
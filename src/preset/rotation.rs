@@ -0,0 +1,123 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::Preset;
+use super::super::state_file;
+
+/// A "Surprise me" target: a random preset, optionally from one group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Surprise {
+    pub group: Option<String>,
+}
+
+impl FromStr for Surprise {
+    type Err = String;
+    /// Parse "random", or "random:Group" for a preset from one group.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut parts = s.splitn(2, ":");
+        if parts.next().map(|p| p.to_lowercase()) != Some("random".to_string()) {
+            return Err(format!("Not a random preset: {}", s));
+        }
+        let group = parts.next().map(|g| g.trim().to_string()).filter(|g| !g.is_empty());
+        Ok(Surprise { group: group })
+    }
+}
+
+impl fmt::Display for Surprise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.group {
+            Some(ref group) => write!(f, "random:{}", group),
+            None => write!(f, "random"),
+        }
+    }
+}
+
+/// Contents of the rotation file.
+#[derive(Serialize, Deserialize, Default)]
+struct RotationFile {
+    #[serde(default)]
+    recent: Vec<String>,
+}
+
+/// Presets recently chosen at random, most recent first, so they aren't
+/// chosen again too soon.  Saved in a local state file.
+pub struct Rotation {
+    path: Option<String>,
+    recent: Vec<String>,
+    /// How many of the most recent choices to avoid
+    avoid: usize,
+}
+
+impl Rotation {
+    /// Load the recent choices.  Without a file, they are only kept until
+    /// Connectr exits.
+    pub fn load(path: Option<&str>, avoid: usize) -> Rotation {
+        let mut state: RotationFile = path.and_then(|p| state_file::load(p, "preset rotation"))
+            .unwrap_or_default();
+        state.recent.truncate(avoid);
+        Rotation {
+            path: path.map(|p| p.to_string()),
+            recent: state.recent,
+            avoid: avoid,
+        }
+    }
+    /// Keys of the recent choices, most recent first.
+    pub fn recent(&self) -> &Vec<String> {
+        &self.recent
+    }
+    /// Choose a preset, from one group or from all of them.  `roll` is a
+    /// random number from 0 to 1.  Presets are weighted by their `weight`
+    /// option, and recent choices are skipped unless nothing else is left.
+    pub fn choose<'a>(&self, presets: &'a [Preset], group: Option<&str>, roll: f64) -> Option<&'a Preset> {
+        let candidates: Vec<&Preset> = presets.iter()
+            .filter(|p| group.is_none() || p.group.as_ref().map(|g| g.as_str()) == group)
+            .filter(|p| p.options.weight() > 0)
+            .collect();
+        let fresh: Vec<&Preset> = candidates.iter()
+            .filter(|p| !self.recent.contains(&p.key()))
+            .cloned()
+            .collect();
+        let pool = match fresh.is_empty() {
+            true => candidates,
+            false => fresh,
+        };
+        let total: u32 = pool.iter().map(|p| p.options.weight()).sum();
+        let mut target = (roll.max(0.0).min(1.0) * total as f64) as u32;
+        for preset in pool.iter() {
+            if target < preset.options.weight() {
+                return Some(preset);
+            }
+            target -= preset.options.weight();
+        }
+        pool.last().cloned()
+    }
+    /// Remember that a preset was chosen, and save the rotation file.
+    pub fn record(&mut self, preset: &Preset) {
+        let key = preset.key();
+        self.recent.retain(|k| *k != key);
+        self.recent.insert(0, key);
+        self.recent.truncate(self.avoid);
+        self.save();
+    }
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            let state = RotationFile { recent: self.recent.clone() };
+            state_file::save(path, "preset rotation", &state);
+        }
+    }
+}
+
+/// A random number from 0 to 1, good enough to pick a preset.
+pub fn random_roll() -> f64 {
+    // Each RandomState is seeded differently, so hashing the time with a
+    // fresh one gives a new number every call.
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        elapsed.as_nanos().hash(&mut hasher);
+    }
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
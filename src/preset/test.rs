@@ -0,0 +1,161 @@
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::super::*;
+
+    #[test]
+    fn test_parse_preset() {
+        let preset = Preset::parse("Morning", "spotify:playlist:morning").unwrap();
+        assert_eq!(preset, Preset::new("Morning", "spotify:playlist:morning"));
+        assert_eq!(preset.spec(), "spotify:playlist:morning");
+        assert_eq!(preset.to_string(), "Morning = spotify:playlist:morning");
+
+        // Links and old style URIs are cleaned up
+        let preset = Preset::parse("Wedding",
+                                   " https://open.spotify.com/playlist/wedding?si=x  track=5 ").unwrap();
+        assert_eq!(preset.target, PresetTarget::Context("spotify:playlist:wedding".to_string()));
+        assert_eq!(preset.offset, Some(PresetOffset::Position(4)));
+        assert_eq!(preset.spec(), "spotify:playlist:wedding track=5");
+
+        let preset = Preset::parse("Best Bit", "spotify:track:best start=1:23").unwrap();
+        assert_eq!(preset.target, PresetTarget::Tracks(vec!["spotify:track:best".to_string()]));
+        assert_eq!(preset.position_ms, Some(83000));
+        assert_eq!(preset.uri(), "spotify:track:best");
+        assert_eq!(preset.context(), None);
+        assert_eq!(preset.spec(), "spotify:track:best start=1:23");
+
+        let preset = Preset::parse("Encore", "spotify:track:one spotify:episode:two \
+                                              track=spotify:track:one start=90").unwrap();
+        assert_eq!(preset.offset, Some(PresetOffset::Uri("spotify:track:one".to_string())));
+        assert_eq!(preset.position_ms, Some(90000));
+        assert_eq!(Preset::parse("Encore", &preset.spec()), Ok(preset));
+
+        assert!(Preset::parse("Empty", "").is_err());
+        assert!(Preset::parse("Empty", "start=1:00").is_err());
+        assert!(Preset::parse("Two", "spotify:album:a spotify:album:b").is_err());
+        assert!(Preset::parse("Mixed", "spotify:album:a spotify:track:b").is_err());
+        assert!(Preset::parse("Zero", "spotify:album:a track=0").is_err());
+        assert!(Preset::parse("Time", "spotify:album:a start=1:xx").is_err());
+        assert!(Preset::parse("Junk", "spotify:album:a junk").is_err());
+    }
+
+    #[test]
+    fn test_play_context() {
+        let json = |preset: &str| {
+            let preset = Preset::parse("Test", preset).unwrap();
+            serde_json::to_string(&preset.play_context()).unwrap()
+        };
+        assert_eq!(json("spotify:album:a"),
+                   r#"{"context_uri":"spotify:album:a","uris":null,"offset":{"position":0,"uri":null}}"#);
+        assert_eq!(json("spotify:album:a track=5 start=0:30"),
+                   r#"{"context_uri":"spotify:album:a","uris":null,"offset":{"position":4,"uri":null},"position_ms":30000}"#);
+        assert_eq!(json("spotify:track:a spotify:track:b track=spotify:track:b"),
+                   r#"{"context_uri":null,"uris":["spotify:track:a","spotify:track:b"],"offset":{"position":null,"uri":"spotify:track:b"}}"#);
+    }
+
+    #[test]
+    fn test_preset_options() {
+        let mut options = PresetOptions::default();
+        assert_eq!(options.parse_field(" device Living Room "), Ok(true));
+        assert_eq!(options.parse_field("volume 25%"), Ok(true));
+        assert_eq!(options.parse_field("Shuffle ON"), Ok(true));
+        assert_eq!(options.parse_field("repeat context"), Ok(true));
+        assert_eq!(options.parse_field("resume"), Ok(true));
+        assert_eq!(options, PresetOptions {
+            device: Some("Living Room".to_string()),
+            volume: Some(25),
+            shuffle: Some(true),
+            repeat: Some(SpotifyRepeat::Context),
+            resume: true,
+            weight: None,
+        });
+        assert_eq!(options.fields(), vec!["device Living Room", "volume 25",
+                                          "shuffle on", "repeat context", "resume"]);
+        assert_eq!(options.parse_field("resume off"), Ok(true));
+        assert!(!options.resume);
+
+        // Anything else might be a quick-save playlist
+        assert_eq!(options.parse_field("spotify:playlist:save"), Ok(false));
+
+        assert!(options.parse_field("device").is_err());
+        assert!(options.parse_field("volume 101").is_err());
+        assert!(options.parse_field("shuffle maybe").is_err());
+        assert!(options.parse_field("repeat all").is_err());
+        assert!(options.parse_field("resume later").is_err());
+        assert!(options.parse_field("weight -1").is_err());
+        assert_eq!(options.weight(), 1);
+        assert_eq!(options.parse_field("weight 0"), Ok(true));
+        assert_eq!(options.weight(), 0);
+
+        let mut preset = Preset::new("Dinner", "spotify:playlist:dinner");
+        preset.options.volume = Some(25);
+        preset.options.shuffle = Some(false);
+        assert_eq!(preset.to_string(), "Dinner = spotify:playlist:dinner, volume 25, shuffle off");
+    }
+
+    #[test]
+    fn test_preset_groups() {
+        let preset = Preset::parse(" Focus / Deep Work ", "spotify:playlist:deep").unwrap();
+        assert_eq!(preset.group, Some("Focus".to_string()));
+        assert_eq!(preset.name, "Deep Work");
+        assert_eq!(preset.key(), "Focus/Deep Work");
+        assert_eq!(preset.to_string(), "Focus/Deep Work = spotify:playlist:deep");
+        // Only the first slash starts a group
+        let preset = Preset::parse("Rock/AC/DC", "spotify:artist:acdc").unwrap();
+        assert_eq!((preset.group.as_ref().map(|g| g.as_str()), preset.name.as_str()), (Some("Rock"), "AC/DC"));
+        assert_eq!(Preset::parse("/Odd", "spotify:album:odd").unwrap().group, None);
+
+        // A doubled slash is part of the name
+        let preset = Preset::parse("AC//DC", "spotify:artist:acdc").unwrap();
+        assert_eq!((preset.group.as_ref(), preset.name.as_str()), (None, "AC/DC"));
+        assert_eq!(preset.key(), "AC//DC");
+        let preset = Preset::parse("Rock // Metal/AC//DC", "spotify:artist:acdc").unwrap();
+        assert_eq!((preset.group.as_ref().map(|g| g.as_str()), preset.name.as_str()), (Some("Rock / Metal"), "AC/DC"));
+        assert_eq!(preset.key(), "Rock // Metal/AC//DC");
+        let preset = Preset::new("Either/Or", "spotify:album:either");
+        assert_eq!(Preset::parse(&preset.key(), "spotify:album:either").unwrap(), preset);
+
+        let presets: Vec<Preset> = ["Morning", "Focus/Deep", "Evening", "Focus/Light", "Party/Loud"]
+            .iter().map(|key| Preset::parse(key, "spotify:album:a").unwrap()).collect();
+        assert_eq!(menu_items(&presets), vec![
+            PresetMenuItem::Preset(&presets[0]),
+            PresetMenuItem::Group("Focus", vec![&presets[1], &presets[3]]),
+            PresetMenuItem::Preset(&presets[2]),
+            PresetMenuItem::Group("Party", vec![&presets[4]]),
+        ]);
+    }
+
+    #[test]
+    fn test_surprise() {
+        assert_eq!("random".parse(), Ok(Surprise { group: None }));
+        assert_eq!(" Random:Focus ".parse(), Ok(Surprise { group: Some("Focus".to_string()) }));
+        assert_eq!(Surprise { group: Some("Focus".to_string()) }.to_string(), "random:Focus");
+        assert!("spotify:playlist:random".parse::<Surprise>().is_err());
+
+        let mut presets: Vec<Preset> = ["Morning", "Focus/Deep", "Focus/Light", "Evening"]
+            .iter().map(|key| Preset::parse(key, "spotify:album:a").unwrap()).collect();
+        presets[0].options.weight = Some(3);
+        presets[3].options.weight = Some(0);
+        let mut rotation = Rotation::load(None, 2);
+        // Weights: Morning 3, Deep 1, Light 1, Evening never
+        assert_eq!(rotation.choose(&presets, None, 0.0), Some(&presets[0]));
+        assert_eq!(rotation.choose(&presets, None, 0.59), Some(&presets[0]));
+        assert_eq!(rotation.choose(&presets, None, 0.61), Some(&presets[1]));
+        assert_eq!(rotation.choose(&presets, None, 1.0), Some(&presets[2]));
+        assert_eq!(rotation.choose(&presets, Some("Focus"), 0.0), Some(&presets[1]));
+        assert_eq!(rotation.choose(&presets, Some("Party"), 0.0), None);
+
+        // Recent choices are avoided until nothing else is left
+        rotation.record(&presets[1]);
+        assert_eq!(rotation.choose(&presets, Some("Focus"), 0.0), Some(&presets[2]));
+        rotation.record(&presets[2]);
+        assert_eq!(rotation.choose(&presets, Some("Focus"), 0.0), Some(&presets[1]));
+        assert_eq!(rotation.choose(&presets, None, 0.99), Some(&presets[0]));
+        rotation.record(&presets[0]);
+        assert_eq!(rotation.recent(), &vec!["Morning".to_string(), "Focus/Light".to_string()]);
+
+        let roll = random_roll();
+        assert!(roll >= 0.0 && roll < 1.0);
+    }
+}
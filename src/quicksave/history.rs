@@ -0,0 +1,74 @@
+use super::super::state_file;
+
+// How many quick-saves to keep in the log.
+pub const QUICKSAVE_MAX_ENTRIES: usize = 20;
+
+/// A track that quick-save added to a playlist.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedTrack {
+    pub uri: String,
+    pub name: String,
+    pub playlist: String,
+    /// Playlist version that Spotify returned after adding the track
+    pub snapshot_id: Option<String>,
+    /// Where the track was added, counting from 0
+    pub position: Option<u32>,
+    /// When it was saved, in seconds since the epoch
+    pub time: i64,
+    /// Saves to several playlists at once share the number, set by
+    /// `SaveHistory::record`
+    pub batch: u64,
+}
+
+/// Recent quick-saves, most recent first.  Saved in a local state file, so
+/// they can be undone after a restart.
+pub struct SaveHistory {
+    path: Option<String>,
+    saves: Vec<SavedTrack>,
+}
+
+impl SaveHistory {
+    /// Load the log.  Without a file, it's only kept until Connectr exits.
+    pub fn load(path: Option<&str>) -> SaveHistory {
+        SaveHistory {
+            path: path.map(|p| p.to_string()),
+            saves: path.and_then(|p| state_file::load(p, "quick-save log")).unwrap_or_default(),
+        }
+    }
+    pub fn saves(&self) -> &Vec<SavedTrack> {
+        &self.saves
+    }
+    /// Add the playlists one quick-save went to, and save the log.
+    pub fn record(&mut self, saved: Vec<SavedTrack>) {
+        if saved.is_empty() {
+            return;
+        }
+        let batch = self.saves.first().map(|s| s.batch + 1).unwrap_or(0);
+        for (idx, mut save) in saved.into_iter().enumerate() {
+            save.batch = batch;
+            self.saves.insert(idx, save);
+        }
+        self.saves.truncate(QUICKSAVE_MAX_ENTRIES);
+        self.save();
+    }
+    /// The playlists the most recent quick-save went to.
+    pub fn last(&self) -> Vec<SavedTrack> {
+        match self.saves.first() {
+            Some(first) => self.saves.iter()
+                .take_while(|s| s.batch == first.batch)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    /// Drop a quick-save that was undone, and save the log.
+    pub fn forget(&mut self, saved: &SavedTrack) {
+        self.saves.retain(|s| s != saved);
+        self.save();
+    }
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            state_file::save(path, "quick-save log", &self.saves);
+        }
+    }
+}
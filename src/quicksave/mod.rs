@@ -0,0 +1,231 @@
+// Quick-save routing rules.
+//
+// Besides each preset's quick-save playlist and the default one, the
+// [quicksave] section of connectr.ini can hold rules that pick playlists by
+// what's playing, where and when:
+//
+//   Sebadoh = spotify:playlist:4aqg0RkXSxknWvIXARV7or spotify:playlist:37i9dQZF1DX4sWSpwq3LiO, artist Sebadoh
+//   Late Night = spotify:playlist:37i9dQZF1DWZeKCadgRdKQ, time 22:00-06:00, device Bedroom
+//   From Albums = spotify:playlist:37i9dQZF1DX4xuWVBs4FgJ, context album
+//
+// The first field lists playlists separated by spaces, and the rest are
+// conditions.  A rule saves to all of its playlists when every condition
+// matches.  Every rule is checked, from top to bottom, so one quick-save
+// can add the track to several playlists.
+//
+// Playlists that already have the track are skipped, and recent saves are
+// logged so the last one can be undone.
+
+#[cfg(test)]
+mod test;
+
+mod history;
+pub use self::history::{SaveHistory, SavedTrack, QUICKSAVE_MAX_ENTRIES};
+
+use std::fmt;
+
+use super::{PlayerState, ConnectPlaybackItem};
+use super::controller::quick_save_context;
+use super::uri::{SpotifyUri, SpotifyUriKind};
+
+/// What's being quick-saved, and where and when.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuickSave {
+    /// The track or episode to save
+    pub uri: String,
+    pub name: String,
+    /// Context that decides where it's saved, see `quick_save_context`
+    pub context: Option<String>,
+    pub artists: Vec<String>,
+    pub device_name: String,
+    pub device_id: Option<String>,
+    /// Local time, in minutes since midnight
+    pub minute: u32,
+}
+
+impl QuickSave {
+    /// What's playing in `player_state`, if anything.
+    pub fn from_player(player_state: &PlayerState, minute: u32) -> Option<QuickSave> {
+        let item = player_state.item.as_ref()?;
+        let artists = match *item {
+            ConnectPlaybackItem::Track(ref t) => t.artists.iter().map(|a| a.name.clone()).collect(),
+            ConnectPlaybackItem::Episode(ref e) => vec![e.show.publisher.clone()],
+        };
+        Some(QuickSave {
+            uri: item.uri().to_string(),
+            name: item.name().to_string(),
+            context: quick_save_context(player_state),
+            artists: artists,
+            device_name: player_state.device.name.clone(),
+            device_id: player_state.device.id.clone(),
+            minute: minute,
+        })
+    }
+}
+
+/// Something that must be true for a rule to apply.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveCondition {
+    /// Any artist of the track, or the publisher of an episode, ignoring case
+    Artist(String),
+    /// Device name ignoring case, or device ID
+    Device(String),
+    /// From and until a time of day, in minutes since midnight.  Wraps
+    /// around midnight if it ends before it starts.
+    Time(u32, u32),
+    /// Kind of context, such as album or playlist
+    Context(SpotifyUriKind),
+}
+
+impl SaveCondition {
+    /// Parse one condition field, such as "artist Sebadoh".
+    pub fn parse(field: &str) -> Result<SaveCondition, String> {
+        let field = field.trim();
+        let (key, value) = match field.find(char::is_whitespace) {
+            Some(idx) => (&field[..idx], field[idx..].trim()),
+            None => (field, ""),
+        };
+        if value.is_empty() {
+            return Err(format!("Missing value for {}", key));
+        }
+        match key.to_lowercase().as_str() {
+            "artist" => Ok(SaveCondition::Artist(value.to_string())),
+            "device" => Ok(SaveCondition::Device(value.to_string())),
+            "time" => {
+                let mut times = value.splitn(2, "-");
+                let from = parse_clock(times.next().unwrap())?;
+                let until = match times.next() {
+                    Some(t) => parse_clock(t)?,
+                    None => return Err(format!("Time must be a range like 22:00-06:00: {}", value)),
+                };
+                Ok(SaveCondition::Time(from, until))
+            },
+            "context" => {
+                match value.to_lowercase().parse::<SpotifyUriKind>() {
+                    Ok(kind) => Ok(SaveCondition::Context(kind)),
+                    Err(_) => Err(format!("Unknown context type: {}", value)),
+                }
+            },
+            _ => Err(format!("Unknown quick-save condition: {}", field)),
+        }
+    }
+
+    pub fn matches(&self, save: &QuickSave) -> bool {
+        match *self {
+            SaveCondition::Artist(ref artist) => {
+                save.artists.iter().any(|a| a.to_lowercase() == artist.to_lowercase())
+            },
+            SaveCondition::Device(ref device) => {
+                save.device_id.as_ref() == Some(device) ||
+                    save.device_name.to_lowercase() == device.to_lowercase()
+            },
+            SaveCondition::Time(from, until) => match from <= until {
+                true => save.minute >= from && save.minute < until,
+                false => save.minute >= from || save.minute < until,
+            },
+            SaveCondition::Context(kind) => {
+                save.context.as_ref()
+                    .and_then(|c| c.parse::<SpotifyUri>().ok())
+                    .map(|c| c.kind() == kind)
+                    .unwrap_or(false)
+            },
+        }
+    }
+}
+
+impl fmt::Display for SaveCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveCondition::Artist(ref artist) => write!(f, "artist {}", artist),
+            SaveCondition::Device(ref device) => write!(f, "device {}", device),
+            SaveCondition::Time(from, until) => {
+                write!(f, "time {:02}:{:02}-{:02}:{:02}", from / 60, from % 60, until / 60, until % 60)
+            },
+            SaveCondition::Context(kind) => write!(f, "context {}", kind.name()),
+        }
+    }
+}
+
+/// Playlists to quick-save to when every condition matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveRule {
+    pub name: String,
+    pub playlists: Vec<SpotifyUri>,
+    pub conditions: Vec<SaveCondition>,
+}
+
+impl SaveRule {
+    /// Parse a rule from its key and value in connectr.ini.
+    pub fn parse(name: &str, value: &str) -> Result<SaveRule, String> {
+        let mut fields = value.split(",");
+        let playlists = fields.next().unwrap().split_whitespace()
+            .map(|p| {
+                match p.parse::<SpotifyUri>()? {
+                    ref uri if uri.kind() == SpotifyUriKind::Playlist => Ok(uri.clone()),
+                    _ => Err(format!("Not a playlist: {}", p)),
+                }
+            })
+            .collect::<Result<Vec<SpotifyUri>, String>>()?;
+        if playlists.is_empty() {
+            return Err(format!("Quick-save rule {} has no playlists", name));
+        }
+        let conditions = fields.filter(|f| !f.trim().is_empty())
+            .map(SaveCondition::parse)
+            .collect::<Result<Vec<SaveCondition>, String>>()?;
+        Ok(SaveRule {
+            name: name.trim().to_string(),
+            playlists: playlists,
+            conditions: conditions,
+        })
+    }
+
+    pub fn matches(&self, save: &QuickSave) -> bool {
+        self.conditions.iter().all(|c| c.matches(save))
+    }
+}
+
+impl fmt::Display for SaveRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let playlists: Vec<String> = self.playlists.iter().map(|p| p.to_string()).collect();
+        write!(f, "{} = {}", self.name, playlists.join(" "))?;
+        for condition in &self.conditions {
+            write!(f, ", {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Playlists from every matching rule, in the order the rules are listed
+/// in connectr.ini, and without duplicates.  Each rule is logged, so it's
+/// clear why a track went where it did.
+pub fn route(rules: &[SaveRule], save: &QuickSave) -> Vec<SpotifyUri> {
+    let mut playlists: Vec<SpotifyUri> = Vec::new();
+    for rule in rules {
+        if !rule.matches(save) {
+            info!("Quick-save rule {} doesn't match {}", rule.name, save.uri);
+            continue;
+        }
+        info!("Quick-save rule {} matches {}", rule.name, save.uri);
+        for playlist in &rule.playlists {
+            if !playlists.contains(playlist) {
+                playlists.push(playlist.clone());
+            }
+        }
+    }
+    playlists
+}
+
+/// Parse "H:MM" into minutes since midnight.
+fn parse_clock(time: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time of day: {}", time);
+    let mut parts = time.trim().splitn(2, ":");
+    let hour = parts.next().unwrap().parse::<u32>().map_err(|_| invalid())?;
+    let minute = match parts.next() {
+        Some(m) => m.parse::<u32>().map_err(|_| invalid())?,
+        None => 0,
+    };
+    match hour < 24 && minute < 60 {
+        true => Ok(hour * 60 + minute),
+        false => Err(invalid()),
+    }
+}
@@ -0,0 +1,108 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn quick_save(context: Option<&str>, minute: u32) -> QuickSave {
+        QuickSave {
+            uri: "spotify:track:one".to_string(),
+            name: "One".to_string(),
+            context: context.map(|c| c.to_string()),
+            artists: vec!["Sebadoh".to_string(), "Lou Barlow".to_string()],
+            device_name: "Kitchen".to_string(),
+            device_id: Some("kitchen-id".to_string()),
+            minute: minute,
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rule = SaveRule::parse("Late Night ",
+                                   "spotify:playlist:late https://open.spotify.com/playlist/night?si=x, \
+                                    time 22:00-6:30, Device Bedroom, context album").unwrap();
+        assert_eq!(rule.name, "Late Night");
+        assert_eq!(rule.playlists, vec!["spotify:playlist:late".parse().unwrap(),
+                                        "spotify:playlist:night".parse().unwrap()]);
+        assert_eq!(rule.conditions, vec![
+            SaveCondition::Time(22 * 60, 6 * 60 + 30),
+            SaveCondition::Device("Bedroom".to_string()),
+            SaveCondition::Context(SpotifyUriKind::Album),
+        ]);
+        assert_eq!(rule.to_string(), "Late Night = spotify:playlist:late spotify:playlist:night, \
+                                      time 22:00-06:30, device Bedroom, context album");
+
+        // Rules without conditions always match
+        assert_eq!(SaveRule::parse("All", "spotify:playlist:all").unwrap().conditions, vec![]);
+
+        assert!(SaveRule::parse("Empty", ", artist Sebadoh").is_err());
+        assert!(SaveRule::parse("Album", "spotify:album:bakesale").is_err());
+        assert!(SaveRule::parse("Bad", "spotify:playlist:a, time 22:00").is_err());
+        assert!(SaveRule::parse("Bad", "spotify:playlist:a, time 25:00-26:00").is_err());
+        assert!(SaveRule::parse("Bad", "spotify:playlist:a, context mixtape").is_err());
+        assert!(SaveRule::parse("Bad", "spotify:playlist:a, mood happy").is_err());
+        assert!(SaveRule::parse("Bad", "spotify:playlist:a, artist").is_err());
+    }
+
+    #[test]
+    fn test_route() {
+        let rules: Vec<SaveRule> = vec![
+            ("Barlow", "spotify:playlist:barlow spotify:playlist:indie, artist lou barlow"),
+            ("Kitchen", "spotify:playlist:kitchen spotify:playlist:indie, device kitchen"),
+            ("Night", "spotify:playlist:night, time 22:00-06:00"),
+            ("Albums", "spotify:playlist:albums, context album"),
+        ].iter().map(|&(name, value)| SaveRule::parse(name, value).unwrap()).collect();
+        let names = |save: &QuickSave| -> Vec<String> {
+            route(&rules, save).iter().map(|p| p.id().to_string()).collect()
+        };
+
+        // Every matching rule adds its playlists, once each
+        assert_eq!(names(&quick_save(None, 12 * 60)), vec!["barlow", "indie", "kitchen"]);
+        assert_eq!(names(&quick_save(Some("spotify:album:bakesale"), 23 * 60)),
+                   vec!["barlow", "indie", "kitchen", "night", "albums"]);
+        assert_eq!(names(&quick_save(Some("spotify:playlist:mix"), 5 * 60 + 59)),
+                   vec!["barlow", "indie", "kitchen", "night"]);
+
+        let mut elsewhere = quick_save(None, 6 * 60);
+        elsewhere.artists = vec!["Someone Else".to_string()];
+        elsewhere.device_name = "Bedroom".to_string();
+        elsewhere.device_id = None;
+        assert!(names(&elsewhere).is_empty());
+        elsewhere.device_name = "KITCHEN".to_string();
+        assert_eq!(names(&elsewhere), vec!["kitchen", "indie"]);
+
+        // Devices match by ID too
+        assert!(SaveCondition::Device("kitchen-id".to_string()).matches(&quick_save(None, 0)));
+    }
+
+    #[test]
+    fn test_save_history() {
+        let saved = |playlist: &str, time: i64| SavedTrack {
+            uri: "spotify:track:one".to_string(),
+            name: "One".to_string(),
+            playlist: playlist.to_string(),
+            snapshot_id: None,
+            position: Some(3),
+            time: time,
+            batch: 0,
+        };
+        let mut history = SaveHistory::load(None);
+        assert!(history.last().is_empty());
+        history.record(vec![saved("spotify:playlist:a", 10)]);
+        history.record(vec![]);
+        history.record(vec![saved("spotify:playlist:b", 10), saved("spotify:playlist:c", 10)]);
+        assert_eq!(history.saves().len(), 3);
+        // The last save went to two playlists
+        let last = history.last();
+        assert_eq!(last.iter().map(|s| s.playlist.as_str()).collect::<Vec<&str>>(),
+                   vec!["spotify:playlist:b", "spotify:playlist:c"]);
+        for s in &last {
+            history.forget(s);
+        }
+        assert_eq!(history.last().len(), 1);
+        assert_eq!(history.last()[0].playlist, "spotify:playlist:a");
+
+        for time in 0..(QUICKSAVE_MAX_ENTRIES as i64 + 5) {
+            history.record(vec![saved("spotify:playlist:a", time)]);
+        }
+        assert_eq!(history.saves().len(), QUICKSAVE_MAX_ENTRIES);
+    }
+}
@@ -0,0 +1,170 @@
+use super::TStatusBar;
+use super::NSCallback;
+use super::Object;
+use super::controller::CommandMessage;
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::Sender;
+
+/// A call made to a `RecordingStatusBar`, with its arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatusBarCall {
+    ClearItems,
+    AddSeparator,
+    AddLabel(String),
+    AddSubmenu(String),
+    AddItem { menu: Option<u64>, label: String, selected: bool },
+    AddQuit(String),
+    UpdateItem(u64, String),
+    SelItem(u64),
+    UnselItem(u64),
+    SetTooltip(String),
+    RegisterUrlHandler,
+    Run(bool),
+}
+
+/// A clickable item as it currently appears in the recorded menu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedItem {
+    pub id: u64,
+    pub menu: Option<u64>,
+    pub label: String,
+    pub selected: bool,
+}
+
+/// Status bar that draws nothing, but records every call made to it.
+///
+/// Keeps the registered callbacks, so tests can click on menu items and
+/// observe the commands that are sent.
+pub struct RecordingStatusBar {
+    tx: Sender<CommandMessage>,
+    idx: u64,
+    calls: Vec<StatusBarCall>,
+    labels: Vec<String>,
+    items: Vec<RecordedItem>,
+    callbacks: BTreeMap<u64, NSCallback>,
+}
+
+impl RecordingStatusBar {
+    /// Every call since creation, oldest first.
+    pub fn calls(&self) -> &Vec<StatusBarCall> {
+        &self.calls
+    }
+    /// Return the recorded calls and start a fresh recording.
+    pub fn take_calls(&mut self) -> Vec<StatusBarCall> {
+        self.calls.drain(..).collect()
+    }
+    /// Text of every label in the current menu.
+    pub fn labels(&self) -> &Vec<String> {
+        &self.labels
+    }
+    /// Every clickable item in the current menu.
+    pub fn items(&self) -> &Vec<RecordedItem> {
+        &self.items
+    }
+    pub fn item(&self, label: &str) -> Option<&RecordedItem> {
+        self.items.iter().find(|i| i.label == label)
+    }
+    /// Click the item with the given ID, as the platform would.
+    pub fn click_item(&self, id: u64) -> bool {
+        match self.callbacks.get(&id) {
+            Some(cb) => { cb(id, &self.tx); true },
+            None => false,
+        }
+    }
+    /// Click the first item with the given label.
+    pub fn click(&self, label: &str) -> bool {
+        match self.item(label) {
+            Some(item) => self.click_item(item.id),
+            None => false,
+        }
+    }
+    fn set_selected(&mut self, sender: u64, selected: bool) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == sender) {
+            item.selected = selected;
+        }
+    }
+}
+
+impl TStatusBar for RecordingStatusBar {
+    type S = RecordingStatusBar;
+    fn new(tx: Sender<CommandMessage>) -> RecordingStatusBar {
+        RecordingStatusBar {
+            tx: tx,
+            idx: 0,
+            calls: Vec::new(),
+            labels: Vec::new(),
+            items: Vec::new(),
+            callbacks: BTreeMap::new(),
+        }
+    }
+    fn can_redraw(&mut self) -> bool {
+        true
+    }
+    fn clear_items(&mut self) {
+        self.calls.push(StatusBarCall::ClearItems);
+        self.labels.clear();
+        self.items.clear();
+        self.callbacks.clear();
+    }
+    fn add_separator(&mut self) {
+        self.calls.push(StatusBarCall::AddSeparator);
+    }
+    fn add_label(&mut self, label: &str) {
+        self.calls.push(StatusBarCall::AddLabel(label.to_string()));
+        self.labels.push(label.to_string());
+    }
+    fn supports_submenus(&self) -> bool {
+        true
+    }
+    fn add_submenu(&mut self, label: &str, callback: NSCallback) -> *mut Object {
+        self.calls.push(StatusBarCall::AddSubmenu(label.to_string()));
+        self.idx += 1;
+        self.callbacks.insert(self.idx, callback);
+        self.idx as *mut Object
+    }
+    fn add_item(&mut self, menu: Option<*mut Object>, item: &str, callback: NSCallback, selected: bool) -> *mut Object {
+        let menu = menu.map(|m| m as u64);
+        self.calls.push(StatusBarCall::AddItem {
+            menu: menu,
+            label: item.to_string(),
+            selected: selected,
+        });
+        self.idx += 1;
+        self.callbacks.insert(self.idx, callback);
+        self.items.push(RecordedItem {
+            id: self.idx,
+            menu: menu,
+            label: item.to_string(),
+            selected: selected,
+        });
+        self.idx as *mut Object
+    }
+    fn add_quit(&mut self, label: &str) {
+        self.calls.push(StatusBarCall::AddQuit(label.to_string()));
+    }
+    fn update_item(&mut self, item: *mut Object, label: &str) {
+        let id = item as u64;
+        self.calls.push(StatusBarCall::UpdateItem(id, label.to_string()));
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.label = label.to_string();
+        }
+    }
+    fn sel_item(&mut self, sender: u64) {
+        self.calls.push(StatusBarCall::SelItem(sender));
+        self.set_selected(sender, true);
+    }
+    fn unsel_item(&mut self, sender: u64) {
+        self.calls.push(StatusBarCall::UnselItem(sender));
+        self.set_selected(sender, false);
+    }
+    fn set_tooltip(&mut self, text: &str) {
+        self.calls.push(StatusBarCall::SetTooltip(text.to_string()));
+    }
+    fn register_url_handler(&mut self) {
+        self.calls.push(StatusBarCall::RegisterUrlHandler);
+    }
+    fn run(&mut self, block: bool) {
+        self.calls.push(StatusBarCall::Run(block));
+    }
+}
@@ -1,3 +1,6 @@
+#[cfg(test)]
+mod test;
+
 extern crate ini;
 use self::ini::Ini;
 use super::http;
@@ -5,16 +8,27 @@ use super::AlarmRepeat;
 use super::AlarmConfig;
 use super::ConnectDeviceList;
 use super::Scrobbler;
+use super::uri::{SpotifyUri, SpotifyUriKind};
+use super::preset::{Preset, Surprise};
+use super::quicksave::{self, QuickSave, SaveRule};
+use super::devices::{DeviceSettings, TransferRule};
 
 extern crate time;
 extern crate fruitbasket;
 
+use std::fs;
+use std::io::{Read, Write};
 use std::path;
 use std::str::FromStr;
 use std::collections::BTreeMap;
 
 const INIFILE: &'static str = "connectr.ini";
+const STATEFILE: &'static str = "connectr_state.json";
+const ROTATIONFILE: &'static str = "connectr_rotation.json";
+const QUICKSAVEFILE: &'static str = "connectr_quicksave.json";
 const PORT: u32 = 5432;
+// How many recent "Surprise Me" choices to avoid, unless set in connectr.ini.
+pub const SURPRISE_NO_REPEAT: usize = 1;
 pub const WEB_PORT: u32 = 5676;
 
 #[derive(Default)]
@@ -35,17 +49,111 @@ pub struct Settings {
     pub access_token: Option<String>,
     pub refresh_token: Option<String>,
     pub expire_utc: Option<u64>,
-    pub presets: Vec<(String,String)>,
-    pub default_quicksave: Option<String>,
-    pub quicksave: BTreeMap<String, String>,
+    pub presets: Vec<Preset>,
+    pub default_quicksave: Option<SpotifyUri>,
+    pub quicksave: BTreeMap<String, SpotifyUri>,
+    /// Rules that pick more playlists to quick-save to
+    pub quicksave_rules: Vec<SaveRule>,
+    /// Device aliases, hidden devices, display order and the default device
+    pub devices: DeviceSettings,
+    /// Rules that move playback when devices come online or go away
+    pub transfer_rules: Vec<TransferRule>,
     pub alarms: Vec<AlarmConfig>,
     pub lastfm_enabled: bool,
     pub lastfm: Option<LastfmSettings>,
+    /// Where to keep state that isn't configuration, like resume points.
+    pub state_file: Option<String>,
+    /// Where to log recent quick-saves, so they can be undone.
+    pub quicksave_file: Option<String>,
+    /// Where to keep the presets recently chosen by "Surprise Me".
+    pub rotation_file: Option<String>,
+    /// How many recent "Surprise Me" choices to avoid.
+    pub surprise_no_repeat: usize,
+    /// The config file these settings came from.  Without one, changes made
+    /// from the menu are only kept until Connectr exits.
+    pub config_file: Option<String>,
 }
 
 impl Settings {
-    pub fn quick_save_playlist(&self, context: &str) -> Option<&str> {
-        match self.quicksave.get(context) {
+    /// Add a preset, and write it to the config file.
+    pub fn add_preset(&mut self, preset: Preset) -> Result<(), SettingsError> {
+        self.import_presets(vec![(preset, None)]).map(|_| ())
+    }
+
+    /// Add presets, each with an optional quick-save playlist, and write
+    /// them to the config file without touching the other entries.  A
+    /// preset that plays something already in the list isn't added twice,
+    /// and a name already in use in its group gets a number.  Returns how
+    /// many were added.
+    pub fn import_presets(&mut self, presets: Vec<(Preset, Option<SpotifyUri>)>)
+                          -> Result<usize, SettingsError> {
+        let mut all = self.presets.clone();
+        let mut added = Vec::new();
+        for (mut preset, save_uri) in presets {
+            if all.iter().any(|p| p.uri() == preset.uri()) {
+                info!("Already have a preset for {}", preset.uri());
+                continue;
+            }
+            preset.name = preset_key_name(&preset.name);
+            preset.group = preset.group.map(|g| preset_key_name(&g)).filter(|g| !g.is_empty());
+            if preset.name.is_empty() {
+                warn!("Skipping preset for {}: its name can't be written to the config file", preset.uri());
+                continue;
+            }
+            let base = preset.name.clone();
+            let mut count = 1;
+            while all.iter().any(|p| p.key() == preset.key()) {
+                count += 1;
+                preset.name = format!("{} ({})", base, count);
+            }
+            all.push(preset.clone());
+            added.push((preset, save_uri));
+        }
+        if let (Some(ref file), false) = (self.config_file.as_ref(), added.is_empty()) {
+            let lines: Vec<String> = added.iter().map(|&(ref preset, ref save_uri)| {
+                match *save_uri {
+                    Some(ref save_uri) => format!("{} = {},{}", preset.key(), preset.spec(), save_uri),
+                    None => format!("{} = {}", preset.key(), preset.spec()),
+                }
+            }).collect();
+            append_to_section(file, "presets", &lines)?;
+        }
+        self.presets = all;
+        for &(ref preset, ref save_uri) in &added {
+            if let Some(ref save_uri) = *save_uri {
+                self.quicksave.insert(preset.uri().to_string(), save_uri.clone());
+            }
+        }
+        Ok(added.len())
+    }
+    /// Every playlist to quick-save to: the playing preset's, and those of
+    /// matching rules.  The default playlist is used if there are none.
+    pub fn quick_save_playlists(&self, save: &QuickSave) -> Vec<SpotifyUri> {
+        let mut playlists = Vec::new();
+        if let Some(ref context) = save.context {
+            let context = match context.parse::<SpotifyUri>() {
+                Ok(uri) => uri.to_string(),
+                Err(_) => context.to_string(),
+            };
+            playlists.extend(self.quicksave.get(&context).cloned());
+        }
+        for playlist in quicksave::route(&self.quicksave_rules, save) {
+            if !playlists.contains(&playlist) {
+                playlists.push(playlist);
+            }
+        }
+        if playlists.is_empty() {
+            playlists.extend(self.default_quicksave.clone());
+        }
+        playlists
+    }
+    pub fn quick_save_playlist(&self, context: &str) -> Option<&SpotifyUri> {
+        // Presets are stored as new style URIs
+        let context = match context.parse::<SpotifyUri>() {
+            Ok(uri) => uri.to_string(),
+            Err(_) => context.to_string(),
+        };
+        match self.quicksave.get(&context) {
             Some(ref uri) => Some(&uri),
             None => {
                 match self.default_quicksave {
@@ -57,10 +165,219 @@ impl Settings {
     }
 }
 
+/// Parse a context to play, like a playlist or album, logging why if it isn't one.
+pub fn parse_context_uri(uri: &str) -> Option<SpotifyUri> {
+    match uri.parse::<SpotifyUri>() {
+        Ok(ref u) if u.is_context() => Some(u.clone()),
+        Ok(_) => { warn!("Not a playable context: {}", uri); None },
+        Err(e) => { warn!("{}", e); None },
+    }
+}
+
+/// A name that can be written as a key in connectr.ini, and in the web
+/// form's "name = value" lines.  Characters that end a key or start a
+/// comment, and commas that would split alarm targets like "random:Group",
+/// become spaces.
+pub fn preset_key_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| match c {
+            '=' | ':' | ';' | '#' | ',' | '[' | ']' | '\\' => ' ',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Add lines to the end of a section of an INI file, creating the section
+/// if needed.  Everything else in the file, comments included, is left as
+/// it was.
+pub fn append_to_section(file: &str, section: &str, lines: &[String]) -> Result<(), SettingsError> {
+    let mut text = String::new();
+    match fs::File::open(file) {
+        Ok(mut f) => {
+            if f.read_to_string(&mut text).is_err() {
+                return Err("Couldn't read configuration.".to_string());
+            }
+        },
+        Err(_) => return Err("Couldn't open configuration.".to_string()),
+    }
+    let newline = match text.contains("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    let mut file_lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    let header = format!("[{}]", section);
+    match file_lines.iter().position(|l| l.trim() == header) {
+        Some(start) => {
+            let end = file_lines.iter().skip(start + 1)
+                .position(|l| l.trim().starts_with('['))
+                .map(|idx| start + 1 + idx)
+                .unwrap_or(file_lines.len());
+            // After the section's last entry.  Comments further down may
+            // belong to the next section, but those right after the header
+            // are about this one.
+            let is_comment = |l: &String| l.trim().starts_with(';') || l.trim().starts_with('#');
+            let at = match (start + 1..end).rev().find(|&i| !file_lines[i].trim().is_empty() &&
+                                                         !is_comment(&file_lines[i])) {
+                Some(last) => last + 1,
+                None => {
+                    start + 1 + file_lines[start + 1..end].iter().take_while(|l| is_comment(l)).count()
+                },
+            };
+            for (idx, line) in lines.iter().enumerate() {
+                file_lines.insert(at + idx, line.clone());
+            }
+        },
+        None => {
+            if file_lines.last().map(|l| !l.trim().is_empty()).unwrap_or(false) {
+                file_lines.push(String::new());
+            }
+            file_lines.push(header);
+            file_lines.extend(lines.iter().cloned());
+        },
+    }
+    let mut out = file_lines.join(newline);
+    out.push_str(newline);
+    match fs::File::create(file).and_then(|mut f| f.write_all(out.as_bytes())) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Couldn't write configuration: {}", e)),
+    }
+}
+
+/// Entries of an INI section in the order they are written in the file.
+/// `Ini` keeps a section's entries in a hash map, which loses the order
+/// that rules are checked in.  Entries not found in `text` come last,
+/// sorted by key.
+pub fn section_in_file_order<'a>(text: &str, name: &str, section: &'a ini::ini::Properties)
+                                 -> Vec<(&'a String, &'a String)> {
+    let header = format!("[{}]", name);
+    let mut entries: Vec<(&String, &String)> = Vec::new();
+    let lines = text.lines()
+        .map(|l| l.trim())
+        .skip_while(|l| *l != header)
+        .skip(1)
+        .take_while(|l| !l.starts_with('['));
+    for line in lines {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        // The key ends at the first = or : that a backslash doesn't escape
+        let mut key = String::new();
+        let mut chars = line.chars();
+        let mut complete = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => key.extend(chars.next()),
+                '=' | ':' => { complete = true; break; },
+                c => key.push(c),
+            }
+        }
+        let key = key.trim();
+        if !complete {
+            continue;
+        }
+        if let Some(entry) = section.iter().find(|&(k, _)| *k == key) {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    let mut rest: Vec<(&String, &String)> = section.iter()
+        .filter(|entry| !entries.contains(entry))
+        .collect();
+    rest.sort();
+    entries.extend(rest);
+    entries
+}
+
+/// Presets in the order they are written in the [presets] section, and
+/// their quick-save playlists by preset URI.
+pub fn read_presets(text: &str, conf: &Ini) -> (Vec<Preset>, BTreeMap<String, SpotifyUri>) {
+    let mut presets = Vec::<Preset>::new();
+    let mut quicksave = BTreeMap::<String,SpotifyUri>::new();
+    if let Some(section) = conf.section(Some("presets".to_owned())) {
+        for (key, value) in section_in_file_order(text, "presets", section) {
+            if let Some((preset, save_uri)) = parse_preset(key, value) {
+                // quicksave is optional
+                if let Some(save_uri) = save_uri {
+                    quicksave.insert(preset.uri().to_string(), save_uri);
+                }
+                presets.push(preset);
+            }
+        }
+    }
+    (presets, quicksave)
+}
+
+/// Parse an alarm target, either a context or a random preset like
+/// "random:Morning", logging why if it isn't one.
+pub fn parse_alarm_context(context: &str) -> Option<String> {
+    match context.parse::<Surprise>() {
+        Ok(surprise) => Some(surprise.to_string()),
+        Err(_) => parse_context_uri(context).map(|uri| uri.to_string()),
+    }
+}
+
+/// Parse a "[Preset],[Quick-save Playlist URI],[Options]" preset value,
+/// logging why if it isn't valid.  An invalid quick-save or option is dropped.
+pub fn parse_preset(name: &str, value: &str) -> Option<(Preset, Option<SpotifyUri>)> {
+    let mut fields = value.split(",");
+    let mut preset = match Preset::parse(name, fields.next().unwrap()) {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("Ignoring preset {}: {}", name, e);
+            return None;
+        },
+    };
+    let mut quicksave = None;
+    for field in fields {
+        match preset.options.parse_field(field) {
+            Ok(true) => {},
+            Ok(false) => match parse_playlist_uri(field) {
+                Some(uri) => quicksave = Some(uri),
+                None => warn!("Preset {}: ignoring unknown field {}", name, field.trim()),
+            },
+            Err(e) => warn!("Preset {}: {}", name, e),
+        }
+    }
+    Some((preset, quicksave))
+}
+
+/// Clean up a preset value from the web form, so links become URIs.
+fn canonical_preset(name: &str, value: &str) -> Option<String> {
+    let (preset, quicksave) = parse_preset(name, value)?;
+    let mut fields = vec![preset.spec()];
+    fields.extend(quicksave.map(|q| q.to_string()));
+    fields.extend(preset.options.fields());
+    Some(fields.join(","))
+}
+
+/// Parse a playlist URI, logging why if it isn't one.
+pub fn parse_playlist_uri(uri: &str) -> Option<SpotifyUri> {
+    match uri.parse::<SpotifyUri>() {
+        Ok(ref u) if u.kind() == SpotifyUriKind::Playlist => Some(u.clone()),
+        Ok(_) => { warn!("Not a playlist: {}", uri); None },
+        Err(e) => { warn!("{}", e); None },
+    }
+}
+
 fn default_inifile() -> String {
     format!("{}/.{}", dirs::home_dir().unwrap().display(), INIFILE)
 }
 
+fn default_statefile() -> String {
+    format!("{}/.{}", dirs::home_dir().unwrap().display(), STATEFILE)
+}
+
+fn default_quicksavefile() -> String {
+    format!("{}/.{}", dirs::home_dir().unwrap().display(), QUICKSAVEFILE)
+}
+
+fn default_rotationfile() -> String {
+    format!("{}/.{}", dirs::home_dir().unwrap().display(), ROTATIONFILE)
+}
+
 fn inifile() -> String {
     // Default to looking in current working directory
     let path = INIFILE.to_string();
@@ -124,24 +441,33 @@ To create your free developer application for Connectr, follow these instruction
     For instance, you might have a "Discover Weekly" preset, and a quick save to a "Best of Discover Weekly" playlist.
     You can also set a global "quick save" playlist, where tracks are saved if not playing from a preset with an associated quick-save playlist.</br>
     </br>
-    All contexts must be specified in Spotify's URI format: ex: <code>spotify:album:2p2UgYlbg4yG44IKDp08Q8</code>
+    Contexts can be Spotify URIs, ex: <code>spotify:album:2p2UgYlbg4yG44IKDp08Q8</code>, or links copied from Spotify, ex: <code>https://open.spotify.com/album/2p2UgYlbg4yG44IKDp08Q8</code>.  Entries that aren't valid are dropped.
     </div>
     </br>
     One preset per line, in either format::</br>
     &nbsp;&nbsp;&nbsp;<code>[Preset Name] = [Context URI]</code></br>
     &nbsp;&nbsp;&nbsp;<code>[Preset Name] = [Context URI],[Quick-save Playlist URI]</code>
     </br></br>
+    Instead of a context, a preset can be a list of track URIs separated by spaces.  Add <code>track=5</code> or <code>track=[Track URI]</code> to start on a particular track, and <code>start=1:23</code> to start part way into it.  For instance:</br>
+    &nbsp;&nbsp;&nbsp;<code>Wedding = spotify:playlist:37i9dQZEVXboyJ0IJdpcuT track=5</code></br>
+    &nbsp;&nbsp;&nbsp;<code>Best Bit = spotify:track:6rqhFgbbKwnb9MLmUQDhG6 start=1:23</code>
+    </br></br>
+    Presets can also pick the device, volume, shuffle and repeat mode before they start, with more fields separated by commas: <code>device [Name or ID]</code>, <code>volume [0-100]</code>, <code>shuffle on|off</code> and <code>repeat off|track|context</code>.  Add <code>resume</code> to start where the preset was left last time, and <code>weight [0-...]</code> to make "Surprise Me" pick it more or less often.  For instance:</br>
+    &nbsp;&nbsp;&nbsp;<code>Dinner = spotify:playlist:37i9dQZEVXboyJ0IJdpcuT, device Kitchen, volume 25, shuffle on</code>
+    </br></br>
+    Start a name with a group and a slash, like <code>Focus/Deep Work = ...</code>, to list the preset in a "Focus" submenu.
+    </br></br>
 </td></tr>
 "###));
     let presets = match settings {
         Some(ref settings) => {
             let mut p = String::new();
-            for &(ref name, ref uri) in &settings.presets {
-                let qsave = match settings.quicksave.get(uri) {
+            for preset in &settings.presets {
+                let qsave = match settings.quicksave.get(preset.uri()) {
                     Some(ref q) => format!(",{}", q),
                     None => String::new(),
                 };
-                p.push_str(&format!("{} = {}{}", name, uri, qsave));
+                p.push_str(&format!("{}{}\n", preset, qsave));
             }
             p
         },
@@ -149,13 +475,13 @@ To create your free developer application for Connectr, follow these instruction
     };
     let quicksave = match settings {
         Some(ref s) => match s.default_quicksave {
-            Some(ref d) => &d,
-            None => "",
+            Some(ref d) => d.to_string(),
+            None => String::new(),
         },
-        None => "",
+        None => String::new(),
     };
     form.push_str(&format!(r###"
-<tr><td>Presets:</br>(one per line)</td><td><textarea rows="10" cols="100"  name="presets" placeholder="First Preset Name = spotify:user:spotify:playlist:37i9dQZEVXboyJ0IJdpcuT">{}</textarea></td></tr>
+<tr><td>Presets:</br>(one per line)</td><td><textarea rows="10" cols="100"  name="presets" placeholder="First Preset Name = spotify:playlist:37i9dQZEVXboyJ0IJdpcuT">{}</textarea></td></tr>
 <tr><td style="width:200px;">Quick-Save URI:</br>(playlist URI)</td><td>
     <input type="text" name="quicksave_default" value="{}" style="width:400px;"></td></tr>
 "###,
@@ -281,7 +607,7 @@ a.tooltip {{ color: #4e4e4e; text-decoration: none; vertical-align: super; font-
     </table><br/>
     <h3>Alarm Schedule:</h3>
 <form method="POST" action="#" accept-charset="UTF-8"><table>
-<tr><th align="center">Time <a href="#" class="tooltip" data-tip="24-hour format">eh?</a></th><th align="center">Repeat</th><th>Spotify URI <a href="#" class="tooltip" data-tip="In Spotify app: right-click playlist, click 'Share', click 'URI'">eh?</a></th><th>Device ID <a href="#" class="tooltip" data-tip="Unique identifier of Spotify hardware.  Devices are listed above.">eh?</a></th></tr>
+<tr><th align="center">Time <a href="#" class="tooltip" data-tip="24-hour format">eh?</a></th><th align="center">Repeat</th><th>Spotify URI <a href="#" class="tooltip" data-tip="In Spotify app: right-click playlist, click 'Share', click 'URI'.  Or 'random' for a random preset, or 'random:Group' for one from a group.">eh?</a></th><th>Device <a href="#" class="tooltip" data-tip="Name or ID of a device listed above.  Names keep working when Spotify gives the device a new ID.  Start with ~ to match loosely, like ~kitchen.">eh?</a></th></tr>
 "###));
     for i in 0..5 {
         form.push_str(&format!(
@@ -340,17 +666,25 @@ pub fn save_web_config(old_settings: Option<&Settings>, mut config: BTreeMap<Str
         .set("secret", secret.trim())
         .set("client_id", client_id.trim());
     if let Some(quicksave) = config.remove("quicksave_default") {
-        c.with_section(Some("connectr".to_owned()))
-            .set("quicksave_default", quicksave.trim());
+        match parse_playlist_uri(&quicksave) {
+            Some(uri) => {
+                c.with_section(Some("connectr".to_owned()))
+                    .set("quicksave_default", uri.to_string());
+            },
+            None => { c.delete_from(Some("connectr"), "quicksave_default"); },
+        }
     }
     {
         // TODO: INI uses HashMap, doesn't support maintaining order
+        c.delete(Some("presets"));
         for preset in presets.split("\n") {
-            let mut pair = preset.split("=");
+            // Only the first '=' separates the name, options have them too
+            let mut pair = preset.splitn(2, "=");
             if pair.clone().count() == 2 {
                 let key = pair.next().unwrap().trim();
-                let value = pair.next().unwrap().trim();
-                c.set_to(Some("presets"), key.to_string(), value.to_string());
+                if let Some(value) = canonical_preset(key, pair.next().unwrap()) {
+                    c.set_to(Some("presets"), key.to_string(), value);
+                }
             }
         }
     }
@@ -442,7 +776,10 @@ pub fn save_web_alarm_config(config: BTreeMap<String,String>) -> Result<(), Sett
                 };
             },
             "context" => {
-                entry.context = value.clone();
+                // Invalid contexts are left empty, and the alarm dropped
+                if let Some(context) = parse_alarm_context(value) {
+                    entry.context = context;
+                }
             },
             "device" => {
                 entry.device = value.clone();
@@ -475,12 +812,22 @@ pub fn read_settings(scopes_version: u32) -> Option<Settings> {
         }
     };
 
+    // Presets are listed, and rules checked, in the order they are written
+    let mut text = String::new();
+    if let Ok(mut f) = fs::File::open(&inifile()) {
+        let _ = f.read_to_string(&mut text);
+    }
+
     let section = conf.section(Some("connectr".to_owned())).unwrap();
     let port = section.get("port").unwrap().parse().unwrap();
     let quicksave_default = match section.get("quicksave_default") {
-        Some(uri) => Some(uri.to_string()),
+        Some(uri) => parse_playlist_uri(uri),
         None => None,
     };
+    let surprise_no_repeat = match section.get("surprise_no_repeat") {
+        Some(count) => count.parse().unwrap_or(SURPRISE_NO_REPEAT),
+        None => SURPRISE_NO_REPEAT,
+    };
 
     let section = conf.section(Some("application".to_owned())).unwrap();
     let secret = section.get("secret").unwrap();
@@ -515,16 +862,33 @@ pub fn read_settings(scopes_version: u32) -> Option<Settings> {
         }
     }
 
-    let mut presets = Vec::<(String,String)>::new();
-    let mut quicksave = BTreeMap::<String,String>::new();
-    if let Some(section) = conf.section(Some("presets".to_owned())) {
+    let (presets, quicksave) = read_presets(&text, &conf);
+    let mut quicksave_rules = Vec::<SaveRule>::new();
+    if let Some(section) = conf.section(Some("quicksave".to_owned())) {
+        for (key, value) in section_in_file_order(&text, "quicksave", section) {
+            match SaveRule::parse(key, value) {
+                Ok(rule) => quicksave_rules.push(rule),
+                Err(e) => warn!("Ignoring quick-save rule {}: {}", key, e),
+            }
+        }
+    }
+    let mut devices = DeviceSettings::default();
+    if let Some(section) = conf.section(Some("devices".to_owned())) {
+        devices.default = section.get("default").map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+        devices.order = section.get("order").map(|o| DeviceSettings::parse_list(o)).unwrap_or_default();
+        devices.hidden = section.get("hidden").map(|h| DeviceSettings::parse_list(h)).unwrap_or_default();
+    }
+    if let Some(section) = conf.section(Some("device_aliases".to_owned())) {
         for (key, value) in section {
-            let mut fields = value.split(",");
-            let uri = fields.next().unwrap().trim(); // URI is required
-            let save_uri = fields.next(); // quicksave is optional
-            presets.push((key.to_owned(), uri.to_owned()));
-            if let Some(save_uri) = save_uri {
-                quicksave.insert(uri.to_owned(), save_uri.trim().to_owned());
+            devices.aliases.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut transfer_rules = Vec::<TransferRule>::new();
+    if let Some(section) = conf.section(Some("transfers".to_owned())) {
+        for (key, value) in section_in_file_order(&text, "transfers", section) {
+            match TransferRule::parse(key, value) {
+                Ok(rule) => transfer_rules.push(rule),
+                Err(e) => warn!("Ignoring transfer rule {}: {}", key, e),
             }
         }
     }
@@ -532,7 +896,12 @@ pub fn read_settings(scopes_version: u32) -> Option<Settings> {
     if let Some(section) = conf.section(Some("alarms".to_owned())) {
         for (_key, value) in section {
             match AlarmConfig::from_str(value) {
-                Ok(a) => alarms.push(a),
+                Ok(mut a) => {
+                    if let Some(context) = parse_alarm_context(&a.context) {
+                        a.context = context;
+                        alarms.push(a);
+                    }
+                },
                 Err(_) => {},
             }
         }
@@ -564,9 +933,17 @@ pub fn read_settings(scopes_version: u32) -> Option<Settings> {
                     presets: presets,
                     default_quicksave: quicksave_default,
                     quicksave: quicksave,
+                    quicksave_rules: quicksave_rules,
+                    devices: devices,
+                    transfer_rules: transfer_rules,
                     alarms: alarms,
                     lastfm_enabled: lastfm_enabled,
                     lastfm: lastfm,
+                    state_file: Some(default_statefile()),
+                    quicksave_file: Some(default_quicksavefile()),
+                    rotation_file: Some(default_rotationfile()),
+                    surprise_no_repeat: surprise_no_repeat,
+                    config_file: Some(inifile()),
     })
 }
 
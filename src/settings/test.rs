@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use preset::{menu_items, PresetMenuItem};
+
+    const COMMENTED_INI: &'static str = "[connectr]\n\
+                                          port = 5432\n\
+                                          \n\
+                                          [presets]\n\
+                                          # Playlist Name = spotify:playlist:uri\n\
+                                          Morning = spotify:playlist:morning\n\
+                                          \n\
+                                          ; Tokens are saved here\n\
+                                          [tokens]\n\
+                                          version = 1\n";
+
+    fn temp_ini(name: &str, text: &str) -> String {
+        let path = ::std::env::temp_dir().join(name);
+        fs::File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn read_file(path: &str) -> String {
+        let mut text = String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_add_preset_keeps_layout() {
+        let file = temp_ini("connectr_test_add_preset.ini", COMMENTED_INI);
+        let mut settings = Settings::default();
+        settings.config_file = Some(file.clone());
+        settings.add_preset(Preset::new("Live: 1999 = Best; Of", "spotify:album:live")).unwrap();
+        assert_eq!(settings.presets[0].name, "Live 1999 Best Of");
+
+        // Only the new line is added, at the end of [presets]
+        let expected = COMMENTED_INI.replace("Morning = spotify:playlist:morning\n",
+                                             "Morning = spotify:playlist:morning\n\
+                                              Live 1999 Best Of = spotify:album:live\n");
+        assert_eq!(read_file(&file), expected);
+        let conf = Ini::load_from_file(&file).unwrap();
+        assert_eq!(conf.get_from(Some("presets"), "Live 1999 Best Of"), Some("spotify:album:live"));
+        assert_eq!(conf.get_from(Some("tokens"), "version"), Some("1"));
+        let _ = fs::remove_file(&file);
+
+        // A missing section is added at the end
+        let file = temp_ini("connectr_test_add_section.ini", "[connectr]\nport = 5432\n");
+        settings.config_file = Some(file.clone());
+        settings.add_preset(Preset::new("Evening", "spotify:playlist:evening")).unwrap();
+        assert_eq!(read_file(&file), "[connectr]\nport = 5432\n\n[presets]\nEvening = spotify:playlist:evening\n");
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_import_presets_round_trip() {
+        let file = temp_ini("connectr_test_import.ini", COMMENTED_INI);
+        let mut settings = Settings::default();
+        settings.config_file = Some(file.clone());
+        settings.presets = vec![Preset::new("Morning", "spotify:playlist:morning")];
+        let presets = vec![
+            (Preset::new("Rock, Paper = Scissors", "spotify:playlist:rock"), "spotify:playlist:saved".parse().ok()),
+            (Preset::new("#1s; Vol. 2", "spotify:album:ones"), None),
+            (Preset::new(" =;# ", "spotify:album:nameless"), None),
+            (Preset::new("AC/DC", "spotify:artist:acdc"), None),
+        ];
+        assert_eq!(settings.import_presets(presets), Ok(3));
+
+        let text = read_file(&file);
+        assert!(text.starts_with("[connectr]\nport = 5432\n\n[presets]\n# Playlist Name = spotify:playlist:uri\n"));
+        assert!(text.ends_with("\n; Tokens are saved here\n[tokens]\nversion = 1\n"));
+
+        // Read back, every imported preset is the same
+        let conf = Ini::load_from_file(&file).unwrap();
+        let mut read: Vec<(Preset, Option<SpotifyUri>)> = conf.section(Some("presets")).unwrap().iter()
+            .filter_map(|(key, value)| parse_preset(key, value))
+            .collect();
+        read.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        let names: Vec<&str> = read.iter().map(|p| p.0.name.as_str()).collect();
+        assert_eq!(names, vec!["1s Vol. 2", "AC/DC", "Morning", "Rock Paper Scissors"]);
+        assert_eq!(read[3].1, "spotify:playlist:saved".parse().ok());
+        assert_eq!(settings.presets.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>(),
+                   vec!["Morning", "Rock Paper Scissors", "1s Vol. 2", "AC/DC"]);
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_section_in_file_order() {
+        let text = "[quicksave]\n\
+                    ; Checked from top to bottom\n\
+                    Zebra = spotify:playlist:z, artist Zebra\n\
+                    Mango = spotify:playlist:m\n\
+                    Apple\\:Pie : spotify:playlist:a\n\
+                    Kiwi = spotify:playlist:k\n\
+                    Banana = spotify:playlist:b\n\
+                    \n\
+                    [transfers]\n\
+                    Kiwi = appears Kiwi, to Kiwi\n\
+                    Any Fruit = appears ~kiwi, to Fruit Bowl\n";
+        let conf = Ini::load_from_str(text).unwrap();
+        let section = conf.section(Some("quicksave")).unwrap();
+        let keys: Vec<&str> = section_in_file_order(text, "quicksave", section).iter()
+            .map(|&(key, _)| key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["Zebra", "Mango", "Apple:Pie", "Kiwi", "Banana"]);
+        let rules: Vec<String> = section_in_file_order(text, "quicksave", section).iter()
+            .map(|&(key, value)| SaveRule::parse(key, value).unwrap().name)
+            .collect();
+        assert_eq!(rules, vec!["Zebra", "Mango", "Apple:Pie", "Kiwi", "Banana"]);
+        let transfers = conf.section(Some("transfers")).unwrap();
+        let rules: Vec<String> = section_in_file_order(text, "transfers", transfers).iter()
+            .map(|&(key, value)| TransferRule::parse(key, value).unwrap().name)
+            .collect();
+        assert_eq!(rules, vec!["Kiwi", "Any Fruit"]);
+
+        // Without the file, entries are sorted
+        let keys: Vec<&str> = section_in_file_order("", "quicksave", section).iter()
+            .map(|&(key, _)| key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["Apple:Pie", "Banana", "Kiwi", "Mango", "Zebra"]);
+    }
+
+    #[test]
+    fn test_preset_order() {
+        let text = "[presets]\n\
+                    Wake Up = spotify:playlist:wake\n\
+                    Focus/Deep Work = spotify:playlist:deep, spotify:playlist:saved\n\
+                    Party/Loud = spotify:album:loud\n\
+                    Commute = spotify:playlist:commute\n\
+                    Focus/Light = spotify:playlist:light\n\
+                    AC//DC = spotify:artist:acdc\n";
+        let conf = Ini::load_from_str(text).unwrap();
+        let (presets, quicksave) = read_presets(text, &conf);
+        let keys: Vec<String> = presets.iter().map(|p| p.key()).collect();
+        assert_eq!(keys, vec!["Wake Up", "Focus/Deep Work", "Party/Loud", "Commute", "Focus/Light", "AC//DC"]);
+        assert_eq!(quicksave.get("spotify:playlist:deep"), "spotify:playlist:saved".parse().ok().as_ref());
+
+        // Each group is shown where its first preset is
+        let items: Vec<String> = menu_items(&presets).iter().map(|item| match *item {
+            PresetMenuItem::Preset(preset) => preset.name.clone(),
+            PresetMenuItem::Group(group, ref members) => format!("{} ({})", group, members.len()),
+        }).collect();
+        assert_eq!(items, vec!["Wake Up", "Focus (2)", "Party (1)", "Commute", "AC/DC"]);
+    }
+
+    #[test]
+    fn test_parse_preset_fields() {
+        let (preset, quicksave) = parse_preset("Dinner", "spotify:playlist:dinner,\
+                                                spotify:playlist:saved, shufle=on, volume 20").unwrap();
+        // A typo doesn't replace the quick-save playlist
+        assert_eq!(quicksave, "spotify:playlist:saved".parse().ok());
+        assert_eq!(preset.options.volume, Some(20));
+        assert_eq!(preset.options.shuffle, None);
+    }
+}
@@ -0,0 +1,53 @@
+// Local state files: small JSON files next to connectr.ini that keep
+// history across restarts, like resume points and recent quick-saves.
+
+#[cfg(test)]
+mod test;
+
+extern crate serde;
+extern crate serde_json;
+
+use self::serde::Serialize;
+use self::serde::de::DeserializeOwned;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// Read `what` from the state file at `path`.  A missing file is normal
+/// the first time, anything else that goes wrong is logged as a warning.
+pub fn load<T: DeserializeOwned>(path: &str, what: &str) -> Option<T> {
+    let mut json = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut json)) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("No {} in {}", what, path);
+            return None;
+        },
+        Err(e) => {
+            warn!("Couldn't read {} from {}: {}", what, path, e);
+            return None;
+        },
+    }
+    match serde_json::from_str(&json) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Couldn't read {} from {}: {}", what, path, e);
+            None
+        },
+    }
+}
+
+/// Write `what` to the state file at `path`, logging a warning if it
+/// can't be saved.
+pub fn save<T: Serialize>(path: &str, what: &str, state: &T) {
+    let json = match serde_json::to_string_pretty(state) {
+        Ok(json) => json,
+        Err(e) => {
+            warn!("Couldn't save {} to {}: {}", what, path, e);
+            return;
+        },
+    };
+    if let Err(e) = File::create(path).and_then(|mut f| f.write_all(json.as_bytes())) {
+        warn!("Couldn't save {} to {}: {}", what, path, e);
+    }
+}
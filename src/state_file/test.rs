@@ -0,0 +1,24 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_load_save() {
+        let path = ::std::env::temp_dir().join(format!("connectr_state_{}.json", ::std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(load::<Vec<String>>(&path, "test state"), None);
+
+        let mut state = BTreeMap::new();
+        state.insert("spotify:episode:pilot".to_string(), 754000u64);
+        save(&path, "test state", &state);
+        assert_eq!(load(&path, "test state"), Some(state));
+
+        // A file that doesn't parse is the same as none
+        assert_eq!(load::<Vec<String>>(&path, "test state"), None);
+        save("/nonexistent/connectr_state.json", "test state", &vec!["lost"]);
+        let _ = ::std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,902 @@
+#[cfg(test)]
+mod tests {
+    extern crate futures;
+    extern crate hyper;
+
+    use super::super::*;
+    use connectr::SpotifyEndpoints;
+    use connectr::SpotifyConnectr;
+    use connectr::{SpotifyError, SpotifyRepeat};
+    use connectr::controller::{handle_command, apply_transfer_rules, RefreshTime};
+    use connectr::settings::Settings;
+    use connectr::preset::Preset;
+    use connectr::quicksave::SaveRule;
+    use connectr::devices::{DeviceSettings, TransferRule, device_events};
+    use connectr::recording::{RecordingStatusBar, StatusBarCall};
+
+    use std::thread;
+    use std::collections::BTreeMap;
+    use std::thread::sleep;
+    use std::sync::{Mutex, MutexGuard, Once};
+    use std::sync::atomic::AtomicBool;
+
+    use self::hyper::{Get, Post, Delete, StatusCode};
+    use self::hyper::server::{Service, Request, Response};
+    use self::hyper::server::Http;
+    use self::futures::Stream;
+    use self::futures::Future;
+
+    static START: Once = Once::new();
+
+    /// Spotify state served by the mock server, and every request it saw.
+    struct MockSpotify {
+        devices: String,
+        player: Option<MockPlayer>,
+        // Body of a 404 response to send to player commands
+        error: Option<String>,
+        // Track URIs in each playlist, by playlist ID
+        playlists: BTreeMap<String, Vec<String>>,
+        requests: Vec<String>,
+    }
+
+    lazy_static! {
+        static ref WEBSERVER_STARTED: AtomicBool = AtomicBool::new(false);
+        static ref MOCK: Mutex<MockSpotify> = Mutex::new(MockSpotify {
+            devices: r#"{"devices": []}"#.to_string(),
+            player: None,
+            error: None,
+            playlists: BTreeMap::new(),
+            requests: Vec::new(),
+        });
+        // Tests share the mock server, so only one may use it at a time.
+        static ref SERIAL: Mutex<()> = Mutex::new(());
+    }
+
+    pub const TEST_API: SpotifyEndpoints = SpotifyEndpoints {
+        scopes: "user-read-private streaming user-read-playback-state",
+        scopes_version: 1,
+        authorize: "http://127.0.0.1:9800/en/authorize",
+        token: "http://127.0.0.1:9800/api/token",
+        devices: "http://127.0.0.1:9800/v1/me/player/devices",
+        player_state: "http://127.0.0.1:9800/v1/me/player",
+        play: "http://127.0.0.1:9800/v1/me/player/play",
+        pause: "http://127.0.0.1:9800/v1/me/player/pause",
+        next: "http://127.0.0.1:9800/v1/me/player/next",
+        previous: "http://127.0.0.1:9800/v1/me/player/previous",
+        seek: "http://127.0.0.1:9800/v1/me/player/seek",
+        volume: "http://127.0.0.1:9800/v1/me/player/volume",
+        shuffle: "http://127.0.0.1:9800/v1/me/player/shuffle",
+        repeat: "http://127.0.0.1:9800/v1/me/player/repeat",
+        player: "http://127.0.0.1:9800/v1/me/player",
+        add_to_playlist: "http://127.0.0.1:9800/v1/playlists",
+        metadata: "http://127.0.0.1:9800/v1",
+        playlists: "http://127.0.0.1:9800/v1/me/playlists",
+        saved_albums: "http://127.0.0.1:9800/v1/me/albums",
+    };
+
+    fn mock() -> MutexGuard<'static, MockSpotify> {
+        MOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start the mock server if needed, and reset it to an empty state.
+    fn init() -> MutexGuard<'static, ()> {
+        let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        START.call_once(|| {
+            #[derive(Clone, Copy)]
+            struct Webapi;
+            impl Service for Webapi {
+                type Request = Request;
+                type Response = Response;
+                type Error = hyper::Error;
+                type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error>>;
+                fn call(&self, req: Request) -> Self::Future {
+                    let (method, uri, _, _headers, body) = req.deconstruct();
+                    Box::new(body.fold(vec![], |mut acc, chunk| {
+                        acc.extend(chunk);
+                        Ok::<_, hyper::Error>(acc)
+                    }).and_then(move |bytes| {
+                        let body = String::from_utf8_lossy(&bytes).to_string();
+                        let mut mock = mock();
+                        mock.requests.push(format!("{} {} {}", method, uri, body).trim().to_string());
+                        let (code, response) = match (method, uri.path()) {
+                            (Post, "/api/token") => {
+                                (StatusCode::Ok, r#"{"access_token": "valid_access_code",
+                                                     "token_type": "Bearer",
+                                                     "expires_in": 3600}"#.to_string())
+                            },
+                            (Get, "/v1/me/player/devices") => (StatusCode::Ok, mock.devices.clone()),
+                            (Get, "/v1/me/player") => match mock.player {
+                                Some(ref player) => (StatusCode::Ok, player.json()),
+                                None => (StatusCode::Accepted, String::new()),
+                            },
+                            // Two pages of playlists, and one of albums
+                            (Get, "/v1/me/playlists") => match uri.query() {
+                                Some(q) if q.contains("offset=1") => (StatusCode::Ok, r#"{"next": null,
+                                    "items": [{"name": "Second", "uri": "spotify:playlist:second",
+                                               "owner": {"id": "bob", "display_name": null}}]}"#.to_string()),
+                                _ => (StatusCode::Ok, r#"{"next": "http://127.0.0.1:9800/v1/me/playlists?offset=1&limit=1",
+                                    "items": [{"name": "First", "uri": "spotify:user:alice:playlist:first",
+                                               "owner": {"id": "alice", "display_name": "Alice"}}]}"#.to_string()),
+                            },
+                            (Get, "/v1/me/albums") => (StatusCode::Ok, r#"{"next": null,
+                                "items": [{"album": {"name": "Bakesale", "uri": "spotify:album:bakesale",
+                                                     "artists": [{"name": "Sebadoh", "uri": "spotify:artist:sebadoh"}]}}]}"#.to_string()),
+                            // Playlists start empty, and snapshots count the changes
+                            (ref method, path) if path.starts_with("/v1/playlists/") &&
+                                path.ends_with("/tracks") => {
+                                let id = path.split("/").nth(3).unwrap().to_string();
+                                let json: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+                                let changes = mock.requests.len();
+                                let tracks = mock.playlists.entry(id).or_insert(vec![]);
+                                match *method {
+                                    Get => {
+                                        let items: Vec<String> = tracks.iter()
+                                            .map(|t| format!(r#"{{"track": {{"uri": "{}"}}}}"#, t))
+                                            .collect();
+                                        (StatusCode::Ok, format!(r#"{{"next": null, "items": [{}]}}"#,
+                                                                 items.join(",")))
+                                    },
+                                    Post => {
+                                        for uri in json["uris"].as_array().unwrap() {
+                                            tracks.push(uri.as_str().unwrap().to_string());
+                                        }
+                                        (StatusCode::Created, format!(r#"{{"snapshot_id": "snap{}"}}"#, changes))
+                                    },
+                                    Delete => {
+                                        for track in json["tracks"].as_array().unwrap() {
+                                            let uri = track["uri"].as_str().unwrap();
+                                            match track["positions"].as_array() {
+                                                Some(positions) => for pos in positions {
+                                                    let pos = pos.as_u64().unwrap() as usize;
+                                                    if tracks.get(pos).map(|t| t.as_str()) == Some(uri) {
+                                                        tracks.remove(pos);
+                                                    }
+                                                },
+                                                None => tracks.retain(|t| t != uri),
+                                            }
+                                        }
+                                        (StatusCode::Ok, format!(r#"{{"snapshot_id": "snap{}"}}"#, changes))
+                                    },
+                                    _ => (StatusCode::MethodNotAllowed, String::new()),
+                                }
+                            },
+                            (Get, "/v1/me") => (StatusCode::Ok, r#"{"id": "alice"}"#.to_string()),
+                            // Contexts are named after their ID, and Bob's
+                            // playlists start with "bob"
+                            (Get, path) if path.starts_with("/v1/albums/") ||
+                                path.starts_with("/v1/playlists/") => {
+                                let id = path.rsplit("/").next().unwrap();
+                                let owner = match id.starts_with("bob") { true => "bob", false => "alice" };
+                                (StatusCode::Ok, format!(r#"{{"name": "{}", "owner": {{"id": "{}", "display_name": null}}}}"#,
+                                                         id, owner))
+                            },
+                            // Player commands have no response
+                            _ => match mock.error {
+                                Some(ref error) => (StatusCode::NotFound, error.clone()),
+                                None => (StatusCode::NoContent, String::new()),
+                            },
+                        };
+                        Ok(Response::new().with_status(code).with_body(response))
+                    }))
+                }
+            }
+            thread::spawn(move || {
+                let addr = "127.0.0.1:9800".parse().unwrap();
+                let server = Http::new().bind(&addr, || Ok(Webapi)).unwrap();
+                WEBSERVER_STARTED.store(true, Ordering::Relaxed);
+                server.run().unwrap();
+            });
+        });
+        while !WEBSERVER_STARTED.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(100));
+        }
+        {
+            let mut mock = mock();
+            mock.devices = devices_json("desktop", 50);
+            mock.player = Some(MockPlayer::new("First", true, 1000));
+            mock.error = None;
+            mock.playlists.clear();
+            mock.requests.clear();
+        }
+        guard
+    }
+
+    const NO_ACTIVE_DEVICE: &'static str = r#"{"error": {"status": 404,
+        "message": "Player command failed: No active device found", "reason": "NO_ACTIVE_DEVICE"}}"#;
+
+    fn devices_json(active: &str, volume: u32) -> String {
+        let device = |id: &str, name: &str, dev_type: &str| {
+            format!(r#"{{"id": "{}", "is_active": {}, "is_restricted": false,
+                        "name": "{}", "type": "{}", "volume_percent": {}}}"#,
+                    id, id == active, name, dev_type, volume)
+        };
+        format!(r#"{{"devices": [{}, {}]}}"#,
+                device("desktop", "Desktop", "Computer"),
+                device("speaker", "Speaker", "Speaker"))
+    }
+
+    /// Player state served by the mock server.
+    #[derive(Clone)]
+    struct MockPlayer {
+        track: &'static str,
+        playing: bool,
+        progress_ms: u64,
+        volume: u32,
+        context: &'static str,
+        device: &'static str,
+    }
+
+    impl MockPlayer {
+        fn new(track: &'static str, playing: bool, progress_ms: u64) -> MockPlayer {
+            MockPlayer {
+                track: track,
+                playing: playing,
+                progress_ms: progress_ms,
+                volume: 50,
+                context: "spotify:playlist:morning",
+                device: "desktop",
+            }
+        }
+        fn json(&self) -> String {
+            let name = match self.device {
+                "speaker" => "Speaker",
+                _ => "Desktop",
+            };
+            format!(r#"{{"timestamp": 0,
+                        "device": {{"id": "{}", "is_active": true, "is_restricted": false,
+                                   "name": "{}", "type": "Computer", "volume_percent": {}}},
+                        "progress_ms": {},
+                        "is_playing": {},
+                        "item": {{"type": "track", "duration_ms": 200000, "name": "{}", "uri": "spotify:track:{}",
+                                 "album": {{"name": "Album", "uri": "spotify:album:album"}},
+                                 "artists": [{{"name": "Artist", "uri": "spotify:artist:artist"}}]}},
+                        "shuffle_state": false,
+                        "repeat_state": "off",
+                        "context": {{"uri": "{}"}}}}"#,
+                    self.device, name, self.volume, self.progress_ms, self.playing,
+                    self.track, self.track, self.context)
+        }
+    }
+
+    fn player_state(track: &'static str, playing: bool, progress_ms: u64) -> PlayerState {
+        serde_json::from_str(&MockPlayer::new(track, playing, progress_ms).json()).unwrap()
+    }
+
+    fn mock_player() -> MockPlayer {
+        mock().player.clone().unwrap()
+    }
+
+    fn set_mock_player(player: MockPlayer) {
+        let mut mock = mock();
+        mock.devices = devices_json(player.device, player.volume);
+        mock.player = Some(player);
+    }
+
+    fn is_playing(spotify: &SpotifyThread) -> bool {
+        match *spotify.player_state.read().unwrap() {
+            Some(ref state) => state.is_playing,
+            None => false,
+        }
+    }
+
+    fn test_settings() -> Settings {
+        let mut settings: Settings = Default::default();
+        settings.presets = vec![
+            Preset::new("Morning", "spotify:playlist:morning"),
+            Preset::new("Evening", "spotify:playlist:evening"),
+            Preset::parse("Focus/Deep Work", "spotify:playlist:deep").unwrap(),
+        ];
+        settings.default_quicksave = "spotify:user:alice:playlist:saved".parse().ok();
+        settings.quicksave_rules = vec![
+            SaveRule::parse("Night Owl", "spotify:playlist:owl, device Bedroom").unwrap(),
+            SaveRule::parse("Sleepy", "spotify:playlist:sleepy spotify:playlist:owl, \
+                                       device Bedroom, artist Artist").unwrap(),
+        ];
+        settings
+    }
+
+    fn test_connectr() -> Option<SpotifyConnectr<'static>> {
+        test_connectr_with(test_settings())
+    }
+
+    fn test_connectr_with(settings: Settings) -> Option<SpotifyConnectr<'static>> {
+        let now = time::now_utc().to_timespec().sec as u64;
+        SpotifyConnectr::new()
+            .with_api(TEST_API)
+            .with_oauth_tokens("access", "refresh", now + 3600)
+            .with_settings(settings)
+            .build()
+    }
+
+    /// Poll until `cond` holds, giving up after a few seconds.
+    fn wait_until<F: Fn() -> bool>(cond: F) -> bool {
+        for _ in 0..50 {
+            if cond() {
+                return true;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        false
+    }
+
+    /// Wait for a request starting with `prefix`, and return all of it.
+    fn wait_for_request(prefix: &str) -> Option<String> {
+        wait_until(|| mock().requests.iter().any(|r| r.starts_with(prefix)));
+        let requests = &mock().requests;
+        requests.iter().find(|r| r.starts_with(prefix)).cloned()
+    }
+
+    fn playing_track(spotify: &SpotifyThread) -> Option<String> {
+        match *spotify.player_state.read().unwrap() {
+            Some(ref state) => state.item.as_ref().map(|i| i.name().to_string()),
+            None => None,
+        }
+    }
+
+    fn active_device(spotify: &SpotifyThread) -> Option<String> {
+        match *spotify.device_list.read().unwrap() {
+            Some(ref devices) => devices.devices.iter()
+                .find(|d| d.is_active).map(|d| d.name.clone()),
+            None => None,
+        }
+    }
+
+    fn click(status: &RecordingStatusBar, label: &str) {
+        mock().requests.clear();
+        assert!(status.click(label), "no menu item named {}", label);
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::SkipNext),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("POST /v1/me/player/next").is_some());
+
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::PlayPause),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/pause").is_some());
+
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Volume(70)),
+                   (RefreshTime::Now, Ok(())));
+        let req = wait_for_request("PUT /v1/me/player/volume").unwrap();
+        assert!(req.contains("volume_percent=70"));
+
+        // Nothing to pause or resume without a known state
+        mock().requests.clear();
+        assert_eq!(handle_command(None, &mut spotify, &Command::PlayPause),
+                   (RefreshTime::Now, Ok(())));
+        assert!(mock().requests.is_empty());
+
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Redraw),
+                   (RefreshTime::Redraw, Ok(())));
+        assert!(mock().requests.is_empty());
+
+        // Resuming plays the item, then seeks to where it was left
+        let resume = Command::Resume("spotify:episode:pilot".to_string(), 754000);
+        assert_eq!(handle_command(Some(&state), &mut spotify, &resume),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play").unwrap()
+                .contains(r#""uris":["spotify:episode:pilot"]"#));
+        assert!(wait_for_request("PUT /v1/me/player/seek").unwrap().contains("position_ms=754000"));
+
+        // Disallowed actions aren't sent at all
+        mock().requests.clear();
+        let mut limited = state.clone();
+        limited.actions.disallows.skipping_next = true;
+        assert_eq!(handle_command(Some(&limited), &mut spotify, &Command::SkipNext),
+                   (RefreshTime::Later, Err(SpotifyError::Disallowed(PlayerAction::SkipNext))));
+        let mut restricted = state.clone();
+        restricted.device.is_restricted = true;
+        assert_eq!(handle_command(Some(&restricted), &mut spotify, &Command::Volume(20)),
+                   (RefreshTime::Later, Err(SpotifyError::Disallowed(PlayerAction::Volume))));
+        assert!(mock().requests.is_empty());
+        assert_eq!(handle_command(Some(&restricted), &mut spotify,
+                                  &Command::SelectDevice("speaker".to_string())),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player ").is_some());
+
+        // Errors from Spotify are passed back
+        mock().error = Some(NO_ACTIVE_DEVICE.to_string());
+        let (_, result) = handle_command(Some(&state), &mut spotify, &Command::SkipNext);
+        assert_eq!(result, Err(SpotifyError::Rejected {
+            status: 404,
+            message: "Player command failed: No active device found".to_string(),
+            reason: Some("NO_ACTIVE_DEVICE".to_string()),
+        }));
+    }
+
+    #[test]
+    fn test_quick_save() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        // Quick-save goes to the default playlist unless a rule matches, and
+        // then to every playlist of every matching rule
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::SaveTrack),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("POST /v1/playlists/saved/tracks").is_some());
+        let mut bedroom = state.clone();
+        bedroom.device.name = "bedroom".to_string();
+        mock().requests.clear();
+        assert_eq!(handle_command(Some(&bedroom), &mut spotify, &Command::SaveTrack),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("POST /v1/playlists/sleepy/tracks").is_some());
+        let saves = mock().requests.iter().filter(|r| r.starts_with("POST /v1/playlists/")).count();
+        assert_eq!(saves, 2);
+        assert!(!mock().requests.iter().any(|r| r.starts_with("POST /v1/playlists/saved")));
+
+        // Saving again doesn't add duplicates
+        mock().requests.clear();
+        assert_eq!(handle_command(Some(&bedroom), &mut spotify, &Command::SaveTrack),
+                   (RefreshTime::Now, Ok(())));
+        assert!(!mock().requests.iter().any(|r| r.starts_with("POST")));
+        assert_eq!(spotify.quick_saves().len(), 3);
+
+        // Undo takes the track out of every playlist the last save went to,
+        // at the position it was added
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::UndoQuickSave),
+                   (RefreshTime::Now, Ok(())));
+        let req = wait_for_request("DELETE /v1/playlists/sleepy/tracks").unwrap();
+        assert!(req.contains(r#""positions":[0]"#));
+        assert!(req.contains(r#""snapshot_id":"snap"#));
+        assert!(mock().playlists["owl"].is_empty());
+        assert_eq!(mock().playlists["saved"], vec!["spotify:track:First"]);
+        assert_eq!(spotify.quick_saves().len(), 1);
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::UndoQuickSave),
+                   (RefreshTime::Now, Ok(())));
+        assert!(mock().playlists["saved"].is_empty());
+        mock().requests.clear();
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::UndoQuickSave),
+                   (RefreshTime::Now, Ok(())));
+        assert!(mock().requests.is_empty());
+    }
+
+    #[test]
+    fn test_remove_track() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        // Removing takes every copy of the track out of the user's own
+        // playlist and skips it
+        mock().playlists.insert("morning".to_string(),
+                                vec!["spotify:track:First".to_string(), "spotify:track:Second".to_string(),
+                                     "spotify:track:First".to_string()]);
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::RemoveTrack),
+                   (RefreshTime::Now, Ok(())));
+        let req = wait_for_request("DELETE /v1/playlists/morning/tracks").unwrap();
+        assert!(!req.contains("positions"));
+        assert!(wait_for_request("POST /v1/me/player/next").is_some());
+        assert_eq!(mock().playlists["morning"], vec!["spotify:track:Second"]);
+        // ...and refuses anyone else's, or anything but a playlist
+        for context in &["spotify:playlist:bobs", "spotify:album:bakesale"] {
+            let mut other = state.clone();
+            other.context.as_mut().unwrap().uri = context.to_string();
+            mock().requests.clear();
+            assert_eq!(handle_command(Some(&other), &mut spotify, &Command::RemoveTrack),
+                       (RefreshTime::Now, Err(SpotifyError::NotYourPlaylist(context.to_string()))));
+            assert!(!mock().requests.iter().any(|r| r.starts_with("DELETE") || r.contains("/next")));
+        }
+    }
+
+    #[test]
+    fn test_surprise() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        // A random preset from a group with one member is always that one
+        let surprise = Command::Preset(spotify.surprise_preset(Some("Focus")).unwrap());
+        assert_eq!(handle_command(Some(&state), &mut spotify, &surprise),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play").unwrap()
+                .contains(r#""context_uri":"spotify:playlist:deep""#));
+        // ...and a surprise with nothing to choose from plays nothing, and
+        // says so
+        mock().requests.clear();
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Surprise(Some("Nope".to_string()))),
+                   (RefreshTime::Now, Err(SpotifyError::NoPresets(Some("Nope".to_string())))));
+        assert!(mock().requests.is_empty());
+    }
+
+    #[test]
+    fn test_preset_options() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        // Presets set up the player in order, then play
+        let mut dinner = Preset::new("Dinner", "spotify:playlist:dinner");
+        dinner.options.device = Some("speaker".to_string());
+        dinner.options.volume = Some(25);
+        dinner.options.shuffle = Some(true);
+        dinner.options.repeat = Some(SpotifyRepeat::Context);
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(dinner.clone())),
+                   (RefreshTime::Now, Ok(())));
+        let requests: Vec<String> = mock().requests.iter()
+            .map(|r| r.split(|c| c == ' ' || c == '?').take(2).collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(requests, vec!["GET /v1/me/player/devices",
+                                  "PUT /v1/me/player",
+                                  "PUT /v1/me/player/volume",
+                                  "PUT /v1/me/player/shuffle",
+                                  "PUT /v1/me/player/repeat",
+                                  "PUT /v1/me/player/play"]);
+        let requests = mock().requests.clone();
+        assert!(requests[1].contains(r#""device_ids":["speaker"],"play":false"#));
+        assert!(requests[2].contains("device_id=speaker") && requests[2].contains("volume_percent=25"));
+        assert!(requests[3].contains("state=true"));
+        assert!(requests[4].contains("state=context"));
+        assert!(requests[5].contains("device_id=speaker") && requests[5].contains("spotify:playlist:dinner"));
+
+        // Devices can be named, but must exist
+        mock().requests.clear();
+        dinner.options.device = Some("DESKTOP".to_string());
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(dinner.clone())),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play?device_id=desktop").is_some());
+        mock().requests.clear();
+        dinner.options.device = Some("Kitchen".to_string());
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(dinner)),
+                   (RefreshTime::Now, Err(SpotifyError::UnknownDevice("Kitchen".to_string()))));
+        assert_eq!(mock().requests, vec!["GET /v1/me/player/devices"]);
+    }
+
+    #[test]
+    fn test_save_preset() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let state = player_state("First", true, 1000);
+
+        // Saving a preset names it after what's playing
+        let mut album = state.clone();
+        album.context.as_mut().unwrap().uri = "spotify:album:Bakesale".to_string();
+        assert_eq!(handle_command(Some(&album), &mut spotify, &Command::SavePreset),
+                   (RefreshTime::Redraw, Ok(())));
+        assert_eq!(mock().requests, vec!["GET /v1/albums/Bakesale"]);
+        assert_eq!(spotify.get_presets().last(),
+                   Some(&Preset::new("Bakesale", "spotify:album:Bakesale")));
+        // Only once, and without replacing a preset with the same name
+        let count = spotify.get_presets().len();
+        assert_eq!(handle_command(Some(&album), &mut spotify, &Command::SavePreset).1, Ok(()));
+        assert_eq!(spotify.get_presets().len(), count);
+        let mut playlist = state.clone();
+        playlist.context.as_mut().unwrap().uri = "spotify:playlist:Morning".to_string();
+        assert_eq!(handle_command(Some(&playlist), &mut spotify, &Command::SavePreset).1, Ok(()));
+        assert_eq!(spotify.get_presets().last().map(|p| p.name.as_str()), Some("Morning (2)"));
+        assert_eq!(spotify.get_presets()[0].name, "Morning");
+
+        // Without a context there's nothing to save, and the user is told
+        mock().requests.clear();
+        let count = spotify.get_presets().len();
+        let mut tracks = state.clone();
+        tracks.context = None;
+        assert_eq!(handle_command(Some(&tracks), &mut spotify, &Command::SavePreset),
+                   (RefreshTime::Redraw, Err(SpotifyError::NothingToSave)));
+        assert_eq!(handle_command(None, &mut spotify, &Command::SavePreset),
+                   (RefreshTime::Redraw, Err(SpotifyError::NothingToSave)));
+        assert!(mock().requests.is_empty());
+        assert_eq!(spotify.get_presets().len(), count);
+    }
+
+    #[test]
+    fn test_default_device() {
+        let _guard = init();
+        let mut settings = test_settings();
+        settings.devices = DeviceSettings {
+            aliases: vec![("speaker".to_string(), "Living Room".to_string())],
+            hidden: vec!["Desktop".to_string()],
+            order: vec![],
+            default: Some("living room".to_string()),
+        };
+        let mut spotify = test_connectr_with(settings).unwrap();
+        mock().devices = devices_json("", 50);
+
+        // With nothing playing, play starts on the default device
+        assert_eq!(handle_command(None, &mut spotify, &Command::PlayPause),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player ").unwrap()
+                .contains(r#""device_ids":["speaker"],"play":true"#));
+
+        // ...and so do presets and resume points
+        mock().requests.clear();
+        let morning = Preset::new("Morning", "spotify:playlist:morning");
+        assert_eq!(handle_command(None, &mut spotify, &Command::Preset(morning.clone())),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play?device_id=speaker").is_some());
+        mock().requests.clear();
+        let resume = Command::Resume("spotify:episode:pilot".to_string(), 1000);
+        assert_eq!(handle_command(None, &mut spotify, &resume), (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play?device_id=speaker").is_some());
+
+        // Not while something is playing, though
+        mock().requests.clear();
+        let state = player_state("First", false, 1000);
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(morning)),
+                   (RefreshTime::Now, Ok(())));
+        assert_eq!(mock().requests.len(), 1);
+        assert!(!mock().requests[0].contains("device_id"));
+
+        // Presets can name a device by its alias
+        mock().requests.clear();
+        let mut dinner = Preset::new("Dinner", "spotify:playlist:dinner");
+        dinner.options.device = Some("Living Room".to_string());
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(dinner)),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play?device_id=speaker").is_some());
+
+        // ...or loosely
+        mock().requests.clear();
+        let mut dinner = Preset::new("Dinner", "spotify:playlist:dinner");
+        dinner.options.device = Some("~living".to_string());
+        assert_eq!(handle_command(Some(&state), &mut spotify, &Command::Preset(dinner)),
+                   (RefreshTime::Now, Ok(())));
+        assert!(wait_for_request("PUT /v1/me/player/play?device_id=speaker").is_some());
+        assert_eq!(spotify.resolve_device("~desk"), Some("desktop".to_string()));
+        assert_eq!(spotify.resolve_device("~kitchen"), None);
+
+        // A default device that isn't online is an error
+        mock().devices = r#"{"devices": []}"#.to_string();
+        assert_eq!(handle_command(None, &mut spotify, &Command::PlayPause),
+                   (RefreshTime::Now, Err(SpotifyError::UnknownDevice("living room".to_string()))));
+    }
+
+    #[test]
+    fn test_transfer_rules() {
+        let _guard = init();
+        let mut settings = test_settings();
+        settings.transfer_rules = vec![
+            TransferRule::parse("Speaker", "appears speaker, to Speaker").unwrap(),
+            TransferRule::parse("Desktop Gone", "leaves Desktop, to Speaker, play").unwrap(),
+        ];
+        let mut spotify = test_connectr_with(settings).unwrap();
+        let both: ConnectDeviceList = serde_json::from_str(&devices_json("desktop", 50)).unwrap();
+        let mut desktop = both.clone();
+        desktop.devices.truncate(1);
+        let mut speaker = both.clone();
+        speaker.devices.remove(0);
+        let state = player_state("First", true, 1000);
+
+        // The speaker comes online while the desktop plays
+        mock().requests.clear();
+        let events = device_events(&desktop, &both);
+        assert!(apply_transfer_rules(&mut spotify, &events, &both, Some(&state), None));
+        assert!(wait_for_request("PUT /v1/me/player ").unwrap()
+                .contains(r#""device_ids":["speaker"],"play":false"#));
+
+        // Nothing is moved when nothing plays
+        mock().requests.clear();
+        assert!(!apply_transfer_rules(&mut spotify, &events, &both, None, None));
+        assert!(mock().requests.is_empty());
+
+        // The desktop goes away while it was playing, even if Spotify
+        // already stopped reporting it
+        let events = device_events(&both, &speaker);
+        assert!(apply_transfer_rules(&mut spotify, &events, &speaker, None, Some(&state)));
+        assert!(wait_for_request("PUT /v1/me/player ").unwrap()
+                .contains(r#""device_ids":["speaker"],"play":true"#));
+
+        // A target that isn't online is skipped
+        mock().requests.clear();
+        let events = device_events(&both, &ConnectDeviceList::default());
+        assert!(!apply_transfer_rules(&mut spotify, &events, &ConnectDeviceList::default(),
+                                      None, Some(&state)));
+        assert!(mock().requests.is_empty());
+
+        // When two rules match, the first one listed always wins
+        let mut settings = test_settings();
+        settings.transfer_rules = vec![
+            TransferRule::parse("Speaker Plays", "appears ~speak, to Speaker, play").unwrap(),
+            TransferRule::parse("Speaker", "appears speaker, to Speaker").unwrap(),
+        ];
+        let mut spotify = test_connectr_with(settings).unwrap();
+        let events = device_events(&desktop, &both);
+        for _ in 0..3 {
+            mock().requests.clear();
+            assert!(apply_transfer_rules(&mut spotify, &events, &both, Some(&state), None));
+            assert!(wait_for_request("PUT /v1/me/player ").unwrap()
+                    .contains(r#""device_ids":["speaker"],"play":true"#));
+        }
+    }
+
+    #[test]
+    fn test_request_library() {
+        let _guard = init();
+        let mut spotify = test_connectr().unwrap();
+        let library = spotify.request_library().unwrap();
+        let names: Vec<(&str, &str, bool)> = library.iter()
+            .map(|e| (e.name.as_str(), e.by.as_str(), e.is_playlist)).collect();
+        assert_eq!(names, vec![("First", "Alice", true), ("Second", "bob", true),
+                               ("Bakesale", "Sebadoh", false)]);
+        assert!(wait_for_request("GET /v1/me/playlists?offset=1").is_some());
+
+        // Imported presets are added once, with their quick-save playlist
+        let choices = connectr::import::parse_selection("3=1 1", library.len()).unwrap();
+        let presets = connectr::import::choices_to_presets(&library, &choices);
+        assert_eq!(spotify.import_presets(presets.clone()), Ok(2));
+        assert_eq!(spotify.import_presets(presets), Ok(0));
+        assert_eq!(spotify.quick_save_playlist("spotify:album:bakesale").map(|u| u.to_string()),
+                   Some("spotify:playlist:first".to_string()));
+        assert_eq!(spotify.get_presets().len(), 5);
+    }
+
+    #[test]
+    fn test_quit() {
+        let _guard = init();
+        let (tx, rx) = channel::<CommandMessage>();
+        let spotify = create_spotify_thread(rx, test_connectr);
+        assert!(wait_until(|| playing_track(&spotify).is_some()));
+
+        // Quitting stops the thread and tells the frontend to exit
+        let _ = tx.send(CommandMessage::new(Command::Quit));
+        let quit = wait_until(|| {
+            spotify.rx.try_iter().any(|cmd| match cmd {
+                SpotifyThreadCommand::Quit => true,
+                _ => false,
+            })
+        });
+        assert!(quit);
+        assert!(spotify.quit(&tx, Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_quit_during_settings_form() {
+        let _guard = init();
+        // Connecting can wait on the settings form, and Quit isn't read
+        // until it's closed
+        let (tx, rx) = channel::<CommandMessage>();
+        let spotify = create_spotify_thread(rx, || {
+            thread::sleep(Duration::from_secs(3));
+            None
+        });
+        let start = time::now_utc();
+        assert!(!spotify.quit(&tx, Duration::from_millis(200)));
+        assert!(time::now_utc() - start < time::Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_spotify_thread_end_to_end() {
+        let _guard = init();
+        let (tx, rx) = channel::<CommandMessage>();
+        let spotify = create_spotify_thread(rx, test_connectr);
+        assert!(wait_until(|| playing_track(&spotify).is_some()));
+
+        let mut app = ConnectrApp { menu: MenuRenderer::new() };
+        let mut status = RecordingStatusBar::new(tx.clone());
+        assert!(app.menu.update(&mut status, build_menu(&spotify, false), menu_callback));
+        assert_eq!(status.calls()[0], StatusBarCall::SetTooltip("First\nArtist\nAlbum".to_string()));
+        let labels: Vec<&str> = status.labels().iter().map(|l| l.trim()).collect();
+        assert!(labels.contains(&"First"));
+        assert!(labels.contains(&"Artist"));
+        assert!(labels.contains(&"3:20"));
+        assert!(status.item("Pause").is_some());
+        assert!(status.item("Morning").unwrap().selected);
+        assert!(!status.item("Evening").unwrap().selected);
+        // Grouped presets are in a submenu
+        let deep = status.item("Deep Work").unwrap();
+        assert!(deep.menu.is_some() && !deep.selected);
+        assert!(status.calls().contains(&StatusBarCall::AddSubmenu("Focus".to_string())));
+        assert!(status.item("Desktop").unwrap().selected);
+        assert!(!status.item("Speaker").unwrap().selected);
+        let volume = status.item("50%").unwrap();
+        assert!(volume.selected);
+        assert!(volume.menu.is_some());
+
+        // Clicks become requests to Spotify.  The mock only changes state
+        // when the test says so, like a very slow Spotify.
+        let mut player = mock_player();
+        player.playing = false;
+        set_mock_player(player.clone());
+        click(&status, "Pause");
+        assert!(wait_for_request("PUT /v1/me/player/pause").is_some());
+        player.volume = 30;
+        set_mock_player(player.clone());
+        click(&status, "30%");
+        assert!(wait_for_request("PUT /v1/me/player/volume").unwrap().contains("volume_percent=30"));
+        player.playing = true;
+        player.context = "spotify:playlist:evening";
+        set_mock_player(player.clone());
+        click(&status, "Evening");
+        assert!(wait_for_request("PUT /v1/me/player/play").unwrap()
+                .contains(r#""context_uri":"spotify:playlist:evening""#));
+        click(&status, "Quick-Save");
+        assert!(wait_for_request("POST /v1/playlists/saved/tracks").unwrap()
+                .contains("spotify:track:First"));
+        assert!(wait_until(|| is_playing(&spotify)));
+        app.menu.update(&mut status, build_menu(&spotify, false), menu_callback);
+        assert!(status.item("Evening").unwrap().selected);
+        assert!(status.item("30%").unwrap().selected);
+
+        // Switching device only moves the checkmarks
+        player.device = "speaker";
+        set_mock_player(player.clone());
+        click(&status, "Speaker");
+        assert!(wait_for_request("PUT /v1/me/player ").unwrap().contains(r#""device_ids":["speaker"]"#));
+        assert!(wait_until(|| active_device(&spotify) == Some("Speaker".to_string())));
+        status.take_calls();
+        assert!(app.menu.update(&mut status, build_menu(&spotify, false), menu_callback));
+        let calls = status.take_calls();
+        assert!(!calls.contains(&StatusBarCall::ClearItems));
+        assert!(calls.contains(&StatusBarCall::UnselItem(status.item("Desktop").unwrap().id)));
+        assert!(calls.contains(&StatusBarCall::SelItem(status.item("Speaker").unwrap().id)));
+        assert!(status.item("Speaker").unwrap().selected);
+
+        // A new track redraws the whole menu
+        player = MockPlayer::new("Second", false, 0);
+        player.volume = 30;
+        player.context = "spotify:playlist:evening";
+        player.device = "speaker";
+        set_mock_player(player.clone());
+        click(&status, "Next");
+        assert!(wait_for_request("POST /v1/me/player/next").is_some());
+        assert!(wait_until(|| playing_track(&spotify) == Some("Second".to_string())));
+        assert!(wait_until(|| !is_playing(&spotify)));
+        assert!(app.menu.update(&mut status, build_menu(&spotify, false), menu_callback));
+        assert!(status.take_calls().contains(&StatusBarCall::ClearItems));
+        assert!(status.labels().iter().any(|l| l.trim() == "Second"));
+        assert!(status.item("Play").is_some());
+
+        // Nothing changed, nothing redrawn
+        assert!(!app.menu.update(&mut status, build_menu(&spotify, false), menu_callback));
+        assert!(status.take_calls().is_empty());
+
+        // Commands show up before Spotify reports them, and survive polls
+        // that haven't caught up yet.
+        click(&status, "Play");
+        assert!(wait_for_request("PUT /v1/me/player/play").is_some());
+        assert!(wait_until(|| is_playing(&spotify)));
+        mock().requests.clear();
+        assert!(wait_for_request("GET /v1/me/player?additional_types=episode").is_some());
+        sleep(Duration::from_millis(300));
+        assert!(is_playing(&spotify));
+
+        // Results come back to whoever asked for them
+        let (msg, reply) = CommandMessage::with_reply(Command::SkipPrev);
+        tx.send(msg).unwrap();
+        assert_eq!(reply.recv_timeout(Duration::from_secs(5)), Ok(Ok(())));
+        {
+            let mut mock = mock();
+            mock.error = Some(NO_ACTIVE_DEVICE.to_string());
+            mock.devices = devices_json("", 50);
+            mock.player = None;
+        }
+        let (msg, reply) = CommandMessage::with_reply(Command::SkipPrev);
+        tx.send(msg).unwrap();
+        match reply.recv_timeout(Duration::from_secs(5)) {
+            Ok(Err(SpotifyError::Rejected { reason, .. })) => {
+                assert_eq!(reason, Some("NO_ACTIVE_DEVICE".to_string()));
+            },
+            other => panic!("unexpected reply: {:?}", other),
+        }
+
+        // Failures are explained until they are fixed
+        let banner = "\u{26a0} No active device \u{2014} pick one below";
+        app.menu.update(&mut status, build_menu(&spotify, false), menu_callback);
+        assert!(status.labels().iter().any(|l| l == banner));
+        assert!(status.calls().iter().any(|c| match *c {
+            StatusBarCall::SetTooltip(ref text) => text.ends_with(banner),
+            _ => false,
+        }));
+        mock().requests.clear();
+        assert!(wait_for_request("GET /v1/me/player").is_some());
+        sleep(Duration::from_millis(300));
+        assert!(spotify.failure.read().unwrap().is_some());
+        {
+            let mut mock = mock();
+            mock.error = None;
+            mock.devices = devices_json("speaker", 50);
+        }
+        click(&status, "Speaker");
+        assert!(wait_until(|| spotify.failure.read().unwrap().is_none()));
+        app.menu.update(&mut status, build_menu(&spotify, false), menu_callback);
+        assert!(!status.labels().iter().any(|l| l == banner));
+
+        let _ = spotify.tx.send(());
+        let _ = spotify.handle.join();
+    }
+}
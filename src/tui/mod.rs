@@ -0,0 +1,348 @@
+extern crate crossterm;
+
+use self::crossterm::{QueueableCommand, ExecutableCommand};
+use self::crossterm::cursor::{Hide, Show, MoveTo};
+use self::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use self::crossterm::style::{Print, SetAttribute, Attribute};
+use self::crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+
+use std::io::{self, Write, Stdout};
+use std::time::{Duration, Instant};
+use std::sync::mpsc::Sender;
+
+use connectr::ConnectDeviceList;
+use connectr::PlayerState;
+
+use connectr::controller::{SpotifyThread, SpotifyThreadCommand, Command, CommandMessage};
+
+const HELP: &'static str = "space play/pause  n/p next/prev  +/- volume  s save  u undo save  x remove all copies  S save preset  r surprise  \
+                            tab switch list  enter select  q quit";
+
+#[derive(PartialEq, Clone, Copy)]
+enum Focus {
+    Presets,
+    Devices,
+}
+
+enum TerminalState {
+    Syncing,
+    Running,
+    InvalidSettings,
+    ConfigActive,
+}
+
+struct TerminalUI {
+    out: Stdout,
+    tx: Sender<CommandMessage>,
+    state: TerminalState,
+    focus: Focus,
+    preset_idx: usize,
+    device_idx: usize,
+    // When the Spotify thread last published new state.  Used to advance
+    // the progress bar between polls.
+    last_update: Instant,
+    rows: Vec<(String, bool)>,
+    // First row shown, when the rows don't fit the terminal
+    scroll: usize,
+}
+
+impl TerminalUI {
+    fn new(tx: Sender<CommandMessage>) -> crossterm::Result<TerminalUI> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        out.execute(EnterAlternateScreen)?;
+        out.execute(Hide)?;
+        Ok(TerminalUI {
+            out: out,
+            tx: tx,
+            state: TerminalState::Syncing,
+            focus: Focus::Presets,
+            preset_idx: 0,
+            device_idx: 0,
+            last_update: Instant::now(),
+            rows: Vec::new(),
+            scroll: 0,
+        })
+    }
+
+    fn send(&self, command: Command) {
+        let _ = self.tx.send(CommandMessage::new(command));
+    }
+
+    fn line(&mut self, text: &str) {
+        self.rows.push((text.to_string(), false));
+    }
+
+    fn highlighted_line(&mut self, text: &str, highlight: bool) {
+        self.rows.push((text.to_string(), highlight));
+    }
+
+    fn flush(&mut self) -> crossterm::Result<()> {
+        let (cols, height) = terminal::size()?;
+        let rows: Vec<(String, bool)> = self.rows.drain(..).collect();
+        // Scroll just enough to keep the selected row, the last highlighted
+        // one, on screen
+        let body = height.saturating_sub(1) as usize;
+        if let Some(selected) = rows.iter().rposition(|&(_, highlight)| highlight) {
+            if selected < self.scroll {
+                self.scroll = selected;
+            }
+            else if selected >= self.scroll + body {
+                self.scroll = selected + 1 - body;
+            }
+        }
+        self.scroll = ::std::cmp::min(self.scroll, rows.len().saturating_sub(body));
+        for row in 0..height.saturating_sub(1) {
+            self.out.queue(MoveTo(0, row))?;
+            self.out.queue(Clear(ClearType::CurrentLine))?;
+            if let Some(&(ref text, highlight)) = rows.get(self.scroll + row as usize) {
+                let text: String = text.chars().take(cols as usize).collect();
+                if highlight {
+                    self.out.queue(SetAttribute(Attribute::Reverse))?;
+                }
+                self.out.queue(Print(text))?;
+                self.out.queue(SetAttribute(Attribute::Reset))?;
+            }
+        }
+        let help: String = HELP.chars().take(cols as usize).collect();
+        self.out.queue(MoveTo(0, height.saturating_sub(1)))?;
+        self.out.queue(Clear(ClearType::CurrentLine))?;
+        self.out.queue(Print(help))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn draw(&mut self, spotify: &SpotifyThread) -> crossterm::Result<()> {
+        match self.state {
+            TerminalState::Syncing => {
+                self.line("Syncing with Spotify...");
+                return self.flush();
+            },
+            TerminalState::InvalidSettings => {
+                self.line("Invalid Configuration!");
+                self.line("");
+                self.line("Press 'c' to reconfigure Connectr in your web browser.");
+                return self.flush();
+            },
+            TerminalState::ConfigActive => {
+                self.line("Configuration is open in your web browser.");
+                self.line("Connectr is paused until it is saved or cancelled.");
+                return self.flush();
+            },
+            TerminalState::Running => {},
+        }
+
+        let device_list = spotify.device_list.read().unwrap();
+        let player_state = spotify.player_state.read().unwrap();
+        let presets = spotify.presets.read().unwrap();
+        let empty_device_list: ConnectDeviceList = Default::default();
+        let empty_player_state: PlayerState = Default::default();
+        let device_list = device_list.as_ref().unwrap_or(&empty_device_list);
+        let player_state = player_state.as_ref().unwrap_or(&empty_player_state);
+
+        let (cols, _) = terminal::size()?;
+        if let Some(ref failure) = *spotify.failure.read().unwrap() {
+            self.line(&format!("! {}", failure.message()));
+            self.line("");
+        }
+        self.line("Now Playing:");
+        match player_state.item {
+            Some(ref item) => {
+                let artist = item.artist().unwrap_or("").to_string();
+                // Episodes show the podcast before its publisher
+                let (second, third) = match item.is_episode() {
+                    true => (item.collection().to_string(), artist),
+                    false => (artist, item.collection().to_string()),
+                };
+                let state = match player_state.is_playing {
+                    true => "Playing",
+                    false => "Paused",
+                };
+                self.line(&format!("  {}", item.name()));
+                self.line(&format!("  {}", second));
+                self.line(&format!("  {}", third));
+                let mut progress = player_state.progress_ms.unwrap_or(0);
+                if player_state.is_playing {
+                    progress += self.last_update.elapsed().as_secs() * 1000;
+                }
+                let progress = ::std::cmp::min(progress, item.duration_ms());
+                self.line(&format!("  {} {}", state,
+                                   progress_bar(progress, item.duration_ms(), cols as usize)));
+            },
+            None => self.line("  unknown"),
+        }
+        self.line("");
+
+        let volume = match player_state.device.volume_percent {
+            Some(v) => format!("{}%", v),
+            None => "unknown".to_string(),
+        };
+        self.line(&format!("Volume: {}", volume));
+        self.line("");
+
+        self.preset_idx = ::std::cmp::min(self.preset_idx, presets.len().saturating_sub(1));
+        self.highlighted_line("Presets:", self.focus == Focus::Presets);
+        for (idx, preset) in presets.iter().enumerate() {
+            let marker = match preset.is_playing(player_state) {
+                true => "*",
+                false => " ",
+            };
+            let selected = self.focus == Focus::Presets && idx == self.preset_idx;
+            self.highlighted_line(&format!(" {} {}", marker, preset.key()), selected);
+        }
+        self.line("");
+
+        self.device_idx = ::std::cmp::min(self.device_idx, device_list.len().saturating_sub(1));
+        self.highlighted_line("Devices:", self.focus == Focus::Devices);
+        if device_list.len() == 0 {
+            self.line("  unavailable");
+        }
+        for (idx, dev) in device_list.into_iter().enumerate() {
+            let marker = match dev.is_active {
+                true => "*",
+                false => " ",
+            };
+            let selected = self.focus == Focus::Devices && idx == self.device_idx;
+            self.highlighted_line(&format!(" {} {}", marker, dev.name), selected);
+        }
+        self.flush()
+    }
+
+    /// Handle a key press.  Returns false if the user asked to quit.
+    fn handle_key(&mut self, key: KeyEvent, spotify: &SpotifyThread) -> bool {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('c') => self.send(Command::Reconfigure),
+            _ => {},
+        }
+        match self.state {
+            TerminalState::Running => {},
+            _ => return true,
+        }
+        match key.code {
+            KeyCode::Char(' ') => self.send(Command::PlayPause),
+            KeyCode::Char('n') => self.send(Command::SkipNext),
+            KeyCode::Char('p') => self.send(Command::SkipPrev),
+            KeyCode::Char('s') => self.send(Command::SaveTrack),
+            KeyCode::Char('S') => self.send(Command::SavePreset),
+            KeyCode::Char('u') => self.send(Command::UndoQuickSave),
+            KeyCode::Char('x') => self.send(Command::RemoveTrack),
+            KeyCode::Char('a') => self.send(Command::EditAlarms),
+            KeyCode::Char('i') => self.send(Command::ImportPresets),
+            KeyCode::Char('r') => self.send(Command::Surprise(None)),
+            KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Char('-') => {
+                let volume = match *spotify.player_state.read().unwrap() {
+                    Some(ref state) => state.device.volume_percent,
+                    None => None,
+                };
+                if let Some(volume) = volume {
+                    let volume = match key.code {
+                        KeyCode::Char('-') => volume.saturating_sub(10),
+                        _ => ::std::cmp::min(volume + 10, 100),
+                    };
+                    self.send(Command::Volume(volume));
+                }
+            },
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Presets => Focus::Devices,
+                    Focus::Devices => Focus::Presets,
+                };
+            },
+            KeyCode::Up => {
+                match self.focus {
+                    Focus::Presets => self.preset_idx = self.preset_idx.saturating_sub(1),
+                    Focus::Devices => self.device_idx = self.device_idx.saturating_sub(1),
+                }
+            },
+            KeyCode::Down => {
+                // Clamped to the list length on the next draw
+                match self.focus {
+                    Focus::Presets => self.preset_idx += 1,
+                    Focus::Devices => self.device_idx += 1,
+                }
+            },
+            KeyCode::Enter => {
+                match self.focus {
+                    Focus::Presets => {
+                        let preset = spotify.presets.read().unwrap()
+                            .get(self.preset_idx).cloned();
+                        if let Some(preset) = preset {
+                            self.send(Command::Preset(preset));
+                        }
+                    },
+                    Focus::Devices => {
+                        let id = match *spotify.device_list.read().unwrap() {
+                            Some(ref devices) => devices.devices.get(self.device_idx)
+                                .and_then(|d| d.id.clone()),
+                            None => None,
+                        };
+                        if let Some(id) = id {
+                            self.send(Command::SelectDevice(id));
+                        }
+                    },
+                }
+            },
+            _ => {},
+        }
+        true
+    }
+}
+
+impl Drop for TerminalUI {
+    fn drop(&mut self) {
+        let _ = self.out.execute(Show);
+        let _ = self.out.execute(LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn progress_bar(progress_ms: u64, duration_ms: u64, cols: usize) -> String {
+    let time = |ms: u64| { format!("{}:{:02}", ms / 1000 / 60, ms / 1000 % 60) };
+    let label = format!("{} / {}", time(progress_ms), time(duration_ms));
+    let width = cols.saturating_sub(label.len() + 16);
+    let filled = match duration_ms {
+        0 => 0,
+        _ => (progress_ms as usize * width) / duration_ms as usize,
+    };
+    format!("[{}{}] {}", "#".repeat(filled), "-".repeat(width - filled), label)
+}
+
+/// Run a full-screen terminal frontend until the user quits.
+///
+/// Consumes the same state snapshots as the menu bar UI, and sends the same
+/// commands to the Spotify thread.
+pub fn run(spotify: &SpotifyThread, tx: Sender<CommandMessage>) -> crossterm::Result<()> {
+    let mut ui = TerminalUI::new(tx)?;
+    loop {
+        while let Ok(cmd) = spotify.rx.try_recv() {
+            match cmd {
+                SpotifyThreadCommand::Update => {
+                    ui.state = TerminalState::Running;
+                    ui.last_update = Instant::now();
+                },
+                SpotifyThreadCommand::InvalidSettings => {
+                    ui.state = TerminalState::InvalidSettings;
+                },
+                SpotifyThreadCommand::ConfigActive => {
+                    ui.state = TerminalState::ConfigActive;
+                },
+                SpotifyThreadCommand::ConfigInactive => {
+                    ui.state = TerminalState::Running;
+                },
+                SpotifyThreadCommand::Quit => return Ok(()),
+            }
+        }
+        ui.draw(spotify)?;
+        // Doubles as the frame timer, so the progress bar keeps moving.
+        if event::poll(Duration::from_millis(250))? {
+            if let Event::Key(key) = event::read()? {
+                if !ui.handle_key(key, spotify) {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
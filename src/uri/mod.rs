@@ -0,0 +1,145 @@
+// Spotify URIs and open.spotify.com links.
+//
+// Spotify accepts several spellings of the same thing:
+//
+//   spotify:playlist:37i9dQZEVXboyJ0IJdpcuT
+//   spotify:user:spotify:playlist:37i9dQZEVXboyJ0IJdpcuT
+//   https://open.spotify.com/playlist/37i9dQZEVXboyJ0IJdpcuT?si=abcdef
+//
+// All of them parse to the same `SpotifyUri`, which prints as the first form.
+
+#[cfg(test)]
+mod test;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpotifyUriKind {
+    Album,
+    Artist,
+    Episode,
+    Playlist,
+    Show,
+    Track,
+    User,
+}
+
+impl SpotifyUriKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SpotifyUriKind::Album => "album",
+            SpotifyUriKind::Artist => "artist",
+            SpotifyUriKind::Episode => "episode",
+            SpotifyUriKind::Playlist => "playlist",
+            SpotifyUriKind::Show => "show",
+            SpotifyUriKind::Track => "track",
+            SpotifyUriKind::User => "user",
+        }
+    }
+}
+
+impl FromStr for SpotifyUriKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "album" => Ok(SpotifyUriKind::Album),
+            "artist" => Ok(SpotifyUriKind::Artist),
+            "episode" => Ok(SpotifyUriKind::Episode),
+            "playlist" => Ok(SpotifyUriKind::Playlist),
+            "show" => Ok(SpotifyUriKind::Show),
+            "track" => Ok(SpotifyUriKind::Track),
+            "user" => Ok(SpotifyUriKind::User),
+            _ => Err(format!("Unknown Spotify type: {}", s)),
+        }
+    }
+}
+
+/// A parsed Spotify URI: what kind of thing it names, and its ID.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpotifyUri {
+    kind: SpotifyUriKind,
+    id: String,
+}
+
+impl SpotifyUri {
+    pub fn new(kind: SpotifyUriKind, id: &str) -> Result<SpotifyUri, String> {
+        let valid = match kind {
+            // Old accounts have user names instead of IDs
+            SpotifyUriKind::User => id.chars().all(|c| c.is_alphanumeric() || "._-".contains(c)),
+            _ => id.chars().all(|c| c.is_ascii_alphanumeric()),
+        };
+        if id.is_empty() || !valid {
+            return Err(format!("Invalid Spotify {} ID: {}", kind.name(), id));
+        }
+        Ok(SpotifyUri { kind: kind, id: id.to_string() })
+    }
+    pub fn kind(&self) -> SpotifyUriKind {
+        self.kind
+    }
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// The URI in Spotify's current format, such as "spotify:album:ID".
+    pub fn uri(&self) -> String {
+        format!("spotify:{}:{}", self.kind.name(), self.id)
+    }
+    /// Link to open in a browser.
+    pub fn url(&self) -> String {
+        format!("https://open.spotify.com/{}/{}", self.kind.name(), self.id)
+    }
+    /// Whether Spotify can play from it as a context, like a playlist.
+    pub fn is_context(&self) -> bool {
+        match self.kind {
+            SpotifyUriKind::Album | SpotifyUriKind::Artist |
+            SpotifyUriKind::Playlist | SpotifyUriKind::Show => true,
+            _ => false,
+        }
+    }
+    /// Whether it names a single playable item, like a track.
+    pub fn is_item(&self) -> bool {
+        match self.kind {
+            SpotifyUriKind::Track | SpotifyUriKind::Episode => true,
+            _ => false,
+        }
+    }
+    /// Parse the path parts of a URI or link, such as ["playlist", "ID"].
+    fn from_parts(parts: &[&str]) -> Result<SpotifyUri, String> {
+        match parts {
+            // Old-style playlists include their owner
+            ["user", _, kind, id] => SpotifyUri::new(kind.parse()?, id),
+            [kind, id] => SpotifyUri::new(kind.parse()?, id),
+            _ => Err(format!("Not a Spotify URI: {}", parts.join(":"))),
+        }
+    }
+}
+
+impl FromStr for SpotifyUri {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("spotify:") {
+            let parts: Vec<&str> = s["spotify:".len()..].split(":").collect();
+            return SpotifyUri::from_parts(&parts);
+        }
+        let path = s.trim_start_matches("https://").trim_start_matches("http://");
+        if !path.starts_with("open.spotify.com/") {
+            return Err(format!("Not a Spotify URI or link: {}", s));
+        }
+        // Drop the ?si= tracking ID, and any #fragment
+        let path = path["open.spotify.com/".len()..]
+            .split(|c| c == '?' || c == '#').next().unwrap_or("");
+        let mut parts: Vec<&str> = path.split("/").filter(|p| !p.is_empty()).collect();
+        // Localized links, like open.spotify.com/intl-de/track/ID
+        if parts.len() > 0 && parts[0].starts_with("intl-") {
+            parts.remove(0);
+        }
+        SpotifyUri::from_parts(&parts)
+    }
+}
+
+impl fmt::Display for SpotifyUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.uri())
+    }
+}
@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_parse_uri() {
+        let uri: SpotifyUri = "spotify:playlist:37i9dQZEVXboyJ0IJdpcuT".parse().unwrap();
+        assert_eq!(uri.kind(), SpotifyUriKind::Playlist);
+        assert_eq!(uri.id(), "37i9dQZEVXboyJ0IJdpcuT");
+        assert_eq!(uri.to_string(), "spotify:playlist:37i9dQZEVXboyJ0IJdpcuT");
+        assert_eq!(uri.url(), "https://open.spotify.com/playlist/37i9dQZEVXboyJ0IJdpcuT");
+        assert!(uri.is_context());
+        assert!(!uri.is_item());
+
+        // Old-style playlists drop their owner
+        let old: SpotifyUri = "spotify:user:spotify:playlist:37i9dQZEVXboyJ0IJdpcuT".parse().unwrap();
+        assert_eq!(old, uri);
+
+        let track: SpotifyUri = " spotify:track:6rqhFgbbKwnb9MLmUQDhG6 ".parse().unwrap();
+        assert_eq!(track.kind(), SpotifyUriKind::Track);
+        assert!(track.is_item());
+        let user: SpotifyUri = "spotify:user:alice.smith".parse().unwrap();
+        assert_eq!(user.kind(), SpotifyUriKind::User);
+        assert_eq!(user.id(), "alice.smith");
+
+        assert!("spotify:playlist".parse::<SpotifyUri>().is_err());
+        assert!("spotify:playlist:".parse::<SpotifyUri>().is_err());
+        assert!("spotify:widget:abc".parse::<SpotifyUri>().is_err());
+        assert!("spotify:track:abc:def".parse::<SpotifyUri>().is_err());
+        assert!("spotify:track:not valid".parse::<SpotifyUri>().is_err());
+        assert!("37i9dQZEVXboyJ0IJdpcuT".parse::<SpotifyUri>().is_err());
+        assert!("".parse::<SpotifyUri>().is_err());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let expected: SpotifyUri = "spotify:album:2p2UgYlbg4yG44IKDp08Q8".parse().unwrap();
+        for url in &["https://open.spotify.com/album/2p2UgYlbg4yG44IKDp08Q8",
+                     "https://open.spotify.com/album/2p2UgYlbg4yG44IKDp08Q8?si=a1b2c3d4e5",
+                     "http://open.spotify.com/album/2p2UgYlbg4yG44IKDp08Q8/",
+                     "open.spotify.com/album/2p2UgYlbg4yG44IKDp08Q8#top",
+                     "https://open.spotify.com/intl-de/album/2p2UgYlbg4yG44IKDp08Q8?si=x"] {
+            assert_eq!(url.parse::<SpotifyUri>(), Ok(expected.clone()), "{}", url);
+        }
+        let old: SpotifyUri = "https://open.spotify.com/user/bob/playlist/37i9dQZEVXboyJ0IJdpcuT?si=1"
+            .parse().unwrap();
+        assert_eq!(old.to_string(), "spotify:playlist:37i9dQZEVXboyJ0IJdpcuT");
+
+        assert!("https://example.com/album/2p2UgYlbg4yG44IKDp08Q8".parse::<SpotifyUri>().is_err());
+        assert!("https://open.spotify.com/".parse::<SpotifyUri>().is_err());
+        assert!("https://open.spotify.com/search/foo".parse::<SpotifyUri>().is_err());
+    }
+}
@@ -15,6 +15,8 @@ use std::sync::mpsc::{channel, Receiver};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::str::FromStr;
 
+extern crate serde;
+use self::serde::de::DeserializeOwned;
 extern crate serde_json;
 use self::serde_json::Value;
 
@@ -24,6 +26,9 @@ use super::http;
 use super::settings;
 use super::SpotifyEndpoints;
 use super::SPOTIFY_API;
+use super::uri::SpotifyUri;
+use super::preset::{Preset, Rotation, Surprise, random_roll};
+use super::quicksave::{QuickSave, SaveHistory, SavedTrack};
 use super::http::HttpResponse;
 
 pub type DeviceId = String;
@@ -43,7 +48,72 @@ pub fn parse_spotify_token(json: &str) -> Option<(String, String, u64)> {
     None
 }
 
-#[derive(Deserialize, Debug, Default)]
+/// Why a request to Spotify failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpotifyError {
+    /// No response from Spotify at all, such as when offline.
+    Connection(String),
+    /// Spotify refused the request.  `reason` is Spotify's error code, such
+    /// as "NO_ACTIVE_DEVICE" or "PREMIUM_REQUIRED", when it sends one.
+    Rejected { status: u32, message: String, reason: Option<String> },
+    /// Spotify says the action can't be done right now, so it wasn't sent.
+    Disallowed(PlayerAction),
+    /// No device with this name or ID is available, so nothing was sent.
+    UnknownDevice(String),
+    /// The context, if any, isn't a playlist the user can change.
+    NotYourPlaylist(String),
+    /// Nothing is playing from a context that can be saved as a preset.
+    NothingToSave,
+    /// "Surprise Me" had no preset to choose from, in the group if any.
+    NoPresets(Option<String>),
+}
+
+impl fmt::Display for SpotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SpotifyError::Connection(ref err) => write!(f, "No response from Spotify: {}", err),
+            &SpotifyError::Rejected { status, ref message, .. } => {
+                write!(f, "Spotify refused the request: {} ({})", message, status)
+            },
+            &SpotifyError::Disallowed(action) => write!(f, "Spotify disallows {:?}", action),
+            &SpotifyError::UnknownDevice(ref name) => write!(f, "No device named {}", name),
+            &SpotifyError::NotYourPlaylist(ref context) => write!(f, "Not your playlist: {}", context),
+            &SpotifyError::NothingToSave => write!(f, "Nothing to save as a preset"),
+            &SpotifyError::NoPresets(None) => write!(f, "No presets to choose from"),
+            &SpotifyError::NoPresets(Some(ref group)) => write!(f, "No presets in {} to choose from", group),
+        }
+    }
+}
+
+/// Check the response to a Spotify command, extracting the error Spotify
+/// sends back on failure.
+pub fn check_response(response: &SpotifyResponse) -> Result<(), SpotifyError> {
+    let status = match response.code {
+        Some(code) => code,
+        None => {
+            let err = match response.data {
+                Err(ref e) => e.clone(),
+                Ok(_) => "unknown error".to_string(),
+            };
+            return Err(SpotifyError::Connection(err));
+        },
+    };
+    if status >= 200 && status < 300 {
+        return Ok(());
+    }
+    let json: Value = match response.data {
+        Ok(ref data) => serde_json::from_str(data).unwrap_or(Value::Null),
+        Err(_) => Value::Null,
+    };
+    let error = &json["error"];
+    Err(SpotifyError::Rejected {
+        status: status,
+        message: error["message"].as_str().unwrap_or("unknown error").to_string(),
+        reason: error["reason"].as_str().map(|r| r.to_string()),
+    })
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct ConnectDevice {
     pub id: Option<String>,
     pub is_active: bool,
@@ -64,7 +134,7 @@ impl fmt::Display for ConnectDevice {
     }
 }
 
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, Clone, Default)]
 pub struct ConnectDeviceList {
     pub devices: Vec<ConnectDevice>,
 }
@@ -101,20 +171,20 @@ impl ConnectDeviceList {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct ConnectPlaybackArtist {
     pub name: String,
     pub uri: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct ConnectPlaybackAlbum {
     pub name: String,
     pub uri: String,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ConnectPlaybackItem {
+#[derive(Deserialize, Clone, Debug)]
+pub struct ConnectPlaybackTrack {
     pub duration_ms: u64,
     pub name: String,
     pub uri: String,
@@ -122,12 +192,116 @@ pub struct ConnectPlaybackItem {
     pub artists: Vec<ConnectPlaybackArtist>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
+pub struct ConnectPlaybackShow {
+    pub name: String,
+    pub publisher: String,
+    pub uri: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ConnectPlaybackEpisode {
+    pub duration_ms: u64,
+    pub name: String,
+    pub uri: String,
+    pub show: ConnectPlaybackShow,
+}
+
+/// Whatever is playing: a music track or a podcast episode.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ConnectPlaybackItem {
+    Track(ConnectPlaybackTrack),
+    Episode(ConnectPlaybackEpisode),
+}
+
+impl ConnectPlaybackItem {
+    pub fn name(&self) -> &str {
+        match *self {
+            ConnectPlaybackItem::Track(ref t) => &t.name,
+            ConnectPlaybackItem::Episode(ref e) => &e.name,
+        }
+    }
+    pub fn uri(&self) -> &str {
+        match *self {
+            ConnectPlaybackItem::Track(ref t) => &t.uri,
+            ConnectPlaybackItem::Episode(ref e) => &e.uri,
+        }
+    }
+    pub fn duration_ms(&self) -> u64 {
+        match *self {
+            ConnectPlaybackItem::Track(ref t) => t.duration_ms,
+            ConnectPlaybackItem::Episode(ref e) => e.duration_ms,
+        }
+    }
+    /// First artist of a track, or publisher of an episode's show.
+    pub fn artist(&self) -> Option<&str> {
+        match *self {
+            ConnectPlaybackItem::Track(ref t) => t.artists.get(0).map(|a| a.name.as_str()),
+            ConnectPlaybackItem::Episode(ref e) => Some(&e.show.publisher),
+        }
+    }
+    /// Album of a track, or show of an episode.
+    pub fn collection(&self) -> &str {
+        match *self {
+            ConnectPlaybackItem::Track(ref t) => &t.album.name,
+            ConnectPlaybackItem::Episode(ref e) => &e.show.name,
+        }
+    }
+    pub fn is_episode(&self) -> bool {
+        match *self {
+            ConnectPlaybackItem::Episode(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct ConnectContext {
     pub uri: String,
 }
 
-#[derive(Deserialize, Debug, Default)]
+/// Something a user can do to the player, which Spotify may disallow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerAction {
+    InterruptPlayback,
+    Pause,
+    Resume,
+    Seek,
+    SkipNext,
+    SkipPrev,
+    ToggleRepeatContext,
+    ToggleRepeatTrack,
+    ToggleShuffle,
+    TransferPlayback,
+    /// Not a Spotify action, but refused by restricted devices
+    Volume,
+}
+
+/// Actions Spotify won't allow in the current player state.  Spotify only
+/// lists the disallowed ones.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PlayerDisallows {
+    pub interrupting_playback: bool,
+    pub pausing: bool,
+    pub resuming: bool,
+    pub seeking: bool,
+    pub skipping_next: bool,
+    pub skipping_prev: bool,
+    pub toggling_repeat_context: bool,
+    pub toggling_repeat_track: bool,
+    pub toggling_shuffle: bool,
+    pub transferring_playback: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PlayerActions {
+    pub disallows: PlayerDisallows,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct PlayerState {
     pub timestamp: i64,
     pub device: ConnectDevice,
@@ -137,15 +311,42 @@ pub struct PlayerState {
     pub shuffle_state: bool,
     pub repeat_state: String,
     pub context: Option<ConnectContext>,
+    #[serde(default)]
+    pub actions: PlayerActions,
 }
 
 impl PlayerState {
     pub fn playing_from_context(&self, context: &str) -> bool {
         match self.context {
-            Some(ref ctx) => ctx.uri == context,
+            // Old and new style URIs for the same playlist are equal
+            Some(ref ctx) => match (ctx.uri.parse::<SpotifyUri>(), context.parse::<SpotifyUri>()) {
+                (Ok(playing), Ok(context)) => playing == context,
+                _ => ctx.uri == context,
+            },
             None => false,
         }
     }
+    /// Whether Spotify will accept the action.  A restricted device accepts
+    /// nothing, but playback can still be transferred away from it.
+    pub fn allows(&self, action: PlayerAction) -> bool {
+        let disallows = &self.actions.disallows;
+        if self.device.is_restricted && action != PlayerAction::TransferPlayback {
+            return false;
+        }
+        !match action {
+            PlayerAction::InterruptPlayback => disallows.interrupting_playback,
+            PlayerAction::Pause => disallows.pausing,
+            PlayerAction::Resume => disallows.resuming,
+            PlayerAction::Seek => disallows.seeking,
+            PlayerAction::SkipNext => disallows.skipping_next,
+            PlayerAction::SkipPrev => disallows.skipping_prev,
+            PlayerAction::ToggleRepeatContext => disallows.toggling_repeat_context,
+            PlayerAction::ToggleRepeatTrack => disallows.toggling_repeat_track,
+            PlayerAction::ToggleShuffle => disallows.toggling_shuffle,
+            PlayerAction::TransferPlayback => disallows.transferring_playback,
+            PlayerAction::Volume => false,
+        }
+    }
 }
 
 impl fmt::Display for PlayerState {
@@ -163,11 +364,11 @@ impl fmt::Display for PlayerState {
             None => 0.0,
         };
         if let Some(ref item) = self.item {
-            let duration: f64 = (item.duration_ms as f64) / 1000.0;
+            let duration: f64 = (item.duration_ms() as f64) / 1000.0;
             let progress: f64 = position/duration*100.0;
             write!(f, "{} on {} [Volume {}%]\n{} <{}>\n{}s / {}s ({:.1}%)\n",
                    play_state, self.device.name, volume,
-                   &item.name, &item.uri,
+                   item.name(), item.uri(),
                    position, duration, progress)
         }
         else {
@@ -201,9 +402,14 @@ pub struct PlayContext {
     pub context_uri: Option<String>,
     pub uris: Option<Vec<String>>,
     pub offset: Option<PlayContextOffset>,
+    /// Where to start in the first track, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position_ms: Option<u64>,
 }
 impl Default for PlayContext {
-    fn default() -> PlayContext { PlayContext { context_uri: None, uris: None, offset: None } }
+    fn default() -> PlayContext {
+        PlayContext { context_uri: None, uris: None, offset: None, position_ms: None }
+    }
 }
 impl PlayContext {
     pub fn new() -> PlayContext {
@@ -246,10 +452,15 @@ impl PlayContext {
         };
         self
     }
+    pub fn position_ms<'a>(&'a mut self, position_ms: u64) -> &'a mut PlayContext {
+        self.position_ms = Some(position_ms);
+        self
+    }
     pub fn build(&self) -> PlayContext {
         PlayContext { context_uri: self.context_uri.clone(),
                       uris: self.uris.clone(),
-                      offset: self.offset.clone() }
+                      offset: self.offset.clone(),
+                      position_ms: self.position_ms }
     }
 }
 
@@ -317,7 +528,10 @@ pub struct AlarmEntry {
     pub time: String,
     pub repeat: AlarmRepeat,
     pub context: PlayContext,
-    pub device: DeviceId,
+    /// Play a random preset instead of the context
+    pub surprise: Option<Surprise>,
+    /// Name, alias or ID of the device, looked up when the alarm goes off
+    pub device: String,
     #[cfg(test)]
     pub now: Option<DateTime<Local>>,
 }
@@ -331,6 +545,7 @@ impl<'a> From<&'a AlarmConfig> for AlarmEntry {
                 .context_uri(&alarm.context)
                 .offset_position(0)
                 .build(),
+            surprise: alarm.context.parse().ok(),
             device: alarm.device.clone(),
             #[cfg(test)]
             now: None,
@@ -376,6 +591,7 @@ impl fmt::Display for AlarmConfig {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SpotifyRepeat {
     Off,
     Track,
@@ -391,11 +607,101 @@ impl ToString for SpotifyRepeat {
     }
 }
 
+/// Just the name of an album, artist, playlist or show.
+#[derive(Deserialize)]
+struct ContextName {
+    name: String,
+}
+
+/// A playlist or album saved in the user's library.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryEntry {
+    pub name: String,
+    pub uri: String,
+    /// Who made it: the playlist owner, or the album artist
+    pub by: String,
+    pub is_playlist: bool,
+}
+
+/// One page of a list from the Web API, with a link to the next.
+#[derive(Deserialize)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LibraryOwner {
+    display_name: Option<String>,
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct LibraryPlaylist {
+    name: String,
+    uri: String,
+    owner: LibraryOwner,
+}
+
+#[derive(Deserialize)]
+struct LibraryAlbum {
+    name: String,
+    uri: String,
+    #[serde(default)]
+    artists: Vec<ConnectPlaybackArtist>,
+}
+
+#[derive(Deserialize)]
+struct LibrarySavedAlbum {
+    album: LibraryAlbum,
+}
+
 #[derive(Serialize)]
 struct UriList {
     uris: Vec<String>,
 }
 
+#[derive(Deserialize)]
+struct PlaylistTrackUri {
+    uri: String,
+}
+
+/// An entry of a playlist.  The track is missing if it isn't available.
+#[derive(Deserialize)]
+struct PlaylistTrack {
+    track: Option<PlaylistTrackUri>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistInfo {
+    owner: LibraryOwner,
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    id: String,
+}
+
+/// Playlist version returned when a playlist changes.
+#[derive(Deserialize)]
+struct PlaylistSnapshot {
+    snapshot_id: String,
+}
+
+#[derive(Serialize)]
+struct TrackPositions {
+    uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    positions: Option<Vec<u32>>,
+}
+
+#[derive(Serialize)]
+struct TrackRemoval {
+    tracks: Vec<TrackPositions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot_id: Option<String>,
+}
+
 #[derive(Serialize)]
 struct DeviceIdList {
     device_ids: Vec<String>,
@@ -434,6 +740,8 @@ pub struct SpotifyConnectr<'a> {
     alarms: Vec<AlarmTimer>,
     next_alarm_id: AtomicUsize,
 
+    rotation: Rotation,
+    save_history: SaveHistory,
     scrobbler: Option<Scrobbler>,
 }
 impl<'a> Default for SpotifyConnectr<'a> {
@@ -454,6 +762,8 @@ impl<'a> Default for SpotifyConnectr<'a> {
             settings_timer_channel: Default::default(),
             alarms: Vec::new(),
             next_alarm_id: AtomicUsize::new(0),
+            rotation: Rotation::load(None, 0),
+            save_history: SaveHistory::load(None),
             scrobbler: None,
         }
     }
@@ -464,10 +774,11 @@ pub struct SpotifyConnectrBuilder<'a> {
     access: Option<String>,
     refresh: Option<String>,
     expire: Option<u64>,
+    settings: Option<settings::Settings>,
 }
 impl<'a> SpotifyConnectrBuilder<'a> {
     pub fn build(&mut self) -> Option<SpotifyConnectr<'a>> {
-        let mut settings: settings::Settings = Default::default();
+        let mut settings: settings::Settings = self.settings.take().unwrap_or_default();
         if self.expire.is_none() {
             settings = match settings::read_settings(self.api.scopes_version) {
                 Some(s) => s,
@@ -493,8 +804,11 @@ impl<'a> SpotifyConnectrBuilder<'a> {
             settings_timer_channel: Default::default(),
             alarms: Vec::new(),
             next_alarm_id: AtomicUsize::new(0),
+            rotation: Rotation::load(None, 0),
+            save_history: SaveHistory::load(None),
             scrobbler: None,
         };
+        cnr.load_state();
         let alarms: Vec<AlarmConfig> = cnr.settings.alarms.clone();
         for alarm in &alarms {
             let _ = cnr.schedule_alarm(alarm.into());
@@ -503,18 +817,25 @@ impl<'a> SpotifyConnectrBuilder<'a> {
         let _ = cnr.schedule_settings_refresh();
         Some(cnr)
     }
-    #[cfg(test)]
-    fn with_api(&mut self, api: SpotifyEndpoints<'a>) -> &mut Self {
+    /// Talk to a different server, such as a local mock of the Spotify API.
+    pub fn with_api(&mut self, api: SpotifyEndpoints<'a>) -> &mut Self {
         self.api = api;
         self
     }
-    #[cfg(test)]
-    fn with_oauth_tokens(&mut self, access: &str, refresh: &str, expire: u64) -> &mut Self {
+    /// Use these tokens instead of the ones in the configuration file.  The
+    /// configuration file is not read at all.
+    pub fn with_oauth_tokens(&mut self, access: &str, refresh: &str, expire: u64) -> &mut Self {
         self.access = Some(access.to_string());
         self.refresh = Some(refresh.to_string());
         self.expire = Some(expire);
         self
     }
+    /// Settings to use when the configuration file is not read.  Only takes
+    /// effect together with `with_oauth_tokens()`.
+    pub fn with_settings(&mut self, settings: settings::Settings) -> &mut Self {
+        self.settings = Some(settings);
+        self
+    }
 }
 
 
@@ -525,6 +846,7 @@ impl<'a> SpotifyConnectr<'a> {
             access: None,
             refresh: None,
             expire: None,
+            settings: None,
         }
     }
     fn scrobbler_authenticate(&mut self) {
@@ -552,10 +874,20 @@ impl<'a> SpotifyConnectr<'a> {
                 let _ = self.schedule_alarm(alarm.into());
             }
             self.settings = settings;
+            self.load_state();
         }
         self.scrobbler_authenticate();
     }
-    pub fn quick_save_playlist(&self, context: &str) -> Option<&str> {
+    /// Load the state files named in the settings.
+    fn load_state(&mut self) {
+        self.rotation = Rotation::load(self.settings.rotation_file.as_ref().map(|f| f.as_str()),
+                                       self.settings.surprise_no_repeat);
+        self.save_history = SaveHistory::load(self.settings.quicksave_file.as_ref().map(|f| f.as_str()));
+    }
+    pub fn quick_save_playlists(&self, save: &QuickSave) -> Vec<SpotifyUri> {
+        self.settings.quick_save_playlists(save)
+    }
+    pub fn quick_save_playlist(&self, context: &str) -> Option<&SpotifyUri> {
         self.settings.quick_save_playlist(context)
     }
     fn expire_offset_to_utc(&self, expires_in: u64) -> u64 {
@@ -770,9 +1102,33 @@ impl<'a> SpotifyConnectr<'a> {
                 info!("Alarm started: {} on {}",
                       old_alarm.entry.context.context_uri.as_ref().unwrap(),
                       old_alarm.entry.device);
-                self.set_target_device(Some(old_alarm.entry.device.clone()));
-                self.play(Some(&old_alarm.entry.context));
-                self.set_target_device(None);
+                let surprise_context = match old_alarm.entry.surprise {
+                    Some(ref surprise) => {
+                        match self.surprise_preset(surprise.group.as_ref().map(|g| g.as_str())) {
+                            Some(preset) => Some(preset.play_context()),
+                            None => { warn!("No preset to play for {}", surprise); None },
+                        }
+                    },
+                    None => None,
+                };
+                let context = match old_alarm.entry.surprise {
+                    Some(_) => surprise_context.as_ref(),
+                    None => Some(&old_alarm.entry.context),
+                };
+                // Device IDs can change, so look the device up every time
+                let device = match context {
+                    Some(_) => self.resolve_device(&old_alarm.entry.device),
+                    None => None,
+                };
+                match (context, device) {
+                    (Some(context), Some(device)) => {
+                        self.set_target_device(Some(device));
+                        self.play(Some(context));
+                        self.set_target_device(None);
+                    },
+                    (Some(_), None) => warn!("Alarm skipped: device {} isn't online.", old_alarm.entry.device),
+                    _ => {},
+                }
                 let id = old_alarm.id;
                 self.alarms.push(old_alarm);
                 let _ = self.alarm_reschedule(id);
@@ -899,8 +1255,32 @@ impl<'a> SpotifyConnectr<'a> {
             _ => None
         }
     }
+    /// ID of the online device with this name, alias or ID, see
+    /// `DeviceSettings::find`.  Logs the devices that are online if none
+    /// matches.
+    pub fn resolve_device(&mut self, name: &str) -> Option<DeviceId> {
+        let devices = match self.request_device_list() {
+            Some(devices) => devices,
+            None => {
+                warn!("Can't find device {}: couldn't get the device list.", name);
+                return None;
+            },
+        };
+        if let Some(device) = self.settings.devices.find(&devices, name) {
+            info!("Device {} is {} <{}>", name, device.name, device.id.as_ref().map_or("", |id| id.as_str()));
+            return device.id.clone();
+        }
+        let online: Vec<&str> = devices.devices.iter().map(|d| d.name.as_str()).collect();
+        warn!("No device matches {}.  Online devices: {}", name, match online.is_empty() {
+            true => "none".to_string(),
+            false => online.join(", "),
+        });
+        None
+    }
     pub fn request_player_state(&mut self) -> Option<PlayerState> {
-        let json_response = http::http(self.api.player_state, None, None,
+        // Podcast episodes are reported as "unknown" unless asked for
+        let query = QueryString::new().add("additional_types", "episode").build();
+        let json_response = http::http(self.api.player_state, Some(&query), None,
                                        http::HttpMethod::GET, self.bearer_token());
         match json_response.code {
             Some(200) => match serde_json::from_str(&json_response.data.unwrap()) {
@@ -978,19 +1358,194 @@ impl<'a> SpotifyConnectr<'a> {
         let body = serde_json::to_string(&DeviceIdList {device_ids: vec![device.clone()], play: play}).unwrap();
         http::http(self.api.player, None, Some(&body), http::HttpMethod::PUT, self.bearer_token())
     }
-    pub fn save_track(&mut self, track: String, playlist: String) -> SpotifyResponse {
-        let playlist_id = playlist.split(":").last().unwrap();
-        let user_id = playlist.split(":").nth(2).unwrap();
-        let uri = format!("{}/{}/playlists/{}/tracks",
-                          self.api.add_to_playlist,
-                          user_id, playlist_id);
+    pub fn save_track(&mut self, track: String, playlist: &SpotifyUri) -> SpotifyResponse {
+        let uri = format!("{}/{}/tracks", self.api.add_to_playlist, playlist.id());
 
         let body = serde_json::to_string(&UriList {uris: vec![track.clone()]}).unwrap();
         http::http(&uri, None, Some(&body), http::HttpMethod::POST, self.bearer_token())
     }
-    pub fn get_presets(&mut self) -> &Vec<(String,String)> {
+    /// Remove a track from a playlist: the occurrences at `positions` of the
+    /// playlist version `snapshot_id`, or every occurrence.
+    pub fn remove_track(&mut self, track: &str, playlist: &SpotifyUri, positions: Option<Vec<u32>>,
+                        snapshot_id: Option<String>) -> SpotifyResponse {
+        let removal = TrackRemoval {
+            tracks: vec![TrackPositions { uri: track.to_string(), positions: positions }],
+            snapshot_id: snapshot_id,
+        };
+        let url = format!("{}/{}/tracks", self.api.add_to_playlist, playlist.id());
+        let body = serde_json::to_string(&removal).unwrap();
+        http::http(&url, None, Some(&body), http::HttpMethod::DELETE, self.bearer_token())
+    }
+    /// Spotify ID of the logged in user.
+    pub fn request_user_id(&mut self) -> Result<String, SpotifyError> {
+        let url = format!("{}/me", self.api.metadata);
+        let response = http::http(&url, None, None, http::HttpMethod::GET, self.bearer_token());
+        check_response(&response)?;
+        let data = response.data.unwrap_or_default();
+        match serde_json::from_str::<UserProfile>(&data) {
+            Ok(user) => Ok(user.id),
+            Err(e) => Err(SpotifyError::Connection(format!("Unexpected response: {}", e))),
+        }
+    }
+    /// Spotify ID of the user that owns a playlist.
+    pub fn request_playlist_owner(&mut self, playlist: &SpotifyUri) -> Result<String, SpotifyError> {
+        let url = format!("{}/playlists/{}", self.api.metadata, playlist.id());
+        let response = http::http(&url, None, None, http::HttpMethod::GET, self.bearer_token());
+        check_response(&response)?;
+        let data = response.data.unwrap_or_default();
+        match serde_json::from_str::<PlaylistInfo>(&data) {
+            Ok(info) => Ok(info.owner.id),
+            Err(e) => Err(SpotifyError::Connection(format!("Unexpected response: {}", e))),
+        }
+    }
+    /// URIs of every track and episode in a playlist, in order.
+    pub fn request_playlist_tracks(&mut self, playlist: &SpotifyUri) -> Result<Vec<String>, SpotifyError> {
+        let url = format!("{}/{}/tracks", self.api.add_to_playlist, playlist.id());
+        let tracks: Vec<PlaylistTrack> = self.request_pages(&url)?;
+        Ok(tracks.into_iter().map(|t| t.track.map(|t| t.uri).unwrap_or_default()).collect())
+    }
+    /// Quick-save to every playlist the settings pick for it, except those
+    /// that already have it.  The saves are logged, so they can be undone
+    /// together.  Returns how many playlists it was added to.
+    pub fn quick_save(&mut self, save: &QuickSave) -> Result<usize, SpotifyError> {
+        let now = time::now_utc().to_timespec().sec as i64;
+        let mut saved = Vec::new();
+        let mut result = Ok(());
+        for playlist in self.quick_save_playlists(save) {
+            // Try every playlist, and report the first failure
+            match self.quick_save_to(save, &playlist, now) {
+                Ok(Some(s)) => saved.push(s),
+                Ok(None) => {},
+                Err(e) => if result.is_ok() { result = Err(e); },
+            }
+        }
+        let count = saved.len();
+        self.save_history.record(saved);
+        result.map(|_| count)
+    }
+    fn quick_save_to(&mut self, save: &QuickSave, playlist: &SpotifyUri,
+                     now: i64) -> Result<Option<SavedTrack>, SpotifyError> {
+        let tracks = self.request_playlist_tracks(playlist)?;
+        if tracks.contains(&save.uri) {
+            info!("{} is already in {}", save.uri, playlist);
+            return Ok(None);
+        }
+        info!("Quick-saving {} to {}", save.uri, playlist);
+        let response = self.save_track(save.uri.clone(), playlist);
+        check_response(&response)?;
+        let snapshot = response.data.ok()
+            .and_then(|data| serde_json::from_str::<PlaylistSnapshot>(&data).ok())
+            .map(|s| s.snapshot_id);
+        Ok(Some(SavedTrack {
+            uri: save.uri.clone(),
+            name: save.name.clone(),
+            playlist: playlist.to_string(),
+            snapshot_id: snapshot,
+            // Added at the end
+            position: Some(tracks.len() as u32),
+            time: now,
+            batch: 0,
+        }))
+    }
+    /// Recent quick-saves, most recent first.
+    pub fn quick_saves(&self) -> &Vec<SavedTrack> {
+        self.save_history.saves()
+    }
+    /// Remove the track the last quick-save added from each playlist it
+    /// went to.  Returns what was undone, or None if there was nothing to
+    /// undo.
+    pub fn undo_quick_save(&mut self) -> Result<Option<String>, SpotifyError> {
+        let last = self.save_history.last();
+        for saved in &last {
+            let playlist = match saved.playlist.parse::<SpotifyUri>() {
+                Ok(uri) => uri,
+                Err(e) => { warn!("{}", e); self.save_history.forget(saved); continue; },
+            };
+            info!("Undoing quick-save of {} to {}", saved.uri, playlist);
+            // With the snapshot, only the occurrence that was added is removed
+            let positions = match saved.snapshot_id {
+                Some(_) => saved.position.map(|p| vec![p]),
+                None => None,
+            };
+            let response = self.remove_track(&saved.uri, &playlist, positions, saved.snapshot_id.clone());
+            check_response(&response)?;
+            self.save_history.forget(saved);
+        }
+        Ok(last.first().map(|s| s.name.clone()))
+    }
+    /// Name of a context, such as a playlist or album, from its metadata.
+    pub fn request_context_name(&mut self, uri: &SpotifyUri) -> Result<String, SpotifyError> {
+        let url = format!("{}/{}s/{}", self.api.metadata, uri.kind().name(), uri.id());
+        let response = http::http(&url, None, None, http::HttpMethod::GET, self.bearer_token());
+        check_response(&response)?;
+        let data = response.data.unwrap_or_default();
+        match serde_json::from_str::<ContextName>(&data) {
+            Ok(context) => Ok(context.name),
+            Err(e) => Err(SpotifyError::Connection(format!("Unexpected response: {}", e))),
+        }
+    }
+    /// Every page of a list, starting from `url`.
+    fn request_pages<T: DeserializeOwned>(&mut self, url: &str) -> Result<Vec<T>, SpotifyError> {
+        let mut items = Vec::new();
+        let mut next = Some(format!("{}?limit=50", url));
+        while let Some(url) = next {
+            let response = http::http(&url, None, None, http::HttpMethod::GET, self.bearer_token());
+            check_response(&response)?;
+            let data = response.data.unwrap_or_default();
+            let page: Page<T> = match serde_json::from_str(&data) {
+                Ok(page) => page,
+                Err(e) => return Err(SpotifyError::Connection(format!("Unexpected response: {}", e))),
+            };
+            items.extend(page.items);
+            next = page.next;
+        }
+        Ok(items)
+    }
+    /// The user's playlists, then their saved albums.
+    pub fn request_library(&mut self) -> Result<Vec<LibraryEntry>, SpotifyError> {
+        let playlists: Vec<LibraryPlaylist> = self.request_pages(self.api.playlists)?;
+        let albums: Vec<LibrarySavedAlbum> = self.request_pages(self.api.saved_albums)?;
+        let mut library: Vec<LibraryEntry> = playlists.into_iter().map(|p| {
+            LibraryEntry {
+                name: p.name,
+                uri: p.uri,
+                by: p.owner.display_name.unwrap_or(p.owner.id),
+                is_playlist: true,
+            }
+        }).collect();
+        library.extend(albums.into_iter().map(|a| {
+            LibraryEntry {
+                by: a.album.artists.get(0).map(|a| a.name.clone()).unwrap_or_default(),
+                name: a.album.name,
+                uri: a.album.uri,
+                is_playlist: false,
+            }
+        }));
+        Ok(library)
+    }
+    pub fn get_presets(&mut self) -> &Vec<Preset> {
         &self.settings.presets
     }
+    /// Choose a random preset, from one group or from all of them, and
+    /// remember the choice so it isn't repeated too soon.
+    pub fn surprise_preset(&mut self, group: Option<&str>) -> Option<Preset> {
+        let preset = self.rotation.choose(&self.settings.presets, group, random_roll())?.clone();
+        info!("Surprise preset: {}", preset.key());
+        self.rotation.record(&preset);
+        Some(preset)
+    }
+    /// Add a preset, and save it to the config file.
+    pub fn add_preset(&mut self, preset: Preset) {
+        if let Err(e) = self.settings.add_preset(preset) {
+            warn!("Couldn't save preset: {}", e);
+        }
+    }
+    /// Add presets with optional quick-save playlists, and save them to the
+    /// config file.  Returns how many were new.
+    pub fn import_presets(&mut self, presets: Vec<(Preset, Option<SpotifyUri>)>)
+                          -> Result<usize, settings::SettingsError> {
+        self.settings.import_presets(presets)
+    }
     fn device_can_scrobble(&self, device_type: &str) -> bool {
         if let Some(ref fm) = self.settings.lastfm {
             let dev = device_type.to_lowercase();
@@ -47,7 +47,10 @@ mod tests {
         shuffle: "http://127.0.0.1:9799/v1/me/player/shuffle",
         repeat: "http://127.0.0.1:9799/v1/me/player/repeat",
         player: "http://127.0.0.1:9799/v1/me/player",
-        add_to_playlist: "http://127.0.0.1:9799/v1/users",
+        add_to_playlist: "http://127.0.0.1:9799/v1/playlists",
+        metadata: "http://127.0.0.1:9799/v1",
+        playlists: "http://127.0.0.1:9799/v1/me/playlists",
+        saved_albums: "http://127.0.0.1:9799/v1/me/albums",
     };
 
     pub const BAD_TEST_API: SpotifyEndpoints = SpotifyEndpoints {
@@ -66,7 +69,10 @@ mod tests {
         shuffle: "http://127.0.0.1:11111/v1/me/player/shuffle",
         repeat: "http://127.0.0.1:11111/v1/me/player/repeat",
         player: "http://127.0.0.1:11111/v1/me/player",
-        add_to_playlist: "http://127.0.0.1:11111/v1/users",
+        add_to_playlist: "http://127.0.0.1:11111/v1/playlists",
+        metadata: "http://127.0.0.1:11111/v1",
+        playlists: "http://127.0.0.1:11111/v1/me/playlists",
+        saved_albums: "http://127.0.0.1:11111/v1/me/albums",
     };
 
     /// Macro to parse the body of a POST request and send a response.
@@ -200,11 +206,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_check_response() {
+        let response = |code: Option<u32>, data: Result<&str, &str>| {
+            SpotifyResponse {
+                code: code,
+                data: data.map(|d| d.to_string()).map_err(|e| e.to_string()),
+            }
+        };
+        assert_eq!(check_response(&response(Some(204), Ok(""))), Ok(()));
+        assert_eq!(check_response(&response(None, Err("timed out"))),
+                   Err(SpotifyError::Connection("timed out".to_string())));
+        let body = r#"{"error": {"status": 404, "message": "Player command failed: No active device found",
+                                 "reason": "NO_ACTIVE_DEVICE"}}"#;
+        assert_eq!(check_response(&response(Some(404), Ok(body))),
+                   Err(SpotifyError::Rejected {
+                       status: 404,
+                       message: "Player command failed: No active device found".to_string(),
+                       reason: Some("NO_ACTIVE_DEVICE".to_string()),
+                   }));
+        assert_eq!(check_response(&response(Some(502), Ok("Bad gateway"))),
+                   Err(SpotifyError::Rejected {
+                       status: 502,
+                       message: "unknown error".to_string(),
+                       reason: None,
+                   }));
+    }
+
     fn build_alarm_entry(time: &str, repeat: AlarmRepeat, now: DateTime<Local>) -> AlarmEntry {
         return AlarmEntry {
             time: time.to_string(),
             repeat: repeat,
             context: PlayContext::new().build(),
+            surprise: None,
             device: "12345".to_string(),
             now: Some(now),
         }
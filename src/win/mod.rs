@@ -2,6 +2,7 @@ extern crate systray;
 
 pub use ::TStatusBar;
 pub use ::NSCallback;
+use ::controller::CommandMessage;
 
 use self::systray::api::api::MenuEnableFlag;
 
@@ -16,13 +17,13 @@ use std::cell::Cell;
 pub struct WindowsStatusBar {
     app: systray::Application,
     idx: Cell<u32>,
-    tx: Sender<String>,
+    tx: Sender<CommandMessage>,
     items: BTreeMap<u64, u32>,
 }
 
 impl TStatusBar for WindowsStatusBar {
     type S = WindowsStatusBar;
-    fn new(tx: Sender<String>) -> WindowsStatusBar {
+    fn new(tx: Sender<CommandMessage>) -> WindowsStatusBar {
         let mut bar = WindowsStatusBar {
             app: systray::Application::new().unwrap(),
             idx: Cell::new(0),
@@ -53,8 +54,11 @@ impl TStatusBar for WindowsStatusBar {
         };
         let _ = win.set_tooltip(&tooltip);
     }
-    fn add_submenu(&mut self, _label: &str, _callback: NSCallback) -> *mut Object {
+    fn supports_submenus(&self) -> bool {
         // TODO: implement submenu support on Windows
+        false
+    }
+    fn add_submenu(&mut self, _label: &str, _callback: NSCallback) -> *mut Object {
         0 as *mut Object
     }
     fn add_label(&mut self, label: &str) {